@@ -0,0 +1,111 @@
+use crate::{
+    ray::Ray,
+    util::{Interval, EMPTY_INTERVAL, UNIVERSE_INTERVAL},
+    vec3::Vec3,
+};
+
+// An axis-aligned bounding box, stored as one interval per axis
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+pub const EMPTY_AABB: Aabb = Aabb {
+    x: EMPTY_INTERVAL,
+    y: EMPTY_INTERVAL,
+    z: EMPTY_INTERVAL,
+};
+
+pub const UNIVERSE_AABB: Aabb = Aabb {
+    x: UNIVERSE_INTERVAL,
+    y: UNIVERSE_INTERVAL,
+    z: UNIVERSE_INTERVAL,
+};
+
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Aabb {
+        let mut bbox = Aabb { x, y, z };
+        bbox.pad_to_minimums();
+        bbox
+    }
+
+    // Treat the two points a and b as extrema for the bounding box
+    pub fn from_points(a: Vec3, b: Vec3) -> Aabb {
+        Aabb::new(
+            Interval::new(a.x.min(b.x), a.x.max(b.x)),
+            Interval::new(a.y.min(b.y), a.y.max(b.y)),
+            Interval::new(a.z.min(b.z), a.z.max(b.z)),
+        )
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            x: Interval::enclosing(a.x, b.x),
+            y: Interval::enclosing(a.y, b.y),
+            z: Interval::enclosing(a.z, b.z),
+        }
+    }
+
+    pub fn axis_interval(&self, axis: usize) -> Interval {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        Vec3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        )
+    }
+
+    // Slab test: clip the ray interval against each axis in turn
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.clip(r, ray_t).is_some()
+    }
+
+    // Returns the part of ray_t during which the ray is inside the box, if any
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        let mut ray_t = ray_t;
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let adinv = 1.0 / r.direction[axis];
+
+            let t0 = (ax.min - r.origin[axis]) * adinv;
+            let t1 = (ax.max - r.origin[axis]) * adinv;
+
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if t0 > ray_t.min {
+                ray_t.min = t0;
+            }
+            if t1 < ray_t.max {
+                ray_t.max = t1;
+            }
+
+            if ray_t.max <= ray_t.min {
+                return None;
+            }
+        }
+        Some(ray_t)
+    }
+
+    // Flat primitives (disks, axis-aligned quads, planes) would otherwise produce a zero-width
+    // slab, which the slab test can miss due to floating point error
+    fn pad_to_minimums(&mut self) {
+        let delta = 0.0001;
+        if self.x.size() < delta {
+            self.x = self.x.expand(delta);
+        }
+        if self.y.size() < delta {
+            self.y = self.y.expand(delta);
+        }
+        if self.z.size() < delta {
+            self.z = self.z.expand(delta);
+        }
+    }
+}
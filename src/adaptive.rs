@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use crate::{
+    camera::{progress_bar, Camera},
+    film::Film,
+    integrator::CameraSample,
+    ray::Ray,
+    util::parallel_for,
+    vec3::Color,
+};
+
+// Spends samples where the image is still noisy instead of the same number on every pixel. Every
+// pixel gets min_samples to begin with, then rounds of as many again go to the pixels where the
+// error of the pixel or one of its neighbours is still above the threshold, until none are or they
+// reach max_samples. Flat, evenly lit parts of the image, like the sky, stop early, while edges,
+// glossy reflections and caustics keep going.
+//
+// A pixel's error is the standard error of its mean in each channel, as estimated from the
+// spread of its samples, carried through the square root the image is gamma corrected with. The
+// threshold is then roughly the noise left in the final 8-bit colors, as a fraction of white.
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    max_samples: u32,
+    threshold: f64,
+    // Where the heatmap of samples taken in each pixel is written
+    heatmap: PathBuf,
+}
+
+impl AdaptiveSampling {
+    // Stops sampling pixels once their error is below 0.01
+    pub fn new(
+        min_samples: u32,
+        max_samples: u32,
+        heatmap: impl Into<PathBuf>,
+    ) -> AdaptiveSampling {
+        // The variance needs at least two samples to estimate
+        let min_samples = min_samples.max(2);
+        AdaptiveSampling {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold: 0.01,
+            heatmap: heatmap.into(),
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> AdaptiveSampling {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn heatmap_output(&self) -> &PathBuf {
+        &self.heatmap
+    }
+
+    // Samples the pixels of the film until they converge, tracing each camera ray with sample.
+    // Returns the scale for splats, which were made once for every sample taken anywhere in the
+    // image.
+    pub fn render(
+        &self,
+        camera: &Camera,
+        film: &Film,
+        sample: impl Fn(&Ray) -> CameraSample + Sync,
+    ) -> f64 {
+        let mut active: Vec<(u32, u32)> = (0..film.height())
+            .flat_map(|y| (0..film.width()).map(move |x| (x, y)))
+            .collect();
+        let mut samples = 0;
+
+        let bar = progress_bar(self.max_samples as u64);
+        while !active.is_empty() && samples < self.max_samples {
+            let batch = self.min_samples.min(self.max_samples - samples);
+            parallel_for(active.len() as u32, |i| {
+                let (x, y) = active[i as usize];
+                for _ in 0..batch {
+                    camera.add_sample(film, x, y, &sample);
+                }
+            });
+            samples += batch;
+            bar.set_position(samples as u64);
+            active.retain(|&(x, y)| self.neighbourhood_error(film, x, y) > self.threshold);
+        }
+        bar.finish_with_message("Done!");
+
+        let total: u64 = (0..film.height())
+            .flat_map(|y| (0..film.width()).map(move |x| film.samples(x, y)))
+            .sum();
+        if total == 0 {
+            return 0.0;
+        }
+        (film.width() as u64 * film.height() as u64) as f64 / total as f64
+    }
+
+    // The largest error of the pixel's mean over its channels, after gamma correction
+    pub fn error(&self, film: &Film, x: u32, y: u32) -> f64 {
+        let mean = film.pixel(x, y, 0.0);
+        let variance = film.variance(x, y);
+        // The square root's slope is 1 / (2 sqrt(mean)), kept finite for black pixels
+        let channel = |mean: f64, variance: f64| variance.sqrt() / (2.0 * mean.max(1e-4).sqrt());
+        channel(mean.r, variance.r)
+            .max(channel(mean.g, variance.g))
+            .max(channel(mean.b, variance.b))
+    }
+
+    // The largest error among the pixel and its neighbours in a 3x3 box. A pixel whose few
+    // samples happened to agree, next to noisy ones, is likely noisy too.
+    fn neighbourhood_error(&self, film: &Film, x: u32, y: u32) -> f64 {
+        let mut error: f64 = 0.0;
+        for ny in y.saturating_sub(1)..(y + 2).min(film.height()) {
+            for nx in x.saturating_sub(1)..(x + 2).min(film.width()) {
+                error = error.max(self.error(film, nx, ny));
+            }
+        }
+        error
+    }
+
+    // The number of samples taken in each pixel as a color, in rows from the top, going from
+    // black for none through red and yellow to white for max_samples
+    pub fn heatmap(&self, film: &Film) -> Vec<Color> {
+        (0..film.height())
+            .flat_map(|y| (0..film.width()).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let t = 3.0 * film.samples(x, y) as f64 / self.max_samples as f64;
+                Color::new(
+                    t.clamp(0.0, 1.0),
+                    (t - 1.0).clamp(0.0, 1.0),
+                    (t - 2.0).clamp(0.0, 1.0),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{integrator::Lighting, sampler::random};
+
+    fn render(adaptive: &AdaptiveSampling, color: impl Fn() -> Color + Sync) -> Film {
+        let camera = Camera::new(1.0, 4, 1, 1);
+        let film = Film::new(4, 4);
+        adaptive.render(&camera, &film, |_| CameraSample {
+            lighting: Lighting {
+                emission: color(),
+                ..Lighting::new()
+            },
+            hit: None,
+        });
+        film
+    }
+
+    #[test]
+    fn constant_scene_stops_at_min_samples() {
+        let adaptive = AdaptiveSampling::new(4, 64, "heatmap.ppm");
+        let film = render(&adaptive, || Color::new(0.5, 0.25, 1.0));
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(film.samples(x, y), 4);
+            }
+        }
+    }
+
+    #[test]
+    fn max_samples_caps_noisy_scene() {
+        // Uniform noise this strong would need thousands of samples to get under the threshold
+        let adaptive = AdaptiveSampling::new(4, 30, "heatmap.ppm");
+        let film = render(&adaptive, || Color::new(random(), random(), random()));
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(film.samples(x, y), 30);
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    film::Film,
+    hit::HitRecord,
+    integrator::{CameraSample, Lighting},
+    ray::Ray,
+    vec3::{Color, Vec3},
+};
+
+// An arbitrary output variable: a pass rendered alongside the image for compositing. The surface
+// passes describe whatever the camera ray hits first, and are black where it hits nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    // The surface's color in RGB, as given by Material::albedo
+    Albedo,
+    // The shading normal in world space, after normal and bump maps
+    Normal,
+    // Distance from the camera, in every channel
+    Depth,
+    // The point hit in world space
+    Position,
+    // Which of the scene's objects and which material the pixel's first sample hits, numbered
+    // from 1 in the order they're first seen across the image, in every channel
+    ObjectId,
+    MaterialId,
+    // The image's light split as in Lighting: scattered once on its way to the camera, scattered
+    // more than once, and seen without scattering at all
+    Direct,
+    Indirect,
+    Emission,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 9] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+    ];
+
+    // The name given on the command line, and to the pass's file or EXR layer
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    fn is_lighting(&self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect | Aov::Emission)
+    }
+
+    fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+// Writes the selected AOVs after the image. They're recorded into an AovFilm carried by the
+// image's film, from the same samples as the image and by the same integrator, so the lighting
+// passes add up to it.
+//
+// An output path ending in .exr writes a single multi-layer EXR with the image as its R, G and B
+// channels and each pass as a layer named after it. Any other path is used as a prefix for a PFM
+// file per pass, so "render" gives render_albedo.pfm and so on.
+pub struct Aovs {
+    selected: Vec<Aov>,
+    output: PathBuf,
+}
+
+impl Aovs {
+    pub fn new(selected: &[Aov], output: impl Into<PathBuf>) -> Aovs {
+        let mut unique = Vec::new();
+        for aov in selected {
+            if !unique.contains(aov) {
+                unique.push(*aov);
+            }
+        }
+        Aovs {
+            selected: unique,
+            output: output.into(),
+        }
+    }
+
+    pub fn selected(&self) -> &[Aov] {
+        &self.selected
+    }
+
+    // Writes the selected passes, along with the image itself when writing an EXR. Passes that
+    // were recorded but not selected, like the denoiser's features, are left out.
+    pub fn write(&self, images: &AovImages, image: &[Color]) -> io::Result<()> {
+        let selected: Vec<(Aov, &[Color])> = self
+            .selected
+            .iter()
+            .filter_map(|aov| images.get(*aov).map(|pixels| (*aov, pixels)))
+            .collect();
+
+        let is_exr = self
+            .output
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            let mut layers = vec![("", image)];
+            layers.extend(selected.iter().map(|(aov, pixels)| (aov.name(), *pixels)));
+            return write_exr(&self.output, images.width, images.height, &layers);
+        }
+
+        let prefix = self
+            .output
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for (aov, pixels) in selected {
+            let path = self
+                .output
+                .with_file_name(format!("{prefix}_{}.pfm", aov.name()));
+            write_pfm(&path, images.width, images.height, pixels)?;
+        }
+        Ok(())
+    }
+}
+
+// The passes being recorded for an image, added to from any number of threads at once. The
+// surface passes average what each sample's camera ray hit first. The lighting passes average
+// each sample's light and take the image's splats as well. The ID passes keep what the first
+// sample of each pixel hit, so their values are never blended at edges.
+pub struct AovFilm {
+    width: u32,
+    height: u32,
+    passes: Vec<(Aov, Film)>,
+    // The object and material numbers hit by the first sample of each pixel, if there are ID
+    // passes. Materials are told apart by where they live, as they can't be compared.
+    ids: Vec<OnceLock<Option<(usize, usize)>>>,
+}
+
+impl AovFilm {
+    pub fn new(width: u32, height: u32, aovs: &[Aov]) -> AovFilm {
+        let mut passes: Vec<(Aov, Film)> = Vec::new();
+        for aov in aovs.iter().filter(|aov| !aov.is_id()) {
+            if passes.iter().all(|(recorded, _)| recorded != aov) {
+                passes.push((*aov, Film::new(width, height)));
+            }
+        }
+        let ids = if aovs.iter().any(Aov::is_id) {
+            (0..width as usize * height as usize)
+                .map(|_| OnceLock::new())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        AovFilm {
+            width,
+            height,
+            passes,
+            ids,
+        }
+    }
+
+    // Records a sample of the pixel, whose camera ray is ray, with its light already in RGB
+    pub fn add_sample(&self, x: u32, y: u32, ray: &Ray, sample: &CameraSample) {
+        for (aov, film) in &self.passes {
+            if let Some(value) = lighting_part(*aov, &sample.lighting) {
+                film.add_sample(x, y, value);
+            }
+        }
+        self.add_surface(x, y, ray, sample.hit.as_ref());
+    }
+
+    // Records what a camera ray through the pixel hit first, for the surface and ID passes only,
+    // for integrators that make their image from splats
+    pub fn add_surface(&self, x: u32, y: u32, ray: &Ray, hit: Option<&HitRecord>) {
+        for (aov, film) in &self.passes {
+            if !aov.is_lighting() {
+                film.add_sample(x, y, surface(*aov, ray, hit));
+            }
+        }
+        if !self.ids.is_empty() {
+            let id = hit.map(|rec| {
+                (
+                    rec.object + 1,
+                    Arc::as_ptr(&rec.material) as *const () as usize,
+                )
+            });
+            let _ = self.ids[y as usize * self.width as usize + x as usize].set(id);
+        }
+    }
+
+    pub fn add_splat(&self, x: u32, y: u32, lighting: &Lighting) {
+        for (aov, film) in &self.passes {
+            if let Some(value) = lighting_part(*aov, lighting) {
+                film.add_splat(x, y, value);
+            }
+        }
+    }
+
+    // The passes, with splats scaled by the image's splat_scale. Materials are numbered from 1 in
+    // the order they're first seen across the image.
+    pub fn images(&self, splat_scale: f64) -> AovImages {
+        let mut images: Vec<(Aov, Vec<Color>)> = self
+            .passes
+            .iter()
+            .map(|(aov, film)| (*aov, film.pixels(splat_scale)))
+            .collect();
+
+        if !self.ids.is_empty() {
+            let gray = |id: usize| Color::new(id as f64, id as f64, id as f64);
+            let mut numbers = HashMap::new();
+            let mut objects = Vec::with_capacity(self.ids.len());
+            let mut materials = Vec::with_capacity(self.ids.len());
+            for id in &self.ids {
+                let (object, material) = match id.get().copied().flatten() {
+                    Some((object, key)) => {
+                        let next = numbers.len() + 1;
+                        (object, *numbers.entry(key).or_insert(next))
+                    }
+                    None => (0, 0),
+                };
+                objects.push(gray(object));
+                materials.push(gray(material));
+            }
+            images.push((Aov::ObjectId, objects));
+            images.push((Aov::MaterialId, materials));
+        }
+
+        AovImages {
+            width: self.width,
+            height: self.height,
+            images,
+        }
+    }
+}
+
+// The recorded passes, each with its pixels in rows from the top
+pub struct AovImages {
+    width: u32,
+    height: u32,
+    images: Vec<(Aov, Vec<Color>)>,
+}
+
+impl AovImages {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&[Color]> {
+        self.images
+            .iter()
+            .find(|(recorded, _)| *recorded == aov)
+            .map(|(_, pixels)| pixels.as_slice())
+    }
+}
+
+// The part of the light that a lighting pass records, or None for other passes
+fn lighting_part(aov: Aov, lighting: &Lighting) -> Option<Color> {
+    match aov {
+        Aov::Direct => Some(lighting.direct),
+        Aov::Indirect => Some(lighting.indirect),
+        Aov::Emission => Some(lighting.emission),
+        _ => None,
+    }
+}
+
+// One sample of a surface pass, for a camera ray that hit rec
+fn surface(aov: Aov, ray: &Ray, rec: Option<&HitRecord>) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let vector = |v: Vec3| Color::new(v.x, v.y, v.z);
+    let Some(rec) = rec else {
+        return black;
+    };
+    match aov {
+        Aov::Albedo => rec.material.albedo(ray, rec),
+        Aov::Normal => vector(rec.material.shading_normal(ray, rec)),
+        Aov::Depth => {
+            let distance = rec.t * ray.direction.length();
+            Color::new(distance, distance, distance)
+        }
+        Aov::Position => vector(rec.p),
+        _ => black,
+    }
+}
+
+// Writes an image as a color PFM: three little-endian floats per pixel, bottom row first
+pub fn write_pfm(path: &Path, width: u32, height: u32, pixels: &[Color]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "PF\n{width} {height}\n-1.0\n")?;
+    for row in pixels.chunks(width as usize).rev() {
+        for pixel in row {
+            for channel in [pixel.r, pixel.g, pixel.b] {
+                out.write_all(&(channel as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+// Writes named RGB layers, each with its pixels in rows from the top, as a single-part scanline
+// OpenEXR file of uncompressed 32-bit floats. A layer named "" gives the plain R, G and B channels
+// that viewers show, and the others give channels like "albedo.R".
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    layers: &[(&str, &[Color])],
+) -> io::Result<()> {
+    // Channels are stored in order of their names, each row of one after another
+    let mut channels: Vec<(String, &[Color], usize)> = Vec::new();
+    for (layer, pixels) in layers {
+        for (index, channel) in ["R", "G", "B"].into_iter().enumerate() {
+            let name = if layer.is_empty() {
+                channel.to_string()
+            } else {
+                format!("{layer}.{channel}")
+            };
+            channels.push((name, pixels, index));
+        }
+    }
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630u32.to_le_bytes());
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut list = Vec::new();
+    for (name, _, _) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // 32-bit float samples, not perceptually linear, one per pixel in both directions
+        for value in [2i32, 0, 1, 1] {
+            list.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    list.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    let attributes: [(&str, &str, Vec<u8>); 8] = [
+        ("channels", "chlist", list),
+        ("compression", "compression", vec![0]),
+        ("dataWindow", "box2i", window.clone()),
+        ("displayWindow", "box2i", window),
+        ("lineOrder", "lineOrder", vec![0]),
+        ("pixelAspectRatio", "float", 1.0f32.to_le_bytes().to_vec()),
+        ("screenWindowCenter", "v2f", [0u8; 8].to_vec()),
+        ("screenWindowWidth", "float", 1.0f32.to_le_bytes().to_vec()),
+    ];
+    for (name, kind, value) in attributes {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(&value);
+    }
+    header.push(0);
+
+    // Each row is a block of its own, found through a table of where each block starts
+    let row_size = width as usize * channels.len() * 4;
+    let block_size = (8 + row_size) as u64;
+    let first_block = (header.len() + 8 * height as usize) as u64;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+    for y in 0..height as u64 {
+        out.write_all(&(first_block + y * block_size).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(row_size as i32).to_le_bytes())?;
+        for (_, pixels, index) in &channels {
+            for pixel in &pixels[y * width as usize..(y + 1) * width as usize] {
+                let value = [pixel.r, pixel.g, pixel.b][*index];
+                out.write_all(&(value as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, spectrum::SampledWavelengths};
+
+    #[test]
+    fn albedo_is_the_surface_color_in_rgb() {
+        let color = Color::new(0.8, 0.4, 0.2);
+        let rec = HitRecord {
+            material: Arc::new(Lambertian::new(color)),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            ..HitRecord::new()
+        };
+        let mut ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        // The same in spectral mode, rather than a radiance at the sampled wavelengths
+        for wavelengths in [None, Some(SampledWavelengths::sample(0.3))] {
+            ray.wavelengths = wavelengths;
+            let albedo = surface(Aov::Albedo, &ray, Some(&rec));
+            assert_eq!((albedo.r, albedo.g, albedo.b), (color.r, color.g, color.b));
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use crate::{
+    camera::Camera,
+    film::Film,
+    hit::{HitRecord, Hittable},
+    integrator::{scatter, CameraSample, Integrator, Lighting},
+    ray::Ray,
+    sampler::random,
+    scene::Scene,
+    util::Interval,
+    vec3::{Color, Vec3},
+};
+
+type Point3 = Vec3;
+
+// Bidirectional path tracing (Veach 1997). Each sample traces one path from the camera and one
+// from a light, then joins every prefix of one to every prefix of the other with a shadow ray.
+// Each way of building a path is weighted against all the others that could have built it with
+// the balance heuristic, so every kind of light transport is found by whichever is best at it.
+//
+// Joining light paths straight to the camera lands on other pixels, so that light is splatted
+// onto the film. This finds caustics, like light focused through a glass sphere onto a diffuse
+// floor, that paths from the camera only find by chance.
+//
+// The environment, and emissive surfaces that weren't added as lights, are only found by camera
+// paths that reach them.
+pub struct Bdpt {
+    max_depth: u32,
+}
+
+impl Bdpt {
+    // max_depth caps the number of segments in a path, as it caps the rays in one from the path
+    // tracer
+    pub fn new(max_depth: u32) -> Bdpt {
+        Bdpt { max_depth }
+    }
+}
+
+impl Integrator for Bdpt {
+    fn sample(&self, ray: &Ray, scene: &Scene, camera: &Camera, film: &Film) -> CameraSample {
+        let max_depth = self.max_depth as usize;
+        // A path with s light and t camera vertices scattered at all but the two at its ends
+        let mut lighting = Lighting::new();
+
+        // Camera paths get one more vertex than light paths, for the camera itself
+        let mut camera_path = vec![Vertex::camera(camera.center())];
+        let pdf = camera.direction_pdf(ray.direction.unit_vector());
+        let escaped = random_walk(
+            scene,
+            *ray,
+            Color::new(1.0, 1.0, 1.0),
+            pdf,
+            max_depth + 1,
+            &mut camera_path,
+        );
+        if let Some((escaped, beta)) = escaped {
+            let direction = escaped.direction.unit_vector();
+            lighting.add(
+                camera_path.len() as u32 - 1,
+                beta * escaped.radiance(scene.environment.radiance(direction)),
+            );
+        }
+        let light_path = light_subpath(scene, ray, max_depth);
+
+        let paths = Paths {
+            scene,
+            camera,
+            light_path: &light_path,
+            camera_path: &camera_path,
+        };
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                // Skip joining the camera to nothing or straight to a light, and paths that are
+                // too long
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 1 > max_depth {
+                    continue;
+                }
+
+                let (contribution, pixel) = paths.connect(s, t);
+                let bounces = (s + t - 2) as u32;
+                if t > 1 {
+                    lighting.add(bounces, contribution);
+                } else if let Some((x, y)) = pixel {
+                    let contribution = match ray.wavelengths {
+                        Some(wavelengths) => wavelengths.to_rgb(contribution),
+                        None => contribution,
+                    };
+                    let mut splat = Lighting::new();
+                    splat.add(bounces, contribution);
+                    film.add_lighting_splat(x, y, &splat);
+                }
+            }
+        }
+
+        let hit = match camera_path.get(1).map(|vertex| &vertex.kind) {
+            Some(VertexKind::Surface { rec, .. }) => Some(HitRecord::clone(rec)),
+            _ => None,
+        };
+        CameraSample { lighting, hit }
+    }
+}
+
+// A point on a camera or light path
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3,
+    // Geometric normal of surfaces and area lights, or zero
+    n: Vec3,
+    // The path's throughput up to this vertex: the light it carries over the density of sampling
+    // it
+    beta: Color,
+    // Whether the material scattered in a direction with no density, like a mirror, so the path
+    // can't be joined here
+    delta: bool,
+    // Densities of sampling this vertex from the one before it on its own path, and from the one
+    // after it if the path were traced the other way. Both are over area, except for vertices on
+    // lights infinitely far away, which use solid angle.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+#[derive(Clone)]
+enum VertexKind {
+    Camera,
+    // On the scene light with this index
+    Light(usize),
+    // Where `ray` hit a surface
+    Surface { rec: Box<HitRecord>, ray: Ray },
+}
+
+impl Vertex {
+    fn camera(p: Point3) -> Vertex {
+        Vertex {
+            kind: VertexKind::Camera,
+            p,
+            n: Vec3::new(0.0, 0.0, 0.0),
+            beta: Color::new(1.0, 1.0, 1.0),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn light(light: usize, p: Point3, n: Vec3, beta: Color, pdf_fwd: f64) -> Vertex {
+        Vertex {
+            kind: VertexKind::Light(light),
+            p,
+            n,
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        !self.n.near_zero()
+    }
+
+    // The scene light this vertex lies on, including surfaces hit on area lights
+    fn light_index(&self) -> Option<usize> {
+        match &self.kind {
+            VertexKind::Camera => None,
+            VertexKind::Light(light) => Some(*light),
+            VertexKind::Surface { rec, .. } => rec.light,
+        }
+    }
+
+    fn is_infinite_light(&self, scene: &Scene) -> bool {
+        match self.kind {
+            VertexKind::Light(light) => scene.lights[light].bounds().is_none(),
+            _ => false,
+        }
+    }
+
+    // BSDF times the cosine at a surface vertex, for light leaving toward the point q
+    fn f(&self, q: Point3) -> Color {
+        let VertexKind::Surface { rec, ray } = &self.kind else {
+            return Color::new(0.0, 0.0, 0.0);
+        };
+        let direction = (q - self.p).unit_vector();
+        ray.reflectance(&*rec.material, rec.material.eval(ray, rec, direction))
+    }
+
+    // Turns a density over solid angle at this vertex into one over area at next
+    fn convert_density(&self, pdf: f64, next: &Vertex, scene: &Scene) -> f64 {
+        if next.is_infinite_light(scene) {
+            return pdf;
+        }
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.is_on_surface() {
+            pdf *= next.n.dot(w / distance_squared.sqrt()).abs();
+        }
+        pdf
+    }
+
+    // Density over area of sampling next from this vertex, having arrived from prev
+    fn pdf(&self, scene: &Scene, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match &self.kind {
+            VertexKind::Light(light) => return light_pdf(scene, *light, self.p, next),
+            VertexKind::Camera => camera.direction_pdf((next.p - self.p).unit_vector()),
+            VertexKind::Surface { rec, ray } => {
+                let Some(prev) = prev else {
+                    return 0.0;
+                };
+                // The material sees the path arrive from prev, on whichever side of it that is
+                let incoming = Ray {
+                    origin: prev.p,
+                    direction: self.p - prev.p,
+                    wavelengths: ray.wavelengths,
+                };
+                let rec = facing(rec, prev.p - self.p);
+                rec.material
+                    .pdf(&incoming, &rec, (next.p - self.p).unit_vector())
+            }
+        };
+        self.convert_density(pdf, next, scene)
+    }
+}
+
+// The hit record as seen from the side of the surface that `toward` points to
+fn facing(rec: &HitRecord, toward: Vec3) -> HitRecord {
+    let mut rec = rec.clone();
+    if rec.normal.dot(toward) < 0.0 {
+        rec.normal = -rec.normal;
+        rec.front_face = !rec.front_face;
+    }
+    rec
+}
+
+// Density over area at next of light leaving the point p on a light toward it
+fn light_pdf(scene: &Scene, light: usize, p: Point3, next: &Vertex) -> f64 {
+    let bounds = scene.world.bounding_box();
+    let light = &scene.lights[light];
+    let w = next.p - p;
+    let distance_squared = w.length_squared();
+    if distance_squared == 0.0 {
+        return 0.0;
+    }
+    let direction = w / distance_squared.sqrt();
+
+    // Light infinitely far away arrives in parallel from the disk it's sampled on
+    let (pdf_position, pdf_direction) = light.emission_pdf(p, direction, &bounds);
+    let mut pdf = if light.bounds().is_none() {
+        pdf_position
+    } else {
+        pdf_direction / distance_squared
+    };
+    if next.is_on_surface() {
+        pdf *= next.n.dot(direction).abs();
+    }
+    pdf
+}
+
+// Density of picking the point p on a light to start a path toward next. For lights infinitely far
+// away this is the density over solid angle of the direction they shine in.
+fn light_origin_pdf(scene: &Scene, light: usize, p: Point3, next: &Vertex) -> f64 {
+    let bounds = scene.world.bounding_box();
+    let pmf = scene
+        .emission_sampler()
+        .pmf(p, Vec3::new(0.0, 0.0, 0.0), light);
+    let light = &scene.lights[light];
+    let direction = (next.p - p).unit_vector();
+    let (pdf_position, pdf_direction) = light.emission_pdf(p, direction, &bounds);
+    if light.bounds().is_none() {
+        pmf * pdf_direction
+    } else {
+        pmf * pdf_position
+    }
+}
+
+// Whether a path leaving next could hit the light at vertex, as it can area lights in the
+// scene's geometry. Paths from the camera can only end on such lights.
+fn hittable(scene: &Scene, vertex: &Vertex, next: &Vertex) -> bool {
+    let Some(light) = vertex.light_index() else {
+        return false;
+    };
+    scene.lights[light].pdf(next.p, (vertex.p - next.p).unit_vector()) > 0.0
+}
+
+// Extends a path from its last vertex along ray until it has max_vertices vertices, recording each
+// hit. beta is the path's throughput so far and pdf the density over solid angle of the ray's
+// direction. Returns the ray that leaves the scene, if one does, with the throughput it carries.
+fn random_walk(
+    scene: &Scene,
+    mut ray: Ray,
+    mut beta: Color,
+    mut pdf: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+) -> Option<(Ray, Color)> {
+    while path.len() < max_vertices {
+        let mut rec = HitRecord::new();
+        if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return Some((ray, beta));
+        }
+
+        let mut vertex = Vertex {
+            kind: VertexKind::Surface {
+                rec: Box::new(rec.clone()),
+                ray,
+            },
+            p: rec.p,
+            n: rec.normal,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        let prev = path
+            .last()
+            .expect("paths start with a camera or light vertex");
+        vertex.pdf_fwd = prev.convert_density(pdf, &vertex, scene);
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            break;
+        }
+
+        let Some((scattered, attenuation)) = scatter(&ray, &rec) else {
+            break;
+        };
+        let wi = scattered.direction.unit_vector();
+        let wo = -ray.direction.unit_vector();
+
+        // Materials without a density for the direction they picked are treated as delta, and
+        // never joined to the other path
+        pdf = rec.material.pdf(&ray, &rec, wi);
+        let delta = pdf <= 0.0;
+        let pdf_rev = if delta {
+            0.0
+        } else {
+            let back = Ray {
+                origin: rec.p + wi,
+                direction: -wi,
+                wavelengths: ray.wavelengths,
+            };
+            rec.material.pdf(&back, &facing(&rec, wi), wo)
+        };
+
+        let n = path.len();
+        path[n - 1].delta = delta;
+        path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2], scene);
+
+        beta = beta * attenuation;
+        if beta.average() <= 0.0 {
+            break;
+        }
+        ray = scattered;
+    }
+    None
+}
+
+// Traces a path of up to max_vertices vertices from a light picked by its power, at the wavelengths
+// of the camera ray
+fn light_subpath(scene: &Scene, camera_ray: &Ray, max_vertices: usize) -> Vec<Vertex> {
+    let mut path = Vec::new();
+    if max_vertices == 0 {
+        return path;
+    }
+
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+    let Some((index, pmf)) = scene.emission_sampler().sample(zero, zero, random()) else {
+        return path;
+    };
+    let light = &scene.lights[index];
+    let Some(emission) = light
+        .sample_emission(&scene.world.bounding_box())
+        .filter(|emission| emission.pdf_position > 0.0 && emission.pdf_direction > 0.0)
+    else {
+        return path;
+    };
+
+    let mut ray = emission.ray;
+    ray.wavelengths = camera_ray.wavelengths;
+    let radiance = ray.radiance(emission.radiance);
+    let cos_theta = if emission.normal.near_zero() {
+        1.0
+    } else {
+        emission.normal.dot(ray.direction).abs()
+    };
+    let beta = radiance * (cos_theta / (pmf * emission.pdf_position * emission.pdf_direction));
+
+    path.push(Vertex::light(
+        index,
+        ray.origin,
+        emission.normal,
+        radiance,
+        pmf * emission.pdf_position,
+    ));
+    random_walk(
+        scene,
+        ray,
+        beta,
+        emission.pdf_direction,
+        max_vertices,
+        &mut path,
+    );
+
+    // Light infinitely far away is picked by direction, and arrives in parallel from the disk
+    if light.bounds().is_none() {
+        path[0].pdf_fwd = pmf * emission.pdf_direction;
+        if let Some(first) = path.get_mut(1) {
+            first.pdf_fwd = emission.pdf_position;
+            if first.is_on_surface() {
+                first.pdf_fwd *= first.n.dot(ray.direction).abs();
+            }
+        }
+    }
+    path
+}
+
+// The two paths traced for a sample, and everything needed to join them
+struct Paths<'a> {
+    scene: &'a Scene,
+    camera: &'a Camera,
+    light_path: &'a [Vertex],
+    camera_path: &'a [Vertex],
+}
+
+impl Paths<'_> {
+    // Joins the first s vertices of the light path to the first t of the camera path, returning
+    // the light this carries to the camera, weighted against the other ways of building the same
+    // path. Joins straight to the camera also return the pixel they land in.
+    fn connect(&self, s: usize, t: usize) -> (Color, Option<(u32, u32)>) {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let scene = self.scene;
+        let pt = &self.camera_path[t - 1];
+        let mut sampled = None;
+        let mut pixel = None;
+
+        let radiance = if s == 0 {
+            // The camera path hit something emissive
+            let VertexKind::Surface { rec, ray } = &pt.kind else {
+                return (black, None);
+            };
+            let emitted = pt.beta * ray.radiance(rec.material.emitted(ray, rec));
+            if rec.light.is_none() {
+                // Nothing else can find surfaces that aren't lights
+                return (emitted, None);
+            }
+            emitted
+        } else if t == 1 {
+            // A vertex on the light path seen directly by the camera. The camera's importance
+            // times the cosine at the camera is its density for a pinhole.
+            let qs = &self.light_path[s - 1];
+            let center = self.camera.center();
+            let distance = (center - qs.p).length();
+            let direction = (center - qs.p) / distance;
+            let Some(raster) = self.camera.raster(-direction) else {
+                return (black, None);
+            };
+            let pdf = self.camera.direction_pdf(-direction);
+            let radiance = qs.beta * qs.f(center) * (pdf / (distance * distance));
+            if radiance.average() <= 0.0 || scene.occluded(qs.p, direction, distance) {
+                return (black, None);
+            }
+            pixel = Some(raster);
+            sampled = Some(Vertex::camera(center));
+            radiance
+        } else if s == 1 {
+            // A light sampled from the last vertex of the camera path, as direct lighting does
+            let VertexKind::Surface { ray, .. } = &pt.kind else {
+                return (black, None);
+            };
+            let Some((index, pmf)) = scene.emission_sampler().sample(pt.p, pt.n, random()) else {
+                return (black, None);
+            };
+            let Some(sample) = scene.lights[index]
+                .sample(pt.p)
+                .filter(|sample| sample.pdf > 0.0)
+            else {
+                return (black, None);
+            };
+
+            // Lights infinitely far away only need a point in the right direction
+            let q = if sample.distance.is_finite() {
+                pt.p + sample.direction * sample.distance
+            } else {
+                pt.p + sample.direction
+            };
+            let light = ray.radiance(sample.radiance) / (sample.pdf * pmf);
+            let radiance = pt.beta * pt.f(q) * light;
+            if radiance.average() <= 0.0 || scene.occluded(pt.p, sample.direction, sample.distance)
+            {
+                return (black, None);
+            }
+            let mut vertex = Vertex::light(index, q, sample.normal, light, 0.0);
+            vertex.pdf_fwd = light_origin_pdf(scene, index, q, pt);
+            sampled = Some(vertex);
+            radiance
+        } else {
+            let qs = &self.light_path[s - 1];
+            let distance = (pt.p - qs.p).length();
+            let direction = (pt.p - qs.p) / distance;
+            let radiance =
+                qs.beta * qs.f(pt.p) * pt.f(qs.p) * pt.beta * (1.0 / (distance * distance));
+            if radiance.average() <= 0.0 || scene.occluded(qs.p, direction, distance) {
+                return (black, None);
+            }
+            radiance
+        };
+
+        if radiance.average() <= 0.0 {
+            return (black, None);
+        }
+        (radiance * self.mis_weight(s, t, sampled.as_ref()), pixel)
+    }
+
+    // Balance heuristic weight of building the path by joining s light vertices to t camera
+    // vertices, found from the ratios of each other strategy's density to this one's. sampled
+    // replaces the light vertex when s is 1, or the camera vertex when t is 1.
+    fn mis_weight(&self, s: usize, t: usize, sampled: Option<&Vertex>) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let (scene, camera) = (self.scene, self.camera);
+        let light_vertex = |i: usize| match sampled {
+            Some(vertex) if s == 1 && i == 0 => vertex,
+            _ => &self.light_path[i],
+        };
+        let camera_vertex = |i: usize| match sampled {
+            Some(vertex) if t == 1 && i == 0 => vertex,
+            _ => &self.camera_path[i],
+        };
+
+        let qs = (s > 0).then(|| light_vertex(s - 1));
+        let pt = camera_vertex(t - 1);
+        let qs_minus = (s > 1).then(|| light_vertex(s - 2));
+        let pt_minus = (t > 1).then(|| camera_vertex(t - 2));
+
+        // The reverse densities of the vertices either side of the join depend on the strategy
+        let pt_rev = match (qs, pt_minus, pt.light_index()) {
+            (Some(qs), _, _) => qs.pdf(scene, camera, qs_minus, pt),
+            (None, Some(pt_minus), Some(light)) => light_origin_pdf(scene, light, pt.p, pt_minus),
+            _ => 0.0,
+        };
+        let pt_minus_rev = pt_minus.map(|pt_minus| match (qs, pt.light_index()) {
+            (Some(qs), _) => pt.pdf(scene, camera, Some(qs), pt_minus),
+            (None, Some(light)) => light_pdf(scene, light, pt.p, pt_minus),
+            (None, None) => 0.0,
+        });
+        let qs_rev = qs.map(|qs| pt.pdf(scene, camera, pt_minus, qs));
+        let qs_minus_rev = qs
+            .zip(qs_minus)
+            .map(|(qs, qs_minus)| qs.pdf(scene, camera, Some(pt), qs_minus));
+
+        let camera_rev = |i: usize| match t - i {
+            1 => pt_rev,
+            2 => pt_minus_rev.unwrap_or(0.0),
+            _ => camera_vertex(i).pdf_rev,
+        };
+        let light_rev = |i: usize| match s - i {
+            1 => qs_rev.unwrap_or(0.0),
+            2 => qs_minus_rev.unwrap_or(0.0),
+            _ => light_vertex(i).pdf_rev,
+        };
+        // The vertices at the join are being joined, so they can't be delta
+        let camera_delta = |i: usize| i + 1 != t && camera_vertex(i).delta;
+        let light_delta = |i: usize| i + 1 != s && light_vertex(i).delta;
+        // Densities that don't exist, as at delta vertices, cancel out
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+
+        // Move the join toward the camera one vertex at a time, then toward the light, skipping
+        // strategies that would join at a delta vertex or rely on hitting a light that can't be
+        // hit
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera_rev(i)) / remap(camera_vertex(i).pdf_fwd);
+            if !camera_delta(i) && !camera_delta(i - 1) {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light_rev(i)) / remap(light_vertex(i).pdf_fwd);
+            let before = if i > 0 {
+                light_delta(i - 1)
+            } else {
+                let next = if s > 1 { light_vertex(1) } else { pt };
+                !hittable(scene, light_vertex(0), next)
+            };
+            if !light_delta(i) && !before {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
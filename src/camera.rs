@@ -1,10 +1,22 @@
-use std::{io::stdout, time::Duration};
+use std::{
+    fs::File,
+    io::{stdout, BufWriter},
+    time::Duration,
+};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
 use crate::{
-    hit::Hittables,
+    adaptive::AdaptiveSampling,
+    aov::{Aov, AovFilm, Aovs},
+    denoise::Denoiser,
+    film::{write_ppm, Film},
+    integrator::{CameraSample, DepthLimits, Integrator, PathTracer},
     ray::Ray,
+    sampler::random,
+    scene::Scene,
+    spectrum::SampledWavelengths,
+    util::parallel_for,
     vec3::{Color, Vec3},
 };
 
@@ -17,8 +29,19 @@ pub struct Camera {
     pixel_delta_v: Vec3,
     pixel00_loc: Vec3,
     pub samples_per_pixel: u32,
-    pixel_sample_scale: f64,
-    pub max_depth: u32,
+    // Takes between a minimum and maximum number of samples in each pixel, depending on how noisy
+    // it is, instead of samples_per_pixel everywhere. Integrators that don't sample pixel by
+    // pixel, like MLT, ignore it.
+    pub adaptive: Option<AdaptiveSampling>,
+    // The light transport algorithm that estimates the color of each sample
+    pub integrator: Box<dyn Integrator>,
+    // Trace sampled wavelengths instead of RGB, for dispersion and other wavelength-dependent
+    // effects
+    pub spectral: bool,
+    // Extra passes for compositing, recorded from the image's samples and written after it
+    pub aovs: Option<Aovs>,
+    // Writes a denoised copy of the image as well, guided by features recorded from its samples
+    pub denoiser: Option<Denoiser>,
 }
 
 impl Camera {
@@ -28,11 +51,6 @@ impl Camera {
         samples_per_pixel: u32,
         max_depth: u32,
     ) -> Camera {
-        let aspect_ratio = aspect_ratio;
-        let image_width = image_width;
-        let samples_per_pixel = samples_per_pixel;
-        let max_depth = max_depth;
-
         // Calculate the image height, and ensure that it's at least 1.
         let mut image_height: u32 = (image_width as f64 / aspect_ratio) as u32;
         image_height = image_height.clamp(1, image_height);
@@ -42,8 +60,6 @@ impl Camera {
         let viewport_width = viewport_height * ((image_width as f64) / (image_height as f64));
         let camera_center = Vec3::new(0.0, 0.0, 0.0);
 
-        let pixel_sample_scale = 1.0 / samples_per_pixel as f64;
-
         // Calculate the vectors across the horizontal and down the vertical viewport edges.
         let viewport_u = Vec3::new(viewport_width, 0.0, 0.0);
         let viewport_v = Vec3::new(0.0, viewport_height, 0.0);
@@ -65,40 +81,144 @@ impl Camera {
             pixel_delta_v,
             pixel00_loc,
             samples_per_pixel,
-            pixel_sample_scale,
-            max_depth,
+            adaptive: None,
+            integrator: Box::new(PathTracer::new(DepthLimits::new(max_depth))),
+            spectral: false,
+            aovs: None,
+            denoiser: None,
         }
     }
 
-    pub fn render(&self, world: &Hittables) {
-        let bar = ProgressBar::new(self.image_height as u64);
-        bar.set_style(
-            ProgressStyle::with_template(
-                "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-            )
-            .unwrap(),
-        );
-        bar.enable_steady_tick(Duration::from_millis(100));
+    pub fn render(&self, scene: &Scene) {
+        // The passes asked for, and the features the denoiser is guided by
+        let mut recorded: Vec<Aov> = self
+            .aovs
+            .as_ref()
+            .map_or(Vec::new(), |aovs| aovs.selected().to_vec());
+        if self.denoiser.is_some() {
+            recorded.extend(Denoiser::FEATURES);
+        }
+        let mut film = Film::new(self.image_width, self.image_height);
+        if !recorded.is_empty() {
+            film = film.with_aovs(AovFilm::new(self.image_width, self.image_height, &recorded));
+        }
+        let splat_scale = self.integrator.render(scene, self, &film);
+        film.write_ppm(&mut stdout(), splat_scale);
 
-        println!("P3\n{} {}\n255", self.image_width, self.image_height);
+        if let Some(adaptive) = &self.adaptive {
+            let file =
+                File::create(adaptive.heatmap_output()).expect("Error writing sample heatmap");
+            write_ppm(
+                &mut BufWriter::new(file),
+                self.image_width,
+                self.image_height,
+                &adaptive.heatmap(&film),
+            );
+        }
 
-        for j in (0..self.image_height).rev() {
-            bar.inc(1);
-            for i in 0..self.image_width {
-                let color: &mut Color = &mut Color::new(0.0, 0.0, 0.0);
-                for _ in 0..(self.samples_per_pixel as u32) {
-                    let r = self.get_ray(i, j);
-                    *color += r.color(world, self.max_depth);
-                }
-                *color = *color * self.pixel_sample_scale;
-                color.write_color(&mut stdout());
+        let images = film.aovs().map(|aovs| aovs.images(splat_scale));
+        if let (Some(aovs), Some(images)) = (&self.aovs, &images) {
+            aovs.write(images, &film.pixels(splat_scale))
+                .expect("Error writing AOVs");
+        }
+
+        if let (Some(denoiser), Some(features)) = (&self.denoiser, &images) {
+            let denoised = denoiser.denoise(&film, splat_scale, features);
+            let file = File::create(denoiser.output()).expect("Error writing denoised image");
+            write_ppm(
+                &mut BufWriter::new(file),
+                self.image_width,
+                self.image_height,
+                &denoised,
+            );
+        }
+    }
+
+    pub fn image_height(&self) -> u32 {
+        self.image_height
+    }
+
+    // Samples every pixel of the film, either samples_per_pixel times or adaptively, returning the
+    // scale for splats made once for each sample
+    pub fn render_samples(&self, film: &Film, sample: impl Fn(&Ray) -> CameraSample + Sync) -> f64 {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.render(self, film, sample),
+            None => {
+                self.render_pixels(film, self.samples_per_pixel, sample);
+                1.0 / self.samples_per_pixel as f64
             }
         }
+    }
+
+    // Takes `samples` samples of every pixel of the film, tracing each camera ray with sample,
+    // with the rows shared out between threads
+    pub fn render_pixels(
+        &self,
+        film: &Film,
+        samples: u32,
+        sample: impl Fn(&Ray) -> CameraSample + Sync,
+    ) {
+        let bar = progress_bar(self.image_height as u64);
+        parallel_for(self.image_height, |y| {
+            for x in 0..self.image_width {
+                for _ in 0..samples {
+                    self.add_sample(film, x, y, &sample);
+                }
+            }
+            bar.inc(1);
+        });
         bar.finish_with_message("Done!");
     }
 
+    // Takes one sample of the pixel x across and y down from the top and adds it to the film,
+    // and to the film's AOV passes if it has any
+    pub fn add_sample(&self, film: &Film, x: u32, y: u32, sample: impl Fn(&Ray) -> CameraSample) {
+        let (r, sample) = self.sample_pixel(x, y, sample);
+        film.add_sample(x, y, sample.lighting.total());
+        if let Some(aovs) = film.aovs() {
+            aovs.add_sample(x, y, &r, &sample);
+        }
+    }
+
+    // One sample of the pixel x across and y down from the top, tracing a random ray through it
+    // with sample, returned along with the ray. In spectral mode the ray carries sampled
+    // wavelengths, and the light found is turned back into RGB.
+    pub fn sample_pixel(
+        &self,
+        x: u32,
+        y: u32,
+        sample: impl Fn(&Ray) -> CameraSample,
+    ) -> (Ray, CameraSample) {
+        let r = self.pixel_ray(x, y);
+        let mut found = sample(&r);
+        found.lighting = found.lighting.map(|color| self.to_rgb(&r, color));
+        (r, found)
+    }
+
+    // A random ray through the pixel x across and y down from the top, carrying sampled
+    // wavelengths in spectral mode
+    pub fn pixel_ray(&self, x: u32, y: u32) -> Ray {
+        let mut r = self.get_ray(x, self.image_height - 1 - y);
+        if self.spectral {
+            r.wavelengths = Some(SampledWavelengths::sample(random()));
+        }
+        r
+    }
+
+    // Turns light found along a ray from pixel_ray back into RGB
+    pub fn to_rgb(&self, r: &Ray, color: Color) -> Color {
+        match r.wavelengths {
+            Some(wavelengths) => wavelengths.to_rgb(color),
+            None => color,
+        }
+    }
+
     pub fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = self.sample_square();
+        self.ray_through(i, j, self.sample_square())
+    }
+
+    // The ray through pixel (i, j), counting rows up from the bottom, at offset from its center
+    pub fn ray_through(&self, i: u32, j: u32, offset: Vec3) -> Ray {
         let pixel_sample = self.pixel00_loc
             + (i as f64 + offset.x) * self.pixel_delta_u
             + (j as f64 + offset.y) * self.pixel_delta_v;
@@ -109,11 +229,70 @@ impl Camera {
         Ray::new(ray_origin, ray_direction)
     }
 
+    // A random offset within the unit square centered on a pixel
     pub fn sample_square(&self) -> Vec3 {
-        Vec3::new(
-            rand::random::<f64>() * self.pixel_sample_scale as f64,
-            rand::random::<f64>() * self.pixel_sample_scale as f64,
-            0.0,
-        )
+        Vec3::new(random() - 0.5, random() - 0.5, 0.0)
+    }
+
+    // Where rays from the camera start, for integrators that connect paths to it
+    pub fn center(&self) -> Vec3 {
+        self.camera_center
+    }
+
+    // The pixel that a ray leaving the camera along direction passes through, counting rows down
+    // from the top, or None if it misses the image
+    pub fn raster(&self, direction: Vec3) -> Option<(u32, u32)> {
+        let forward = self.forward();
+        let cos_theta = direction.dot(forward);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+
+        // Where the ray crosses the viewport, measured in pixels from its lower left corner
+        let distance = (self.pixel00_loc - self.camera_center).dot(forward) / cos_theta;
+        let corner = self.pixel00_loc - 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+        let offset = self.camera_center + direction * distance - corner;
+        let u = offset.dot(self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let v = offset.dot(self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+        if u < 0.0 || v < 0.0 || u >= self.image_width as f64 || v >= self.image_height as f64 {
+            return None;
+        }
+        Some((u as u32, self.image_height - 1 - v as u32))
+    }
+
+    // Density over solid angle with which camera rays leave along the unit vector direction, taken
+    // over the whole image. For a pinhole this is also the importance it gives off in that
+    // direction times the cosine to the view direction, normalized so that camera rays carry a
+    // weight of one: 1 / (A cos^3) for an image of area A at distance one.
+    pub fn direction_pdf(&self, direction: Vec3) -> f64 {
+        if self.raster(direction).is_none() {
+            return 0.0;
+        }
+        let forward = self.forward();
+        let focal_length = (self.pixel00_loc - self.camera_center).dot(forward);
+        let area = self.pixel_delta_u.length()
+            * self.image_width as f64
+            * self.pixel_delta_v.length()
+            * self.image_height as f64
+            / (focal_length * focal_length);
+        let cos_theta = direction.dot(forward);
+        1.0 / (area * cos_theta * cos_theta * cos_theta)
+    }
+
+    fn forward(&self) -> Vec3 {
+        self.pixel_delta_v.cross(self.pixel_delta_u).unit_vector()
     }
 }
+
+// A progress bar counting up to len, for the stages of a render
+pub fn progress_bar(len: u64) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap(),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    csg::{pair_crossings, Solid, Span},
+    disk::disk_bounding_box,
+    hit::{HitRecord, Hittable},
+    material::Material,
+    onb::Onb,
+    poly::solve_quadratic,
+    ray::Ray,
+    util::{Interval, PI},
+    vec3::Vec3,
+};
+
+// A cone with a circular base of the given radius, tapering to a point at `apex`
+pub struct Cone {
+    pub base: Vec3,
+    pub apex: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+    height: f64,
+    frame: Onb,
+}
+
+impl Cone {
+    pub fn new(
+        base: Vec3,
+        apex: Vec3,
+        radius: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Cone {
+        Cone {
+            base,
+            apex,
+            radius: radius.max(0.0),
+            capped,
+            material,
+            height: (apex - base).length(),
+            frame: Onb::new(apex - base),
+        }
+    }
+
+    // Every point where the line through r crosses the surface, sorted by t, with outward normals
+    fn crossings(&self, r: &Ray, capped: bool) -> Vec<HitRecord> {
+        // A cone with no radius has no surface, and its normals and UVs would divide by zero
+        if self.radius <= 0.0 {
+            return Vec::new();
+        }
+
+        // Work in the cone's frame, where the axis is +z, the base is at the origin and the apex
+        // is at z = height
+        let o = self.frame.to_local(r.origin - self.base);
+        let d = self.frame.to_local(r.direction);
+
+        // Local (t, normal, u, v) for each crossing
+        let mut hits: Vec<(f64, Vec3, f64, f64)> = Vec::with_capacity(3);
+
+        // Side: x^2 + y^2 = k^2 (height - z)^2, where k is the slope of the cone
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let w = self.height - o.z;
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.y * d.y + k2 * w * d.z);
+        let c = o.x * o.x + o.y * o.y - k2 * w * w;
+        // solve_quadratic degrades to the linear case for rays parallel to the cone's surface
+        for t in solve_quadratic(a, b, c) {
+            let p = o + t * d;
+            if (0.0..=self.height).contains(&p.z) {
+                // Gradient of the implicit surface; it vanishes at the apex, where we fall back
+                // to the axis
+                let gradient = Vec3::new(p.x, p.y, k2 * (self.height - p.z));
+                let normal = if gradient.near_zero() {
+                    Vec3::new(0.0, 0.0, 1.0)
+                } else {
+                    gradient.unit_vector()
+                };
+                let u = (p.y.atan2(p.x) + PI) / (2.0 * PI);
+                hits.push((t, normal, u, p.z / self.height));
+            }
+        }
+
+        if capped && d.z.abs() > 1e-12 {
+            let t = -o.z / d.z;
+            let p = o + t * d;
+            let dist_squared = p.x * p.x + p.y * p.y;
+            if dist_squared <= self.radius * self.radius {
+                let u = (p.y.atan2(p.x) + PI) / (2.0 * PI);
+                let v = dist_squared.sqrt() / self.radius;
+                hits.push((t, Vec3::new(0.0, 0.0, -1.0), u, v));
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits.into_iter()
+            .map(|(t, local_normal, u, v)| HitRecord {
+                p: r.at(t),
+                normal: self.frame.local(local_normal),
+                material: Arc::clone(&self.material),
+                t,
+                u,
+                v,
+                ..HitRecord::new()
+            })
+            .collect()
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        for crossing in self.crossings(r, self.capped) {
+            if ray_t.surrounds(crossing.t) {
+                *rec = crossing;
+                rec.set_face_normal(r, rec.normal);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::surrounding(
+            &disk_bounding_box(self.base, self.frame.w, self.radius),
+            &Aabb::from_points(self.apex, self.apex),
+        )
+    }
+}
+
+// An uncapped cone is not closed, so as a solid it is always treated as capped
+impl Solid for Cone {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        pair_crossings(self.crossings(r, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    // A capped cone with a base of radius 1 on the origin and its apex at (0, 0, 1), so its side
+    // slopes at 45 degrees
+    fn cone() -> Cone {
+        Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            true,
+            grey(),
+        )
+    }
+
+    #[test]
+    fn ray_tangent_to_side() {
+        // The side touches the line x = -0.5, z = 0.5 along y at (-0.5, 0, 0.5)
+        let side_normal = Vec3::new(-1.0, 0.0, 1.0).unit_vector();
+        let inside = Ray::new(Vec3::new(-0.5 + 1e-6, -5.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        let rec = hit(&cone(), &inside).expect("ray just inside the tangent should hit");
+        assert!((rec.t - 5.0).abs() < 1e-2);
+        assert_near(rec.normal, side_normal);
+
+        let outside = Ray::new(Vec3::new(-0.5 - 1e-6, -5.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        assert!(hit(&cone(), &outside).is_none());
+    }
+
+    #[test]
+    fn ray_parallel_to_side() {
+        // Parallel to the line from the apex to (1, 0, 0), which leaves a single linear root
+        let r = Ray::new(Vec3::new(-2.0, 0.0, 2.0), Vec3::new(1.0, 0.0, -1.0));
+        let rec = hit(&cone(), &r).expect("ray parallel to the side should hit it once");
+        assert!((rec.t - 1.5).abs() < 1e-9);
+        assert_near(rec.p, Vec3::new(-0.5, 0.0, 0.5));
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 1.0).unit_vector());
+    }
+
+    #[test]
+    fn ray_grazing_base_rim() {
+        // Just above the base the ray skims the cap's plane and enters through the side
+        let above = Ray::new(Vec3::new(-5.0, 0.0, 1e-9), Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&cone(), &above).expect("ray just above the rim should hit the side");
+        assert!((rec.t - 4.0).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 1.0).unit_vector());
+
+        let below = Ray::new(Vec3::new(-5.0, 0.0, -1e-9), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&cone(), &below).is_none());
+
+        // Straight up just inside the rim hits the cap from below
+        let up = Ray::new(Vec3::new(1.0 - 1e-6, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = hit(&cone(), &up).expect("ray just inside the rim should hit the cap");
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn zero_radius_is_never_hit() {
+        let thin = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+            true,
+            grey(),
+        );
+        // Straight down the axis, where the cap's UVs used to divide zero by zero
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit(&thin, &r).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    ray::Ray,
+    util::Interval,
+};
+
+// An interval along a ray during which the ray is inside a solid. Unlike the records returned by
+// Hittable::hit, both records store the outward facing normal of the surface they cross, since
+// which side the ray arrives from is only known once the spans have been combined.
+#[derive(Clone)]
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+// A closed Hittable that can enumerate every interval along a ray where the ray is inside it.
+// Spans cover the whole line through the ray, including negative t, so that rays starting inside
+// a solid are classified correctly. They are sorted by t and do not overlap.
+pub trait Solid: Hittable {
+    fn spans(&self, r: &Ray) -> Vec<Span>;
+
+    // Bounds of the enclosed volume, which only differs from the surface's bounding box for
+    // unbounded solids such as half-spaces
+    fn solid_bounding_box(&self) -> Aabb {
+        self.bounding_box()
+    }
+}
+
+// Pairs up boundary crossings (sorted by t, with outward normals) into entry/exit spans. A
+// dangling crossing left over by a numerically tangent ray is dropped.
+pub fn pair_crossings(crossings: Vec<HitRecord>) -> Vec<Span> {
+    let mut spans = Vec::with_capacity(crossings.len() / 2);
+    let mut iter = crossings.into_iter();
+    while let (Some(enter), Some(exit)) = (iter.next(), iter.next()) {
+        spans.push(Span { enter, exit });
+    }
+    spans
+}
+
+// Reports the first span boundary inside ray_t as a regular hit
+pub fn first_span_hit(spans: &[Span], r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+    for boundary in spans.iter().flat_map(|span| [&span.enter, &span.exit]) {
+        if ray_t.surrounds(boundary.t) {
+            *rec = boundary.clone();
+            rec.set_face_normal(r, boundary.normal);
+            return true;
+        }
+    }
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// A boolean combination of two solids. Each surface keeps the material of the solid it came
+// from, so a hole drilled with a Difference shows the material of the drill.
+pub struct Csg {
+    pub left: Box<dyn Solid>,
+    pub right: Box<dyn Solid>,
+    pub op: CsgOp,
+    bbox: Aabb,
+}
+
+impl Csg {
+    pub fn new(left: Box<dyn Solid>, right: Box<dyn Solid>, op: CsgOp) -> Csg {
+        let left_box = left.solid_bounding_box();
+        let right_box = right.solid_bounding_box();
+        let bbox = match op {
+            CsgOp::Union => Aabb::surrounding(&left_box, &right_box),
+            CsgOp::Intersection => Aabb {
+                x: overlap(left_box.x, right_box.x),
+                y: overlap(left_box.y, right_box.y),
+                z: overlap(left_box.z, right_box.z),
+            },
+            CsgOp::Difference => left_box,
+        };
+        Csg {
+            left,
+            right,
+            op,
+            bbox,
+        }
+    }
+
+    pub fn union(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(left, right, CsgOp::Union)
+    }
+
+    pub fn intersection(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(left, right, CsgOp::Intersection)
+    }
+
+    pub fn difference(left: Box<dyn Solid>, right: Box<dyn Solid>) -> Csg {
+        Csg::new(left, right, CsgOp::Difference)
+    }
+}
+
+fn overlap(a: Interval, b: Interval) -> Interval {
+    Interval::new(a.min.max(b.min), a.max.min(b.max))
+}
+
+impl Solid for Csg {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        if !self
+            .bbox
+            .hit(r, Interval::new(f64::NEG_INFINITY, f64::INFINITY))
+        {
+            return Vec::new();
+        }
+
+        let left = self.left.spans(r);
+        let right = self.right.spans(r);
+        if left.is_empty() && (right.is_empty() || self.op != CsgOp::Union) {
+            return Vec::new();
+        }
+
+        // Sweep over every boundary of both children in order, tracking whether we are inside
+        // each one. Since each child's spans are disjoint, every boundary toggles that state.
+        let mut events: Vec<(HitRecord, bool)> = Vec::with_capacity(2 * (left.len() + right.len()));
+        for (spans, from_left) in [(left, true), (right, false)] {
+            for span in spans {
+                events.push((span.enter, from_left));
+                events.push((span.exit, from_left));
+            }
+        }
+        events.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+        let mut result = Vec::new();
+        let (mut in_left, mut in_right, mut inside) = (false, false, false);
+        let mut enter: Option<HitRecord> = None;
+        for (mut boundary, from_left) in events {
+            if from_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+
+            // Surfaces carved out by the right operand face into it
+            if !from_left && self.op == CsgOp::Difference {
+                boundary.normal = -boundary.normal;
+            }
+
+            let now_inside = self.op.contains(in_left, in_right);
+            if now_inside && !inside {
+                enter = Some(boundary);
+            } else if !now_inside && inside {
+                if let Some(enter) = enter.take() {
+                    result.push(Span {
+                        enter,
+                        exit: boundary,
+                    });
+                }
+            }
+            inside = now_inside;
+        }
+        result
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+        first_span_hit(&self.spans(r), r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sphere::Sphere,
+        test_util::{assert_near, grey, hit},
+        vec3::Vec3,
+    };
+
+    fn sphere(x: f64) -> Box<dyn Solid> {
+        Box::new(Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, grey()))
+    }
+
+    // Unit spheres centered at x = -0.5 and x = 0.5, overlapping between x = -0.5 and x = 0.5
+    fn overlapping(op: CsgOp) -> Csg {
+        Csg::new(sphere(-0.5), sphere(0.5), op)
+    }
+
+    // Along +x from (from, 0, 0), so t = x - from
+    fn along_x(from: f64) -> Ray {
+        Ray::new(Vec3::new(from, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    fn span_ts(spans: &[Span]) -> Vec<(f64, f64)> {
+        spans.iter().map(|s| (s.enter.t, s.exit.t)).collect()
+    }
+
+    fn assert_spans(spans: &[Span], expected: &[(f64, f64)]) {
+        let ts = span_ts(spans);
+        assert_eq!(ts.len(), expected.len(), "{:?} != {:?}", ts, expected);
+        for (&(enter, exit), &(want_enter, want_exit)) in ts.iter().zip(expected) {
+            assert!(
+                (enter - want_enter).abs() < 1e-9 && (exit - want_exit).abs() < 1e-9,
+                "{:?} != {:?}",
+                ts,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn pair_crossings_drops_dangling_crossing() {
+        let crossing = |t| HitRecord {
+            t,
+            ..HitRecord::new()
+        };
+        let spans = pair_crossings(vec![crossing(1.0), crossing(2.0), crossing(3.0)]);
+        assert_spans(&spans, &[(1.0, 2.0)]);
+    }
+
+    #[test]
+    fn first_span_hit_skips_boundaries_outside_ray_t() {
+        let spans = sphere(0.0).spans(&along_x(-5.0));
+        assert_spans(&spans, &[(4.0, 6.0)]);
+
+        let r = along_x(-5.0);
+        let mut rec = HitRecord::new();
+        assert!(first_span_hit(
+            &spans,
+            &r,
+            Interval::new(4.5, 10.0),
+            &mut rec
+        ));
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert!(!first_span_hit(
+            &spans,
+            &r,
+            Interval::new(6.5, 10.0),
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn union_of_overlapping_spheres() {
+        let csg = overlapping(CsgOp::Union);
+        assert_spans(&csg.spans(&along_x(-5.0)), &[(3.5, 6.5)]);
+    }
+
+    #[test]
+    fn intersection_of_overlapping_spheres() {
+        let csg = overlapping(CsgOp::Intersection);
+        let spans = csg.spans(&along_x(-5.0));
+        assert_spans(&spans, &[(4.5, 5.5)]);
+        // Entered through the right sphere's surface and left through the left one's
+        assert_near(spans[0].enter.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert_near(spans[0].exit.normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn difference_of_overlapping_spheres() {
+        let csg = overlapping(CsgOp::Difference);
+        let spans = csg.spans(&along_x(-5.0));
+        assert_spans(&spans, &[(3.5, 4.5)]);
+        // The carved surface faces into the right sphere
+        assert_near(spans[0].exit.normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ray_starting_inside_solid() {
+        // Starting inside the left sphere only, at x = -1
+        let r = along_x(-1.0);
+
+        let rec = hit(&overlapping(CsgOp::Union), &r).expect("should leave the union");
+        assert!((rec.t - 2.5).abs() < 1e-9);
+        assert!(!rec.front_face);
+
+        let rec = hit(&overlapping(CsgOp::Difference), &r).expect("should leave the difference");
+        assert!((rec.t - 0.5).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        let rec =
+            hit(&overlapping(CsgOp::Intersection), &r).expect("should enter the intersection");
+        assert!((rec.t - 0.5).abs() < 1e-9);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn spans_that_only_touch() {
+        // Unit spheres at x = -1 and x = 1 meet at the origin, where t = 5
+        let union = Csg::union(sphere(-1.0), sphere(1.0)).spans(&along_x(-5.0));
+        let ts = span_ts(&union);
+        assert!((ts[0].0 - 3.0).abs() < 1e-9 && (ts[ts.len() - 1].1 - 7.0).abs() < 1e-9);
+        for pair in ts.windows(2) {
+            assert!((pair[0].1 - pair[1].0).abs() < 1e-9, "gap in {:?}", ts);
+        }
+
+        let intersection = Csg::intersection(sphere(-1.0), sphere(1.0));
+        assert!(intersection.spans(&along_x(-5.0)).is_empty());
+
+        let difference = Csg::difference(sphere(-1.0), sphere(1.0));
+        assert_spans(&difference.spans(&along_x(-5.0)), &[(3.0, 5.0)]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    csg::{pair_crossings, Solid, Span},
+    disk::disk_bounding_box,
+    hit::{HitRecord, Hittable},
+    material::Material,
+    onb::Onb,
+    poly::solve_quadratic,
+    ray::Ray,
+    util::{Interval, PI},
+    vec3::Vec3,
+};
+
+// A cylinder of the given radius running from `base` to `top`, optionally closed with end caps
+pub struct Cylinder {
+    pub base: Vec3,
+    pub top: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+    height: f64,
+    frame: Onb,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Vec3,
+        top: Vec3,
+        radius: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Cylinder {
+        Cylinder {
+            base,
+            top,
+            radius: radius.max(0.0),
+            capped,
+            material,
+            height: (top - base).length(),
+            frame: Onb::new(top - base),
+        }
+    }
+
+    // Every point where the line through r crosses the surface, sorted by t, with outward normals
+    fn crossings(&self, r: &Ray, capped: bool) -> Vec<HitRecord> {
+        // A cylinder with no radius has no surface, and its normals and UVs would divide by zero
+        if self.radius <= 0.0 {
+            return Vec::new();
+        }
+
+        // Work in the cylinder's frame, where the axis is +z and the base is at the origin
+        let o = self.frame.to_local(r.origin - self.base);
+        let d = self.frame.to_local(r.direction);
+
+        // Local (t, normal, u, v) for each crossing
+        let mut hits: Vec<(f64, Vec3, f64, f64)> = Vec::with_capacity(4);
+
+        // Side: x^2 + y^2 = radius^2 for 0 <= z <= height. A ray parallel to the axis never
+        // crosses the side.
+        let a = d.x * d.x + d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.y * d.y);
+        let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+        if a > 1e-12 {
+            for t in solve_quadratic(a, b, c) {
+                let p = o + t * d;
+                if (0.0..=self.height).contains(&p.z) {
+                    let normal = Vec3::new(p.x, p.y, 0.0) / self.radius;
+                    let u = (p.y.atan2(p.x) + PI) / (2.0 * PI);
+                    hits.push((t, normal, u, p.z / self.height));
+                }
+            }
+        }
+
+        if capped && d.z.abs() > 1e-12 {
+            for (z, nz) in [(0.0, -1.0), (self.height, 1.0)] {
+                let t = (z - o.z) / d.z;
+                let p = o + t * d;
+                let dist_squared = p.x * p.x + p.y * p.y;
+                if dist_squared <= self.radius * self.radius {
+                    let u = (p.y.atan2(p.x) + PI) / (2.0 * PI);
+                    let v = dist_squared.sqrt() / self.radius;
+                    hits.push((t, Vec3::new(0.0, 0.0, nz), u, v));
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits.into_iter()
+            .map(|(t, local_normal, u, v)| HitRecord {
+                p: r.at(t),
+                normal: self.frame.local(local_normal),
+                material: Arc::clone(&self.material),
+                t,
+                u,
+                v,
+                ..HitRecord::new()
+            })
+            .collect()
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        for crossing in self.crossings(r, self.capped) {
+            if ray_t.surrounds(crossing.t) {
+                *rec = crossing;
+                rec.set_face_normal(r, rec.normal);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::surrounding(
+            &disk_bounding_box(self.base, self.frame.w, self.radius),
+            &disk_bounding_box(self.top, self.frame.w, self.radius),
+        )
+    }
+}
+
+// An uncapped cylinder is not closed, so as a solid it is always treated as capped
+impl Solid for Cylinder {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        pair_crossings(self.crossings(r, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    // A capped cylinder of radius 1 standing on the origin along +z, 2 units tall
+    fn cylinder() -> Cylinder {
+        Cylinder::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            1.0,
+            true,
+            grey(),
+        )
+    }
+
+    #[test]
+    fn ray_tangent_to_side() {
+        // Just inside the tangent line the two roots are close together around x = 0
+        let inside = Ray::new(Vec3::new(-5.0, 1.0 - 1e-6, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&cylinder(), &inside).expect("ray just inside the tangent should hit");
+        assert!((rec.t - 5.0).abs() < 1e-2);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        let outside = Ray::new(Vec3::new(-5.0, 1.0 + 1e-6, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&cylinder(), &outside).is_none());
+    }
+
+    #[test]
+    fn ray_grazing_cap_edge() {
+        let inside = Ray::new(Vec3::new(1.0 - 1e-6, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = hit(&cylinder(), &inside).expect("ray just inside the rim should hit the cap");
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        // Parallel to the axis and just outside, so it misses both the cap and the side
+        let outside = Ray::new(Vec3::new(1.0 + 1e-6, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit(&cylinder(), &outside).is_none());
+    }
+
+    #[test]
+    fn ray_parallel_to_cap_at_rim() {
+        // Just below the top rim the ray skims the cap's plane and enters through the side
+        let below = Ray::new(Vec3::new(-5.0, 0.0, 2.0 - 1e-9), Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&cylinder(), &below).expect("ray just below the rim should hit the side");
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        let above = Ray::new(Vec3::new(-5.0, 0.0, 2.0 + 1e-9), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&cylinder(), &above).is_none());
+    }
+
+    #[test]
+    fn zero_radius_is_never_hit() {
+        let thin = Cylinder::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            0.0,
+            true,
+            grey(),
+        );
+        // Straight down the axis, where the cap's UVs used to divide zero by zero
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit(&thin, &r).is_none());
+    }
+}
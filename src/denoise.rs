@@ -0,0 +1,307 @@
+use std::path::PathBuf;
+
+use crate::{
+    aov::{Aov, AovImages},
+    camera::progress_bar,
+    film::Film,
+    util::parallel_for,
+    vec3::Color,
+};
+
+// Cleans up noisy renders with a non-local means filter guided by feature buffers, after
+// Rousselle et al.'s adaptive rendering with non-local means filtering. Each pixel becomes a
+// weighted average of the pixels in a window around it. Neighbours count for more the more the
+// patch of noisy color around them looks like the patch around the pixel, measured against how
+// noisy the two are, so that differences the noise could explain are ignored. The albedo, normal
+// and depth of what each pixel sees then keep edges, texture and silhouettes that the noise
+// hides from the color alone. The camera records these features from the same samples as the
+// image, so they line up with it exactly.
+//
+// The noise comes from the variance of each pixel's samples, which the film keeps as it adds them,
+// smoothed over its neighbours, so pixels that converged are left nearly as they are. Integrators
+// that only splat, like MLT, give no variance, and their images pass through unchanged.
+pub struct Denoiser {
+    // Half the width of the window of neighbours, and of the patches compared
+    radius: u32,
+    patch_radius: u32,
+    // Scales how different patches must be, in standard deviations of their noise, before they
+    // stop being blended
+    strength: f64,
+    // How different features must be before they stop being blended: in each albedo channel, in
+    // the normal's direction, and in depth as a fraction of the pixel's depth
+    albedo_bandwidth: f64,
+    normal_bandwidth: f64,
+    depth_bandwidth: f64,
+    // Where the denoised image is written
+    output: PathBuf,
+}
+
+impl Denoiser {
+    pub const FEATURES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+    // Blends a 15x15 window with 3x3 patches by default
+    pub fn new(output: impl Into<PathBuf>) -> Denoiser {
+        Denoiser {
+            radius: 7,
+            patch_radius: 1,
+            strength: 0.45,
+            albedo_bandwidth: 0.1,
+            normal_bandwidth: 0.3,
+            depth_bandwidth: 0.05,
+            output: output.into(),
+        }
+    }
+
+    pub fn with_window(mut self, radius: u32, patch_radius: u32) -> Denoiser {
+        self.radius = radius;
+        self.patch_radius = patch_radius;
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Denoiser {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_feature_bandwidths(mut self, albedo: f64, normal: f64, depth: f64) -> Denoiser {
+        self.albedo_bandwidth = albedo;
+        self.normal_bandwidth = normal;
+        self.depth_bandwidth = depth;
+        self
+    }
+
+    pub fn output(&self) -> &PathBuf {
+        &self.output
+    }
+
+    // The film's image, with its splats scaled by splat_scale, denoised with the help of the
+    // features recorded from its samples. Features that weren't recorded don't guide the filter.
+    pub fn denoise(&self, film: &Film, splat_scale: f64, features: &AovImages) -> Vec<Color> {
+        let (width, height) = (film.width(), film.height());
+        let image = film.pixels(splat_scale);
+        let variance = self.smoothed_variance(film);
+        let albedo = features.get(Aov::Albedo);
+        let normal = features.get(Aov::Normal);
+        let depth = features.get(Aov::Depth);
+
+        let index = |x: u32, y: u32| y as usize * width as usize + x as usize;
+        let square = |c: Color| c.r * c.r + c.g * c.g + c.b * c.b;
+        let feature_distance = |p: usize, q: usize| {
+            let mut distance = 0.0;
+            if let Some(albedo) = albedo {
+                distance += square(albedo[p] - albedo[q]) / self.albedo_bandwidth.powi(2);
+            }
+            if let Some(normal) = normal {
+                distance += square(normal[p] - normal[q]) / self.normal_bandwidth.powi(2);
+            }
+            if let Some(depth) = depth {
+                let scale = self.depth_bandwidth * depth[p].r.max(1e-3);
+                distance += ((depth[p].r - depth[q].r) / scale).powi(2);
+            }
+            distance
+        };
+
+        // Squared distance between the patches around p and q, per channel and pixel, in units
+        // of their noise. The part of the difference that the noise accounts for is subtracted,
+        // so that identical but noisy patches come out at zero on average.
+        let patch = self.patch_radius as i64;
+        let patch_distance = |px: u32, py: u32, qx: u32, qy: u32| {
+            let mut total = 0.0;
+            let mut count = 0;
+            for dy in -patch..=patch {
+                for dx in -patch..=patch {
+                    let (Some(p), Some(q)) = (
+                        offset(px, py, dx, dy, width, height).map(|(x, y)| index(x, y)),
+                        offset(qx, qy, dx, dy, width, height).map(|(x, y)| index(x, y)),
+                    ) else {
+                        continue;
+                    };
+                    let (d, v_p, v_q) = (image[p] - image[q], variance[p], variance[q]);
+                    for (d, v_p, v_q) in [
+                        (d.r, v_p.r, v_q.r),
+                        (d.g, v_p.g, v_q.g),
+                        (d.b, v_p.b, v_q.b),
+                    ] {
+                        total += (d * d - (v_p + v_p.min(v_q)))
+                            / (1e-10 + self.strength * self.strength * (v_p + v_q));
+                    }
+                    count += 3;
+                }
+            }
+            if count > 0 {
+                (total / count as f64).max(0.0)
+            } else {
+                0.0
+            }
+        };
+
+        let denoised = Film::new(width, height);
+        let radius = self.radius as i64;
+        let bar = progress_bar(height as u64);
+        parallel_for(height, |y| {
+            for x in 0..width {
+                let p = index(x, y);
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut total_weight = 0.0;
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let Some((qx, qy)) = offset(x, y, dx, dy, width, height) else {
+                            continue;
+                        };
+                        let q = index(qx, qy);
+                        let weight = (-patch_distance(x, y, qx, qy) - feature_distance(p, q)).exp();
+                        sum += image[q] * weight;
+                        total_weight += weight;
+                    }
+                }
+                // The pixel itself always has a weight of one, so this never divides by zero
+                denoised.add_sample(x, y, sum / total_weight);
+            }
+            bar.inc(1);
+        });
+        bar.finish_with_message("Done!");
+        denoised.pixels(0.0)
+    }
+
+    // Each pixel's variance averaged with its neighbours in a 3x3 box, as the estimate from one
+    // pixel's samples is itself noisy
+    fn smoothed_variance(&self, film: &Film) -> Vec<Color> {
+        let (width, height) = (film.width(), film.height());
+        let mut smoothed = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut count = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if let Some((qx, qy)) = offset(x, y, dx, dy, width, height) {
+                            sum += film.variance(qx, qy);
+                            count += 1;
+                        }
+                    }
+                }
+                smoothed.push(sum / count as f64);
+            }
+        }
+        smoothed
+    }
+}
+
+// The pixel (dx, dy) away from (x, y), if it's within the image
+fn offset(x: u32, y: u32, dx: i64, dy: i64, width: u32, height: u32) -> Option<(u32, u32)> {
+    let (qx, qy) = (x as i64 + dx, y as i64 + dy);
+    if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+        return None;
+    }
+    Some((qx as u32, qy as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        aov::AovFilm,
+        hit::HitRecord,
+        material::{Lambertian, Material},
+        ray::Ray,
+        vec3::Vec3,
+    };
+
+    const SIZE: u32 = 16;
+
+    // Features of a wall facing the camera, with the albedo and depth given for each column
+    fn features(column: impl Fn(u32) -> (f64, f64)) -> AovImages {
+        let aovs = AovFilm::new(SIZE, SIZE, &Denoiser::FEATURES);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        for x in 0..SIZE {
+            let (albedo, depth) = column(x);
+            let material: Arc<dyn Material> =
+                Arc::new(Lambertian::new(Color::new(albedo, albedo, albedo)));
+            let mut rec = HitRecord {
+                material,
+                t: depth,
+                ..HitRecord::new()
+            };
+            rec.set_face_normal(&ray, Vec3::new(0.0, 0.0, 1.0));
+            for y in 0..SIZE {
+                aovs.add_surface(x, y, &ray, Some(&rec));
+            }
+        }
+        aovs.images(0.0)
+    }
+
+    // A film whose left and right halves average to the given values, with noisy samples
+    fn halves(left: f64, right: f64, noise: f64) -> Film {
+        let mut rng = StdRng::seed_from_u64(5);
+        let film = Film::new(SIZE, SIZE);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let value = if x < SIZE / 2 { left } else { right };
+                for _ in 0..8 {
+                    let sample = value + noise * (2.0 * rng.gen::<f64>() - 1.0);
+                    film.add_sample(x, y, Color::new(sample, sample, sample));
+                }
+            }
+        }
+        film
+    }
+
+    // Average of a column of the image
+    fn column(image: &[Color], x: u32) -> f64 {
+        (0..SIZE)
+            .map(|y| image[(y * SIZE + x) as usize].r)
+            .sum::<f64>()
+            / SIZE as f64
+    }
+
+    #[test]
+    fn converged_pixels_pass_through() {
+        let denoiser = Denoiser::new("denoised.ppm");
+        let features = features(|_| (0.5, 1.0));
+        // A flat image, and a checkerboard whose every pixel has converged
+        for checker in [0.0, 0.5] {
+            let film = Film::new(SIZE, SIZE);
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let value = 0.25 + checker * ((x + y) % 2) as f64;
+                    film.add_sample(x, y, Color::new(value, value, value));
+                    film.add_sample(x, y, Color::new(value, value, value));
+                }
+            }
+
+            let denoised = denoiser.denoise(&film, 0.0, &features);
+            for (denoised, original) in denoised.iter().zip(film.pixels(0.0)) {
+                assert!((denoised.r - original.r).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn feature_edges_are_kept() {
+        let denoiser = Denoiser::new("denoised.ppm");
+        let film = halves(0.2, 0.8, 3.0);
+        let edge = |image: &[Color]| (column(image, SIZE / 2 - 1), column(image, SIZE / 2));
+
+        // So noisy that the colors alone blur the edge
+        let unguided = AovFilm::new(SIZE, SIZE, &[]).images(0.0);
+        let (left, right) = edge(&denoiser.denoise(&film, 0.0, &unguided));
+        assert!(right - left < 0.4, "{left} {right}");
+
+        // The halves differ in albedo, then only in depth
+        let split = |x: u32| x < SIZE / 2;
+        for features in [
+            features(|x| if split(x) { (0.2, 1.0) } else { (0.8, 1.0) }),
+            features(|x| if split(x) { (0.5, 1.0) } else { (0.5, 2.0) }),
+        ] {
+            let (left, right) = edge(&denoiser.denoise(&film, 0.0, &features));
+            assert!(
+                (left - 0.2).abs() < 0.05 && (right - 0.8).abs() < 0.05,
+                "{left} {right}"
+            );
+        }
+    }
+}
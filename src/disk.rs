@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    material::Material,
+    onb::Onb,
+    ray::Ray,
+    util::{Interval, PI},
+    vec3::Vec3,
+};
+
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+    frame: Onb,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Arc<dyn Material>) -> Disk {
+        let frame = Onb::new(normal);
+        Disk {
+            center,
+            normal: frame.w,
+            radius: radius.max(0.0),
+            material,
+            frame,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let denom = r.direction.dot(self.normal);
+        if denom.abs() < 1e-12 {
+            return false;
+        }
+
+        let t = (self.center - r.origin).dot(self.normal) / denom;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        let p = r.at(t);
+        let local = self.frame.to_local(p - self.center);
+        let dist_squared = local.x * local.x + local.y * local.y;
+        if dist_squared > self.radius * self.radius {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_normal(r, self.normal);
+        // u is the angle around the normal, v the distance from the center
+        rec.u = (local.y.atan2(local.x) + PI) / (2.0 * PI);
+        rec.v = dist_squared.sqrt() / self.radius;
+        rec.material = Arc::clone(&self.material);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        disk_bounding_box(self.center, self.normal, self.radius)
+    }
+}
+
+// The extent of a disk along each axis is radius * sin(angle between the normal and that axis)
+pub fn disk_bounding_box(center: Vec3, normal: Vec3, radius: f64) -> Aabb {
+    let extent = Vec3::new(
+        radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+        radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+        radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+    );
+    Aabb::from_points(center - extent, center + extent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    // A disk of radius 1 on the origin facing +z
+    fn disk() -> Disk {
+        Disk::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            grey(),
+        )
+    }
+
+    #[test]
+    fn ray_parallel_to_disk() {
+        let above = Ray::new(Vec3::new(-5.0, 0.0, 1e-9), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&disk(), &above).is_none());
+        let within = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&disk(), &within).is_none());
+    }
+
+    #[test]
+    fn ray_grazing_rim() {
+        // Nearly parallel, landing just inside or just outside the rim
+        let inside = Ray::new(
+            Vec3::new(-2.0 + 1e-6, 0.0, 1e-3),
+            Vec3::new(1.0, 0.0, -1e-3),
+        );
+        let rec = hit(&disk(), &inside).expect("ray landing just inside the rim should hit");
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        let outside = Ray::new(
+            Vec3::new(-2.0 - 1e-6, 0.0, 1e-3),
+            Vec3::new(1.0, 0.0, -1e-3),
+        );
+        assert!(hit(&disk(), &outside).is_none());
+
+        // From below the normal is flipped to face the ray
+        let below = Ray::new(Vec3::new(1.0 - 1e-6, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = hit(&disk(), &below).expect("ray from below just inside the rim should hit");
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(!rec.front_face);
+    }
+}
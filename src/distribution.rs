@@ -0,0 +1,200 @@
+// A piecewise-constant distribution over [0,1), proportional to a list of non-negative values,
+// which can also be sampled as a discrete distribution over the indices of the values. When every
+// value is zero it falls back to uniform.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    // Average of func, which is its integral over [0,1)
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for (i, value) in func.iter().enumerate() {
+            cdf.push(cdf[i] + value.max(0.0) / n as f64);
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // Maps u in [0,1) to a value in [0,1), returning it with its density and the index of the
+    // piece it fell in
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let i = self.find(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = ((i as f64 + offset) / self.count() as f64).min(1.0 - f64::EPSILON);
+        (x, self.density(i), i)
+    }
+
+    // Picks an index with probability proportional to its value, returning it with that
+    // probability
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let i = self.find(u);
+        (i, self.discrete_pdf(i))
+    }
+
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+
+    // Density of sample_continuous at x
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.density(i)
+    }
+
+    fn density(&self, i: usize) -> f64 {
+        self.discrete_pdf(i) * self.count() as f64
+    }
+
+    // Index of the piece whose range of the CDF contains u
+    fn find(&self, u: f64) -> usize {
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.clamp(1, self.count()) - 1
+    }
+}
+
+// A piecewise-constant distribution over [0,1)^2 of a function given on an nu by nv grid, stored
+// row by row. Samples pick a row from the marginal distribution, then a column within the row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(nu)
+            .take(nv)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    // Maps (u1, u2) in [0,1)^2 to a point (u, v) in [0,1)^2, returning it with its density
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.conditional.len();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        self.conditional[row].pdf(u) * self.marginal.pdf(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Distribution1D {
+        Distribution1D::new(vec![1.0, 0.0, 3.0, 2.0, 0.5])
+    }
+
+    #[test]
+    fn samples_agree_with_pdfs() {
+        let distribution = example();
+        let samples = 10000;
+        let mut counts = [0; 5];
+        for k in 0..samples {
+            let u = (k as f64 + 0.5) / samples as f64;
+
+            let (x, pdf, i) = distribution.sample_continuous(u);
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(i, (x * 5.0) as usize);
+            assert_eq!(pdf, distribution.pdf(x));
+
+            let (i, probability) = distribution.sample_discrete(u);
+            assert_eq!(probability, distribution.discrete_pdf(i));
+            counts[i] += 1;
+        }
+
+        // Stratified u lands in each piece in proportion to its value, and never in empty ones
+        for (count, value) in counts.iter().zip([1.0, 0.0, 3.0, 2.0, 0.5]) {
+            let expected = value / 6.5 * samples as f64;
+            assert!((*count as f64 - expected).abs() <= 1.0, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn pdfs_integrate_to_one() {
+        let distribution = example();
+        let steps = 1000;
+        let integral: f64 = (0..steps)
+            .map(|k| distribution.pdf((k as f64 + 0.5) / steps as f64) / steps as f64)
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-9);
+        let total: f64 = (0..5).map(|i| distribution.discrete_pdf(i)).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        let func = [0.0, 1.0, 2.0, 0.0, 4.0, 1.0, 0.5, 0.0, 3.0];
+        let distribution = Distribution2D::new(&func, 3, 3);
+        let steps = 300;
+        let mut integral = 0.0;
+        for j in 0..steps {
+            for i in 0..steps {
+                let (u, v) = (
+                    (i as f64 + 0.5) / steps as f64,
+                    (j as f64 + 0.5) / steps as f64,
+                );
+                integral += distribution.pdf(u, v) / (steps * steps) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-9);
+
+        let ((u, v), pdf) = distribution.sample(0.3, 0.7);
+        assert!((pdf - distribution.pdf(u, v)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn all_zero_falls_back_to_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 4]);
+        assert_eq!(distribution.integral(), 0.0);
+        for u in [0.0, 0.1, 0.5, 0.9] {
+            let (x, pdf, _) = distribution.sample_continuous(u);
+            assert!((x - u).abs() < 1e-12);
+            assert_eq!(pdf, 1.0);
+            assert_eq!(distribution.sample_discrete(u).1, 0.25);
+        }
+
+        let distribution = Distribution2D::new(&[0.0; 6], 3, 2);
+        let ((u, v), pdf) = distribution.sample(0.25, 0.75);
+        assert!((u - 0.25).abs() < 1e-12 && (v - 0.75).abs() < 1e-12);
+        assert_eq!(pdf, 1.0);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    distribution::Distribution2D,
+    sampler::random,
+    texture::{ImageTexture, Texture},
+    util::{degrees_to_radians, PI},
+    vec3::{Color, Vec3},
+};
+
+// Light arriving from infinitely far away, seen by rays that leave the scene without hitting
+// anything
+pub trait Environment: Send + Sync {
+    // Radiance arriving from the unit vector direction
+    fn radiance(&self, direction: Vec3) -> Color;
+
+    // Picks a unit vector direction to gather light from, returning it with its density over solid
+    // angle. Environments that can't be sampled return None and are only seen by escaping rays.
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    // Density over solid angle with which sample picks the unit vector direction
+    fn pdf(&self, _direction: Vec3) -> f64 {
+        0.0
+    }
+}
+
+// A blend from white at the horizon to light blue overhead
+pub struct GradientSky;
+
+impl Environment for GradientSky {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let t = 0.5 * (direction.y + 1.0);
+        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    }
+}
+
+// An image of everything surrounding the scene in latitude-longitude layout, such as an HDR light
+// probe. The top row is straight up (+y), the bottom row straight down, and the center of the
+// image looks toward -z, with +x a quarter turn to its right.
+//
+// Directions are sampled in proportion to the image's luminance, so small bright features like the
+// sun are found by shadow rays rather than only by chance.
+pub struct EnvironmentMap {
+    image: ImageTexture,
+    scale: f64,
+    rotation: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: ImageTexture) -> EnvironmentMap {
+        let (width, height) = (image.width(), image.height());
+
+        // Each row is weighted by sin(theta) at its center, since rows near the poles cover less
+        // solid angle
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                let luminance = image.pixel(x as i64, y as i64).luminance().max(0.0);
+                func.push(luminance * sin_theta);
+            }
+        }
+
+        EnvironmentMap {
+            distribution: Distribution2D::new(&func, width, height),
+            image,
+            scale: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> EnvironmentMap {
+        self.scale = scale;
+        self
+    }
+
+    // Turns the environment by `degrees` about the y axis
+    pub fn with_rotation(mut self, degrees: f64) -> EnvironmentMap {
+        self.rotation = degrees_to_radians(degrees);
+        self
+    }
+
+    // Image coordinates of the unit vector direction, with u across and t down from the top
+    fn coordinates(&self, direction: Vec3) -> (f64, f64) {
+        let phi = direction.x.atan2(-direction.z) - self.rotation;
+        let u = (0.5 + phi / (2.0 * PI)).rem_euclid(1.0);
+        let t = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, t)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let (u, t) = self.coordinates(direction);
+        self.image.value(u, 1.0 - t, direction) * self.scale
+    }
+
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        let ((u, t), pdf) = self.distribution.sample(random(), random());
+        let theta = t * PI;
+        let sin_theta = theta.sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        // The image spans 2 pi by pi radians
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let direction = Vec3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos());
+        Some((direction, pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, t) = self.coordinates(direction);
+        let sin_theta = (t * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, t) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    io::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{aov::AovFilm, integrator::Lighting, vec3::Color};
+
+// The image being rendered. Camera samples are averaged into the pixel they were taken for, while
+// integrators that trace paths from the lights splat what they find onto whichever pixel it lands
+// in. Both can be added from any number of threads at once. Pixels are counted across from the
+// left and down from the top.
+//
+// A film can also carry AOV passes, which the camera records from the same samples as the image.
+pub struct Film {
+    width: u32,
+    height: u32,
+    sums: Vec<AtomicColor>,
+    counts: Vec<AtomicU64>,
+    // Sums of the squares of the samples, for estimating their variance
+    squares: Vec<AtomicColor>,
+    splats: Vec<AtomicColor>,
+    aovs: Option<AovFilm>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let size = width as usize * height as usize;
+        Film {
+            width,
+            height,
+            sums: (0..size).map(|_| AtomicColor::new()).collect(),
+            counts: (0..size).map(|_| AtomicU64::new(0)).collect(),
+            squares: (0..size).map(|_| AtomicColor::new()).collect(),
+            splats: (0..size).map(|_| AtomicColor::new()).collect(),
+            aovs: None,
+        }
+    }
+
+    pub fn with_aovs(mut self, aovs: AovFilm) -> Film {
+        self.aovs = Some(aovs);
+        self
+    }
+
+    pub fn aovs(&self) -> Option<&AovFilm> {
+        self.aovs.as_ref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn add_sample(&self, x: u32, y: u32, color: Color) {
+        let i = self.index(x, y);
+        self.sums[i].add(color);
+        self.squares[i].add(color * color);
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_splat(&self, x: u32, y: u32, color: Color) {
+        self.splats[self.index(x, y)].add(color);
+    }
+
+    // Splats the total light onto the image, and each part of it onto the matching lighting pass
+    pub fn add_lighting_splat(&self, x: u32, y: u32, lighting: &Lighting) {
+        self.add_splat(x, y, lighting.total());
+        if let Some(aovs) = &self.aovs {
+            aovs.add_splat(x, y, lighting);
+        }
+    }
+
+    // The mean of the pixel's samples plus its splats times splat_scale. Splats are estimates of
+    // the whole image, so they're scaled by how many were made for each pixel.
+    pub fn pixel(&self, x: u32, y: u32, splat_scale: f64) -> Color {
+        let i = self.index(x, y);
+        let count = self.counts[i].load(Ordering::Relaxed);
+        let mean = if count > 0 {
+            self.sums[i].get() / count as f64
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        };
+        mean + self.splats[i].get() * splat_scale
+    }
+
+    // How many samples the pixel has taken
+    pub fn samples(&self, x: u32, y: u32) -> u64 {
+        self.counts[self.index(x, y)].load(Ordering::Relaxed)
+    }
+
+    // The variance of the mean of the pixel's samples in each channel, estimated from the spread
+    // of the samples, or zero with fewer than two. Splats aren't counted.
+    pub fn variance(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        let count = self.counts[i].load(Ordering::Relaxed);
+        if count < 2 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let n = count as f64;
+        let mean = self.sums[i].get() / n;
+        let spread = (self.squares[i].get() - mean * mean * n) / (n * (n - 1.0));
+        Color::new(spread.r.max(0.0), spread.g.max(0.0), spread.b.max(0.0))
+    }
+
+    // Every pixel as given by pixel(), top row first
+    pub fn pixels(&self, splat_scale: f64) -> Vec<Color> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| self.pixel(x, y, splat_scale)))
+            .collect()
+    }
+
+    // Writes the image as a plain PPM, top row first
+    pub fn write_ppm(&self, out: &mut impl Write, splat_scale: f64) {
+        write_ppm(out, self.width, self.height, &self.pixels(splat_scale));
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+// Writes pixels in rows from the top as a plain PPM
+pub fn write_ppm(out: &mut impl Write, width: u32, height: u32, pixels: &[Color]) {
+    writeln!(out, "P3\n{} {}\n255", width, height).expect("Error writing image header");
+    for pixel in pixels {
+        pixel.write_color(out);
+    }
+}
+
+// A color that can be added to from several threads, storing each channel's bits in an atomic
+struct AtomicColor {
+    channels: [AtomicU64; 3],
+}
+
+impl AtomicColor {
+    fn new() -> AtomicColor {
+        AtomicColor {
+            channels: [0.0f64, 0.0, 0.0].map(|value| AtomicU64::new(value.to_bits())),
+        }
+    }
+
+    fn add(&self, color: Color) {
+        for (channel, value) in self.channels.iter().zip([color.r, color.g, color.b]) {
+            // A retried compare-and-swap, as there's no atomic float addition
+            let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+        }
+    }
+
+    fn get(&self) -> Color {
+        let [r, g, b] = &self.channels;
+        Color::new(
+            f64::from_bits(r.load(Ordering::Relaxed)),
+            f64::from_bits(g.load(Ordering::Relaxed)),
+            f64::from_bits(b.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variance_is_unbiased() {
+        let film = Film::new(2, 1);
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            film.add_sample(0, 0, Color::new(value, 2.0 * value, 5.0));
+        }
+        // The samples' variance is 5/3 with Bessel's correction, and their mean's a quarter of it
+        let variance = film.variance(0, 0);
+        assert!((variance.r - 5.0 / 12.0).abs() < 1e-12);
+        assert!((variance.g - 4.0 * 5.0 / 12.0).abs() < 1e-12);
+        assert!(variance.b.abs() < 1e-12);
+        assert_eq!(film.samples(0, 0), 4);
+
+        // A single sample says nothing about the spread
+        film.add_sample(1, 0, Color::new(7.0, 7.0, 7.0));
+        assert_eq!(film.variance(1, 0).r, 0.0);
+    }
+}
@@ -0,0 +1,369 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{aabb::Aabb, light::bounding_sphere, sampler::random, util::PI, vec3::Vec3};
+
+type Point3 = Vec3;
+
+// Directional trees are refined no deeper than this, and split quadrants holding more than this
+// fraction of the light
+const MAX_DIRECTIONAL_DEPTH: u32 = 20;
+const DIRECTIONAL_THRESHOLD: f64 = 0.01;
+const MAX_SPATIAL_DEPTH: u32 = 48;
+
+// A spatial-directional tree for path guiding, after Müller et al.'s practical path guiding. A
+// binary tree halves the scene's bounds along x, y and z in turn, and each of its leaves holds a
+// quadtree over directions that learns where the light arriving in that part of the scene comes
+// from. Paths record the light they find as they go, and between passes of training each leaf's
+// quadtree is refined where it collected the most light, and leaves that collected many samples
+// are split.
+//
+// Each leaf keeps two quadtrees: the one learned in the last pass, which is sampled, and the one
+// being learned in this pass. Recording can happen from any number of threads at once.
+pub struct GuidingTree {
+    min: [f64; 3],
+    size: [f64; 3],
+    nodes: Vec<SpatialNode>,
+}
+
+struct SpatialNode {
+    depth: u32,
+    // Index of the first of its two children, or 0 for a leaf
+    children: usize,
+    samples: AtomicU64,
+    sampling: DirectionalTree,
+    building: DirectionalTree,
+}
+
+impl GuidingTree {
+    // A single leaf covering the bounds, sampling directions uniformly. Unbounded axes are cut
+    // down to the bounding sphere of the rest of the scene.
+    pub fn new(bounds: &Aabb) -> GuidingTree {
+        let (center, radius) = bounding_sphere(bounds);
+        let radius = radius.max(1.0);
+        let mut min = [0.0; 3];
+        let mut size = [0.0; 3];
+        for axis in 0..3 {
+            let interval = bounds.axis_interval(axis);
+            let (low, high) = if interval.min.is_finite() && interval.max.is_finite() {
+                (interval.min, interval.max)
+            } else {
+                (center[axis] - radius, center[axis] + radius)
+            };
+            // Flat scenes still need some extent to split
+            min[axis] = low - 1e-3;
+            size[axis] = (high - low).max(0.0) + 2e-3;
+        }
+
+        GuidingTree {
+            min,
+            size,
+            nodes: vec![SpatialNode::leaf(
+                0,
+                DirectionalTree::new(),
+                DirectionalTree::new(),
+            )],
+        }
+    }
+
+    // A direction sampled from what the leaf around p learned in the last pass
+    pub fn sample(&self, p: Point3) -> Vec3 {
+        let (u, v) = self.leaf(p).sampling.sample();
+        square_to_direction(u, v)
+    }
+
+    // Density over solid angle of sample at p
+    pub fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        let (u, v) = direction_to_square(direction);
+        self.leaf(p).sampling.pdf(u, v) / (4.0 * PI)
+    }
+
+    // Records that the light arriving at p from the unit vector direction had the given
+    // brightness, divided by the density it was sampled with
+    pub fn record(&self, p: Point3, direction: Vec3, value: f64) {
+        if !value.is_finite() || value < 0.0 {
+            return;
+        }
+        let leaf = self.leaf(p);
+        leaf.samples.fetch_add(1, Ordering::Relaxed);
+        let (u, v) = direction_to_square(direction);
+        leaf.building.record(u, v, value);
+    }
+
+    // Ends a pass of training. Leaves that recorded more than spatial_threshold samples are
+    // split, and each leaf then samples what it learned while learning again into a quadtree
+    // refined where that found the most light.
+    pub fn refine(&mut self, spatial_threshold: f64) {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            let samples = node.samples.load(Ordering::Relaxed);
+            if node.children == 0
+                && samples as f64 > spatial_threshold
+                && node.depth < MAX_SPATIAL_DEPTH
+            {
+                // Both halves start from what the whole leaf learned, with half its samples
+                let depth = node.depth + 1;
+                let building = node.building.clone();
+                let children = self.nodes.len();
+                for _ in 0..2 {
+                    let child = SpatialNode::leaf(depth, DirectionalTree::new(), building.clone());
+                    child.samples.store(samples / 2, Ordering::Relaxed);
+                    self.nodes.push(child);
+                }
+                let node = &mut self.nodes[i];
+                node.children = children;
+                node.sampling = DirectionalTree::new();
+                node.building = DirectionalTree::new();
+            }
+            i += 1;
+        }
+
+        for node in self.nodes.iter_mut().filter(|node| node.children == 0) {
+            let refined = node.building.refined();
+            node.sampling = std::mem::replace(&mut node.building, refined);
+            node.samples.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // The leaf whose box contains p, or the nearest one for points outside the bounds
+    fn leaf(&self, p: Point3) -> &SpatialNode {
+        let mut min = self.min;
+        let mut size = self.size;
+        let mut node = &self.nodes[0];
+        while node.children != 0 {
+            let axis = node.depth as usize % 3;
+            size[axis] *= 0.5;
+            let upper = p[axis] >= min[axis] + size[axis];
+            if upper {
+                min[axis] += size[axis];
+            }
+            node = &self.nodes[node.children + upper as usize];
+        }
+        node
+    }
+}
+
+impl SpatialNode {
+    fn leaf(depth: u32, sampling: DirectionalTree, building: DirectionalTree) -> SpatialNode {
+        SpatialNode {
+            depth,
+            children: 0,
+            samples: AtomicU64::new(0),
+            sampling,
+            building,
+        }
+    }
+}
+
+// Directions map to the unit square by the cosine of their angle from the z axis and the angle
+// around it, which keeps areas in proportion, so densities over the square are 4π times those
+// over the sphere
+fn direction_to_square(direction: Vec3) -> (f64, f64) {
+    let d = direction.unit_vector();
+    let cos_theta = d.z.clamp(-1.0, 1.0);
+    let mut phi = d.y.atan2(d.x);
+    if phi < 0.0 {
+        phi += 2.0 * PI;
+    }
+    (
+        (0.5 * (cos_theta + 1.0)).min(1.0 - f64::EPSILON),
+        (phi / (2.0 * PI)).min(1.0 - f64::EPSILON),
+    )
+}
+
+fn square_to_direction(u: f64, v: f64) -> Vec3 {
+    let cos_theta = 2.0 * u - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// A distribution over the unit square as a quadtree. Each node holds the light recorded in each
+// of its four quadrants, and quadrants with a child are split again. The density within a leaf
+// quadrant is uniform.
+struct DirectionalTree {
+    nodes: Vec<QuadNode>,
+}
+
+struct QuadNode {
+    sums: [AtomicF64; 4],
+    // Index of the node splitting each quadrant, or 0 where it isn't split
+    children: [usize; 4],
+}
+
+impl QuadNode {
+    fn new(sums: [f64; 4]) -> QuadNode {
+        QuadNode {
+            sums: sums.map(AtomicF64::new),
+            children: [0; 4],
+        }
+    }
+
+    fn sums(&self) -> [f64; 4] {
+        [0, 1, 2, 3].map(|q| self.sums[q].get())
+    }
+}
+
+// Which quadrant of a node (u, v) falls in, numbered across then up, with where it falls within
+// that quadrant
+fn quadrant(u: f64, v: f64) -> (usize, f64, f64) {
+    let (right, top) = (u >= 0.5, v >= 0.5);
+    let q = right as usize + 2 * top as usize;
+    (
+        q,
+        (2.0 * u - right as u8 as f64).min(1.0 - f64::EPSILON),
+        (2.0 * v - top as u8 as f64).min(1.0 - f64::EPSILON),
+    )
+}
+
+impl DirectionalTree {
+    // A single node with nothing recorded, sampled uniformly
+    fn new() -> DirectionalTree {
+        DirectionalTree {
+            nodes: vec![QuadNode::new([0.0; 4])],
+        }
+    }
+
+    fn record(&self, mut u: f64, mut v: f64, value: f64) {
+        let mut node = &self.nodes[0];
+        loop {
+            let (q, qu, qv) = quadrant(u, v);
+            node.sums[q].add(value);
+            if node.children[q] == 0 {
+                return;
+            }
+            node = &self.nodes[node.children[q]];
+            (u, v) = (qu, qv);
+        }
+    }
+
+    // Picks a quadrant at each level in proportion to the light recorded there, falling back to
+    // uniform below nodes that recorded none
+    fn sample(&self) -> (f64, f64) {
+        let mut node = &self.nodes[0];
+        let (mut min_u, mut min_v, mut size) = (0.0, 0.0, 1.0);
+        loop {
+            let sums = node.sums();
+            let total: f64 = sums.iter().sum();
+            if total <= 0.0 {
+                break;
+            }
+            let mut target = random() * total;
+            let mut q = 3;
+            for (i, sum) in sums.iter().enumerate() {
+                if target < *sum {
+                    q = i;
+                    break;
+                }
+                target -= sum;
+            }
+            size *= 0.5;
+            min_u += size * (q % 2) as f64;
+            min_v += size * (q / 2) as f64;
+            if node.children[q] == 0 {
+                break;
+            }
+            node = &self.nodes[node.children[q]];
+        }
+        (min_u + size * random(), min_v + size * random())
+    }
+
+    // Density of sample at (u, v)
+    fn pdf(&self, mut u: f64, mut v: f64) -> f64 {
+        let mut node = &self.nodes[0];
+        let mut pdf = 1.0;
+        loop {
+            let sums = node.sums();
+            let total: f64 = sums.iter().sum();
+            if total <= 0.0 {
+                return pdf;
+            }
+            let (q, qu, qv) = quadrant(u, v);
+            pdf *= 4.0 * sums[q] / total;
+            if node.children[q] == 0 {
+                return pdf;
+            }
+            node = &self.nodes[node.children[q]];
+            (u, v) = (qu, qv);
+        }
+    }
+
+    // An empty tree split wherever a quadrant of this one recorded more than a small fraction of
+    // all its light. Quadrants that weren't split before share theirs evenly between the new
+    // children, so the refined tree can go several levels deeper in one pass.
+    fn refined(&self) -> DirectionalTree {
+        let root = &self.nodes[0];
+        let total: f64 = root.sums().iter().sum();
+        let mut refined = DirectionalTree::new();
+        if total > 0.0 {
+            refined.refine_node(0, self, Some(0), root.sums(), total, 1);
+        }
+        refined
+    }
+
+    // Splits the quadrants of new node `index` whose share of `sums` is large enough, following
+    // node `old` of the tree being refined where it has one
+    fn refine_node(
+        &mut self,
+        index: usize,
+        old: &DirectionalTree,
+        old_node: Option<usize>,
+        sums: [f64; 4],
+        total: f64,
+        depth: u32,
+    ) {
+        for (q, sum) in sums.iter().enumerate() {
+            if sum / total <= DIRECTIONAL_THRESHOLD || depth >= MAX_DIRECTIONAL_DEPTH {
+                continue;
+            }
+            let old_child = old_node
+                .map(|node| old.nodes[node].children[q])
+                .filter(|&child| child != 0);
+            let child_sums = match old_child {
+                Some(child) => old.nodes[child].sums(),
+                None => [sum / 4.0; 4],
+            };
+            let child = self.nodes.len();
+            self.nodes.push(QuadNode::new([0.0; 4]));
+            self.nodes[index].children[q] = child;
+            self.refine_node(child, old, old_child, child_sums, total, depth + 1);
+        }
+    }
+}
+
+impl Clone for DirectionalTree {
+    fn clone(&self) -> DirectionalTree {
+        DirectionalTree {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| QuadNode {
+                    sums: node.sums().map(AtomicF64::new),
+                    children: node.children,
+                })
+                .collect(),
+        }
+    }
+}
+
+// A float that can be added to from several threads, storing its bits in an atomic
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> AtomicF64 {
+        AtomicF64(AtomicU64::new(value.to_bits()))
+    }
+
+    fn add(&self, value: f64) {
+        // A retried compare-and-swap, as there's no atomic float addition
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
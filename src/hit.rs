@@ -1,16 +1,28 @@
 use std::sync::Arc;
 
+use crate::aabb::{Aabb, EMPTY_AABB};
 use crate::material::{Lambertian, Material};
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::random;
 use crate::util::Interval;
 use crate::vec3::{Color, Vec3};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub front_face: bool,
     pub p: Vec3,
     pub normal: Vec3,
     pub material: Arc<dyn Material>,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    // Direction of increasing u along the surface, or zero if the surface doesn't provide one
+    pub tangent: Vec3,
+    // Index of the scene light whose shape was hit, for area lights
+    pub light: Option<usize>,
+    // Index of the object in the scene's list that was hit, for object ID passes
+    pub object: usize,
 }
 
 impl HitRecord {
@@ -21,6 +33,11 @@ impl HitRecord {
             normal: Vec3::new(0.0, 0.0, 0.0),
             material: Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0))),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::new(0.0, 0.0, 0.0),
+            light: None,
+            object: 0,
         }
     }
 
@@ -32,35 +49,68 @@ impl HitRecord {
             -outward_normal
         };
     }
+
+    // Alpha test against the material's opacity. Scene::hit makes it once for each hit it finds.
+    pub fn is_masked(&self) -> bool {
+        let opacity = self.material.opacity(self);
+        opacity < 1.0 && random() >= opacity
+    }
+
+    // Local shading frame with w along the normal and u along the tangent, when there is one
+    pub fn shading_frame(&self) -> Onb {
+        if self.tangent.near_zero() {
+            Onb::new(self.normal)
+        } else {
+            Onb::from_tangent(self.normal, self.tangent)
+        }
+    }
 }
 
-pub trait Hittable {
+impl Default for HitRecord {
+    fn default() -> Self {
+        HitRecord::new()
+    }
+}
+
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    fn bounding_box(&self) -> Aabb;
 }
 
 pub struct Hittables {
     objects: Vec<Box<dyn Hittable>>,
+    bbox: Aabb,
 }
 
 impl Hittables {
     pub fn new() -> Hittables {
         Hittables {
             objects: Vec::new(),
+            bbox: EMPTY_AABB,
         }
     }
 
     pub fn add(&mut self, object: Box<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
 }
 
+impl Default for Hittables {
+    fn default() -> Self {
+        Hittables::new()
+    }
+}
+
 impl Hittable for Hittables {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let mut temp_rec = HitRecord::new();
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
+            temp_rec.light = None;
             if object.hit(
                 r,
                 Interval {
@@ -71,14 +121,15 @@ impl Hittable for Hittables {
             ) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
-                rec.p = temp_rec.p;
-                rec.normal = temp_rec.normal;
-                rec.t = temp_rec.t;
-                rec.front_face = temp_rec.front_face;
-                rec.material = Arc::clone(&temp_rec.material);
+                *rec = temp_rec.clone();
+                rec.object = index;
             }
         }
 
         hit_anything
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
 }
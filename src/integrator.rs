@@ -0,0 +1,932 @@
+use crate::{
+    bdpt::Bdpt,
+    camera::Camera,
+    film::Film,
+    guiding::GuidingTree,
+    hit::{HitRecord, Hittable},
+    light::bounding_sphere,
+    material::Bounce,
+    mlt::Mlt,
+    onb::Onb,
+    photon_map::PhotonMap,
+    ray::Ray,
+    sampler::random,
+    scene::Scene,
+    spectrum::SampledWavelengths,
+    util::{power_heuristic, Interval},
+    vec3::{Color, Vec3},
+};
+
+// A light transport algorithm, which the camera calls once for each sample of each pixel
+pub trait Integrator: Send + Sync {
+    // Estimates the light arriving back along a ray from the camera, and finds the surface the
+    // ray hits first. Integrators that also trace paths from the lights splat the light they
+    // carry straight to the camera onto the film.
+    fn sample(&self, ray: &Ray, scene: &Scene, camera: &Camera, film: &Film) -> CameraSample;
+
+    // Renders the image onto the film, returning the scale its splats are given. By default every
+    // pixel gets the camera's samples, each traced with `sample`, and each of those may splat one
+    // estimate of the whole image.
+    fn render(&self, scene: &Scene, camera: &Camera, film: &Film) -> f64 {
+        camera.render_samples(film, |ray| self.sample(ray, scene, camera, film))
+    }
+}
+
+// What an integrator found along a camera ray: the light arriving back along it, and the surface
+// it hit first, if any, which the film's surface passes are recorded from
+pub struct CameraSample {
+    pub lighting: Lighting,
+    pub hit: Option<HitRecord>,
+}
+
+// Picks an integrator by the name given on the command line: "path", "naive", "whitted", "ao",
+// "bdpt", "photon", "mlt" or "guided"
+pub fn by_name(name: &str, max_depth: u32) -> Option<Box<dyn Integrator>> {
+    let integrator: Box<dyn Integrator> = match name {
+        "path" => Box::new(PathTracer::new(DepthLimits::new(max_depth))),
+        "naive" => Box::new(PathTracer::naive(DepthLimits::new(max_depth))),
+        "whitted" => Box::new(Whitted::new(max_depth)),
+        "ao" => Box::new(AmbientOcclusion::new(f64::INFINITY)),
+        "bdpt" => Box::new(Bdpt::new(max_depth)),
+        "photon" => Box::new(PhotonMapper::new(DepthLimits::new(max_depth), 200_000)),
+        "mlt" => Box::new(Mlt::new(DepthLimits::new(max_depth))),
+        "guided" => Box::new(GuidedPathTracer::new(DepthLimits::new(max_depth))),
+        _ => return None,
+    };
+    Some(integrator)
+}
+
+// How far paths are followed. `max` caps the number of rays in a path, counting the one from the
+// camera, and the others cap how many bounces of each kind it may take. After `roulette_depth`
+// bounces, paths are ended at random with Russian roulette.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthLimits {
+    pub max: u32,
+    pub diffuse: u32,
+    pub specular: u32,
+    pub transmission: u32,
+    pub roulette_depth: u32,
+}
+
+impl DepthLimits {
+    // Limits every kind of bounce only by the overall maximum
+    pub fn new(max: u32) -> DepthLimits {
+        DepthLimits {
+            max,
+            diffuse: max,
+            specular: max,
+            transmission: max,
+            roulette_depth: 3,
+        }
+    }
+}
+
+// Bounces a path has taken so far
+#[derive(Default)]
+struct BounceCounts {
+    total: u32,
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+}
+
+impl BounceCounts {
+    // Counts a bounce, returning whether the path may continue after it
+    fn add(&mut self, bounce: Bounce, limits: &DepthLimits) -> bool {
+        self.total += 1;
+        let (count, limit) = match bounce {
+            Bounce::Diffuse => (&mut self.diffuse, limits.diffuse),
+            Bounce::Specular => (&mut self.specular, limits.specular),
+            Bounce::Transmission => (&mut self.transmission, limits.transmission),
+        };
+        *count += 1;
+        *count <= limit
+    }
+}
+
+// Which estimator counts light that can be found both by sampling it directly and by BSDF
+// sampling, such as area lights and sampled environments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sharing {
+    // Both, weighted with multiple importance sampling
+    Mis,
+    // Only direct lighting, for integrators that don't follow BSDF samples off these surfaces
+    LightsOnly,
+    // Only BSDF sampling
+    BsdfOnly,
+}
+
+// Follows a single path from the camera through the scene. At each hit it gathers what the
+// surface emits, samples one light for direct lighting, and continues in a direction sampled
+// from the material.
+//
+// By default light sampling and BSDF sampling are combined with multiple importance sampling.
+// The naive tracer only finds area lights and the environment by hitting them, which is far
+// noisier; lights that rays can't hit are still sampled directly.
+pub struct PathTracer {
+    limits: DepthLimits,
+    sharing: Sharing,
+}
+
+impl PathTracer {
+    pub fn new(limits: DepthLimits) -> PathTracer {
+        PathTracer {
+            limits,
+            sharing: Sharing::Mis,
+        }
+    }
+
+    pub fn naive(limits: DepthLimits) -> PathTracer {
+        PathTracer {
+            limits,
+            sharing: Sharing::BsdfOnly,
+        }
+    }
+
+    // Traces a path from the camera ray, keeping the light it finds apart by how many times that
+    // light scattered before reaching the camera
+    pub fn trace(&self, ray: &Ray, scene: &Scene) -> CameraSample {
+        let mut lighting = Lighting::new();
+        let mut first_hit = None;
+        // Fraction of the light found further along the path that makes it back to the camera
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bounces = BounceCounts::default();
+
+        // The density with which the previous hit's material sampled the current ray, or 0 for
+        // camera rays and materials that aren't also lit by sampling the lights, and the normal
+        // at that hit. Light the ray finds that could have been sampled from that hit is weighted
+        // against the light sample.
+        let mut bsdf_pdf = 0.0;
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        let mis = |light_pdf: f64, bsdf_pdf: f64| {
+            if self.sharing == Sharing::Mis && bsdf_pdf > 0.0 {
+                power_heuristic(bsdf_pdf, light_pdf)
+            } else {
+                1.0
+            }
+        };
+
+        // Once the path reaches the bounce limit, no more light is gathered
+        for depth in 0..self.limits.max {
+            let mut rec = HitRecord::new();
+            if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                let direction = ray.direction.unit_vector();
+                let weight = mis(scene.environment.pdf(direction), bsdf_pdf);
+                lighting.add(
+                    depth,
+                    throughput * ray.radiance(scene.environment.radiance(direction)) * weight,
+                );
+                break;
+            }
+
+            // Whatever the surface emits and the light it reflects directly from the lights
+            let mut emitted = rec.material.emitted(&ray, &rec);
+            if let Some(light) = rec.light {
+                let direction = ray.direction.unit_vector();
+                let light_pdf = scene.light_pdf(ray.origin, normal, light, direction);
+                emitted = emitted * mis(light_pdf, bsdf_pdf);
+            }
+            lighting.add(depth, throughput * ray.radiance(emitted));
+            lighting.add(
+                depth + 1,
+                throughput * direct_light(&ray, scene, &rec, self.sharing),
+            );
+            if depth == 0 {
+                first_hit = Some(rec.clone());
+            }
+
+            let Some((scattered, attenuation)) = scatter(&ray, &rec) else {
+                break;
+            };
+            if !bounces.add(rec.material.bounce(&ray, &rec, &scattered), &self.limits) {
+                break;
+            }
+            throughput = throughput * attenuation;
+
+            // Russian roulette: end paths that can only carry a little light at random, and
+            // boost the ones that survive to make up for it
+            if bounces.total >= self.limits.roulette_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+                if survival <= 0.0 || random() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            bsdf_pdf = rec
+                .material
+                .pdf(&ray, &rec, scattered.direction.unit_vector());
+            normal = rec.normal;
+            ray = scattered;
+        }
+
+        CameraSample {
+            lighting,
+            hit: first_hit,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn sample(&self, ray: &Ray, scene: &Scene, _camera: &Camera, _film: &Film) -> CameraSample {
+        self.trace(ray, scene)
+    }
+}
+
+// The light a path found, split into what the surface or environment it first hit gives off,
+// what reaches that surface straight from a light and is scattered once toward the camera, and
+// what scatters more than once
+#[derive(Debug, Clone, Copy)]
+pub struct Lighting {
+    pub emission: Color,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl Lighting {
+    pub fn new() -> Lighting {
+        let black = Color::new(0.0, 0.0, 0.0);
+        Lighting {
+            emission: black,
+            direct: black,
+            indirect: black,
+        }
+    }
+
+    pub fn total(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+
+    // Each part with f applied to it, such as a conversion to RGB or a scale
+    pub fn map(&self, f: impl Fn(Color) -> Color) -> Lighting {
+        Lighting {
+            emission: f(self.emission),
+            direct: f(self.direct),
+            indirect: f(self.indirect),
+        }
+    }
+
+    // Adds light that scattered `bounces` times
+    pub fn add(&mut self, bounces: u32, color: Color) {
+        match bounces {
+            0 => self.emission += color,
+            1 => self.direct += color,
+            _ => self.indirect += color,
+        }
+    }
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting::new()
+    }
+}
+
+// Path tracing with caustics from a photon map (Jensen 1996). Photons are traced from the lights
+// through glass, mirrors and other specular surfaces and stored where they land on diffuse ones.
+// Paths from the camera gather them at every diffuse hit, and so skip light they find by hitting a
+// light through specular bounces after a diffuse one, which the photons already carry. This makes
+// light focused through glass smooth instead of speckled, and finds it for lights rays can't hit.
+//
+// The photons are traced afresh for every render, before any paths from the camera.
+pub struct PhotonMapper {
+    limits: DepthLimits,
+    photons: usize,
+    gather_count: usize,
+    gather_radius: Option<f64>,
+}
+
+impl PhotonMapper {
+    // Traces `photons` photons, and by default gathers the nearest 50 within 2% of the scene's
+    // bounding radius
+    pub fn new(limits: DepthLimits, photons: usize) -> PhotonMapper {
+        PhotonMapper {
+            limits,
+            photons,
+            gather_count: 50,
+            gather_radius: None,
+        }
+    }
+
+    // Estimates caustics from the nearest `count` photons, searching no further than `radius`.
+    // More photons blur the caustics less and a smaller radius keeps their edges sharper, at the
+    // cost of noise.
+    pub fn with_gather(mut self, count: usize, radius: f64) -> PhotonMapper {
+        self.gather_count = count;
+        self.gather_radius = Some(radius);
+        self
+    }
+
+    // Traces a path like the path tracer, gathering caustics from map if there is one
+    fn trace(&self, ray: &Ray, scene: &Scene, map: Option<&PhotonMap>) -> CameraSample {
+        let radius = self
+            .gather_radius
+            .unwrap_or_else(|| 0.02 * bounding_sphere(&scene.world.bounding_box()).1);
+
+        let mut lighting = Lighting::new();
+        let mut first_hit = None;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bounces = BounceCounts::default();
+
+        // As in the path tracer, light found by hitting it is weighted against light sampling
+        let mut bsdf_pdf = 0.0;
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        let mis = |light_pdf: f64, bsdf_pdf: f64| {
+            if bsdf_pdf > 0.0 {
+                power_heuristic(bsdf_pdf, light_pdf)
+            } else {
+                1.0
+            }
+        };
+        // Whether the path has been specular since its last diffuse hit, so lights it hits now
+        // were already gathered there as caustics
+        let mut diffuse = false;
+        let mut caustic = false;
+
+        for depth in 0..self.limits.max {
+            let mut rec = HitRecord::new();
+            if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                let direction = ray.direction.unit_vector();
+                let weight = mis(scene.environment.pdf(direction), bsdf_pdf);
+                lighting.add(
+                    depth,
+                    throughput * ray.radiance(scene.environment.radiance(direction)) * weight,
+                );
+                break;
+            }
+
+            let mut emitted = rec.material.emitted(&ray, &rec);
+            if let Some(light) = rec.light {
+                let direction = ray.direction.unit_vector();
+                let light_pdf = scene.light_pdf(ray.origin, normal, light, direction);
+                let weight = if caustic {
+                    0.0
+                } else {
+                    mis(light_pdf, bsdf_pdf)
+                };
+                emitted = emitted * weight;
+            }
+            lighting.add(depth, throughput * ray.radiance(emitted));
+            lighting.add(
+                depth + 1,
+                throughput * direct_light(&ray, scene, &rec, Sharing::Mis),
+            );
+            if depth == 0 {
+                first_hit = Some(rec.clone());
+            }
+
+            let Some((scattered, attenuation)) = scatter(&ray, &rec) else {
+                break;
+            };
+            bsdf_pdf = rec
+                .material
+                .pdf(&ray, &rec, scattered.direction.unit_vector());
+            if let Some(map) = map {
+                if bsdf_pdf > 0.0 {
+                    // Caustic light scattered at least once before reaching here, and again here
+                    lighting.add(
+                        depth + 2,
+                        throughput * map.radiance(&ray, &rec, self.gather_count, radius),
+                    );
+                    diffuse = true;
+                    caustic = false;
+                } else {
+                    caustic = diffuse;
+                }
+            }
+
+            if !bounces.add(rec.material.bounce(&ray, &rec, &scattered), &self.limits) {
+                break;
+            }
+            throughput = throughput * attenuation;
+
+            if bounces.total >= self.limits.roulette_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+                if survival <= 0.0 || random() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            normal = rec.normal;
+            ray = scattered;
+        }
+
+        CameraSample {
+            lighting,
+            hit: first_hit,
+        }
+    }
+}
+
+impl Integrator for PhotonMapper {
+    // Single samples, outside of render, have no photons to gather
+    fn sample(&self, ray: &Ray, scene: &Scene, _camera: &Camera, _film: &Film) -> CameraSample {
+        self.trace(ray, scene, None)
+    }
+
+    fn render(&self, scene: &Scene, camera: &Camera, film: &Film) -> f64 {
+        let map = PhotonMap::trace_caustics(scene, self.photons, self.limits.max, camera.spectral);
+        camera.render_samples(film, |ray| self.trace(ray, scene, Some(&map)))
+    }
+}
+
+// Path tracing guided by a learned distribution of where light comes from (Müller et al. 2017),
+// for scenes lit indirectly, like a room lit through a doorway, where the BSDF rarely sends paths
+// toward the light. The image is rendered in training passes of 1, 2, 4 and so on samples per
+// pixel, each recording the light its paths find into a GuidingTree, which is refined after every
+// pass. A final pass with the camera's samples per pixel then uses what they learned, and only it
+// is kept.
+//
+// At surfaces with a BSDF density, each direction is sampled from either the material or the
+// tree, picked at random, and weighted by the density of the mix of the two (one-sample multiple
+// importance sampling), which light samples are weighted against too. Paths are only guided at
+// such surfaces; glass, mirrors and the like are always followed by sampling the material.
+pub struct GuidedPathTracer {
+    limits: DepthLimits,
+    training_passes: u32,
+    spatial_threshold: f64,
+    bsdf_fraction: f64,
+}
+
+// A point where a path was guided, for recording the light it found from there on
+struct GuideVertex {
+    p: Vec3,
+    direction: Vec3,
+    // The path's throughput up to and including the bounce here
+    throughput: Color,
+    // Light the path had already found before leaving
+    radiance: Color,
+    pdf: f64,
+}
+
+impl GuidedPathTracer {
+    // Trains for 5 passes, splitting the tree where a leaf recorded more than 12000 samples times
+    // the square root of the pass's samples per pixel, and samples the material half the time
+    pub fn new(limits: DepthLimits) -> GuidedPathTracer {
+        GuidedPathTracer {
+            limits,
+            training_passes: 5,
+            spatial_threshold: 12000.0,
+            bsdf_fraction: 0.5,
+        }
+    }
+
+    pub fn with_training(mut self, passes: u32, spatial_threshold: f64) -> GuidedPathTracer {
+        self.training_passes = passes;
+        self.spatial_threshold = spatial_threshold;
+        self
+    }
+
+    // The chance of sampling the material rather than the tree where paths are guided
+    pub fn with_bsdf_fraction(mut self, fraction: f64) -> GuidedPathTracer {
+        self.bsdf_fraction = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    // Traces a path like the path tracer, guided by tree if there is one. When learning, the
+    // light found after each guided bounce is recorded into the tree.
+    fn trace(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        tree: Option<&GuidingTree>,
+        learn: bool,
+    ) -> CameraSample {
+        let mut lighting = Lighting::new();
+        let mut first_hit = None;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bounces = BounceCounts::default();
+        let mut vertices = Vec::new();
+
+        // As in the path tracer, light found by hitting it is weighted against light sampling,
+        // here by the density with which the previous bounce was sampled from the mix
+        let mut scatter_pdf = 0.0;
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        let mis = |light_pdf: f64, scatter_pdf: f64| {
+            if scatter_pdf > 0.0 {
+                power_heuristic(scatter_pdf, light_pdf)
+            } else {
+                1.0
+            }
+        };
+
+        for depth in 0..self.limits.max {
+            let mut rec = HitRecord::new();
+            if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                let direction = ray.direction.unit_vector();
+                let weight = mis(scene.environment.pdf(direction), scatter_pdf);
+                lighting.add(
+                    depth,
+                    throughput * ray.radiance(scene.environment.radiance(direction)) * weight,
+                );
+                break;
+            }
+
+            let mut emitted = rec.material.emitted(&ray, &rec);
+            if let Some(light) = rec.light {
+                let direction = ray.direction.unit_vector();
+                let light_pdf = scene.light_pdf(ray.origin, normal, light, direction);
+                emitted = emitted * mis(light_pdf, scatter_pdf);
+            }
+            lighting.add(depth, throughput * ray.radiance(emitted));
+            if depth == 0 {
+                first_hit = Some(rec.clone());
+            }
+
+            // Sample the material first, to see whether it has a density to mix the tree with
+            let sampled = scatter(&ray, &rec);
+            let material_pdf = |direction: Vec3| rec.material.pdf(&ray, &rec, direction);
+            let guide = tree.filter(|_| {
+                sampled.as_ref().is_some_and(|(scattered, _)| {
+                    material_pdf(scattered.direction.unit_vector()) > 0.0
+                })
+            });
+            let mix_pdf = |direction: Vec3| match guide {
+                Some(tree) => {
+                    self.bsdf_fraction * material_pdf(direction)
+                        + (1.0 - self.bsdf_fraction) * tree.pdf(rec.p, direction)
+                }
+                None => material_pdf(direction),
+            };
+            lighting.add(
+                depth + 1,
+                throughput
+                    * weighted_direct_light(&ray, scene, &rec, |light_pdf, direction| {
+                        power_heuristic(light_pdf, mix_pdf(direction))
+                    }),
+            );
+
+            let Some((mut scattered, mut attenuation)) = sampled else {
+                break;
+            };
+            let mut pdf = material_pdf(scattered.direction.unit_vector());
+            if let Some(tree) = guide {
+                if random() >= self.bsdf_fraction {
+                    let direction = tree.sample(rec.p);
+                    scattered = Ray::new(rec.p, direction);
+                    scattered.wavelengths = ray.wavelengths;
+                    let f = rec.material.eval(&ray, &rec, direction);
+                    let mix = mix_pdf(direction);
+                    if mix <= 0.0 {
+                        break;
+                    }
+                    attenuation = ray.reflectance(&*rec.material, f) / mix;
+                    pdf = mix;
+                } else {
+                    let mix = mix_pdf(scattered.direction.unit_vector());
+                    attenuation = attenuation * (pdf / mix);
+                    pdf = mix;
+                }
+            }
+
+            if !bounces.add(rec.material.bounce(&ray, &rec, &scattered), &self.limits) {
+                break;
+            }
+            throughput = throughput * attenuation;
+            if learn && guide.is_some() {
+                vertices.push(GuideVertex {
+                    p: rec.p,
+                    direction: scattered.direction.unit_vector(),
+                    throughput,
+                    radiance: lighting.total(),
+                    pdf,
+                });
+            }
+
+            if bounces.total >= self.limits.roulette_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+                if survival <= 0.0 || random() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            scatter_pdf = pdf;
+            normal = rec.normal;
+            ray = scattered;
+        }
+
+        // The light found after each guided bounce, divided by the throughput up to there, is an
+        // estimate of the light arriving there from the direction the path left in
+        if let Some(tree) = tree.filter(|_| learn) {
+            let radiance = lighting.total();
+            let ratio = |found: f64, throughput: f64| {
+                if throughput > 0.0 {
+                    found / throughput
+                } else {
+                    0.0
+                }
+            };
+            for vertex in vertices {
+                let found = radiance - vertex.radiance;
+                let incident = (ratio(found.r, vertex.throughput.r)
+                    + ratio(found.g, vertex.throughput.g)
+                    + ratio(found.b, vertex.throughput.b))
+                    / 3.0;
+                tree.record(vertex.p, vertex.direction, incident / vertex.pdf);
+            }
+        }
+
+        CameraSample {
+            lighting,
+            hit: first_hit,
+        }
+    }
+}
+
+impl Integrator for GuidedPathTracer {
+    // Single samples, outside of render, aren't guided
+    fn sample(&self, ray: &Ray, scene: &Scene, _camera: &Camera, _film: &Film) -> CameraSample {
+        self.trace(ray, scene, None, false)
+    }
+
+    fn render(&self, scene: &Scene, camera: &Camera, film: &Film) -> f64 {
+        let mut tree = GuidingTree::new(&scene.world.bounding_box());
+        for pass in 0..self.training_passes {
+            let samples = 1 << pass;
+            let training = Film::new(film.width(), film.height());
+            camera.render_pixels(&training, samples, |ray| {
+                self.trace(ray, scene, Some(&tree), true)
+            });
+            tree.refine(self.spatial_threshold * (samples as f64).sqrt());
+        }
+        camera.render_samples(film, |ray| self.trace(ray, scene, Some(&tree), false))
+    }
+}
+
+// Whitted-style ray tracing: direct lighting at every hit, plus perfect reflection and refraction.
+// Paths only continue through materials that scatter in discrete directions or have no closed
+// form BSDF, like glass, mirrors and coatings; everything else is lit by the lights alone.
+pub struct Whitted {
+    max_depth: u32,
+}
+
+impl Whitted {
+    pub fn new(max_depth: u32) -> Whitted {
+        Whitted { max_depth }
+    }
+}
+
+impl Integrator for Whitted {
+    fn sample(&self, ray: &Ray, scene: &Scene, _camera: &Camera, _film: &Film) -> CameraSample {
+        let mut lighting = Lighting::new();
+        let mut first_hit = None;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..self.max_depth {
+            let mut rec = HitRecord::new();
+            if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                lighting.add(
+                    depth,
+                    throughput
+                        * ray.radiance(scene.environment.radiance(ray.direction.unit_vector())),
+                );
+                break;
+            }
+
+            lighting.add(
+                depth,
+                throughput * ray.radiance(rec.material.emitted(&ray, &rec)),
+            );
+            lighting.add(
+                depth + 1,
+                throughput * direct_light(&ray, scene, &rec, Sharing::LightsOnly),
+            );
+            if depth == 0 {
+                first_hit = Some(rec.clone());
+            }
+
+            let Some((scattered, attenuation)) = scatter(&ray, &rec) else {
+                break;
+            };
+            if rec
+                .material
+                .pdf(&ray, &rec, scattered.direction.unit_vector())
+                > 0.0
+            {
+                break;
+            }
+            throughput = throughput * attenuation;
+            ray = scattered;
+        }
+
+        CameraSample {
+            lighting,
+            hit: first_hit,
+        }
+    }
+}
+
+// The fraction of the hemisphere above each visible point that is open within `distance`,
+// weighted by the cosine to the normal. Creases and corners come out darker.
+pub struct AmbientOcclusion {
+    distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: f64) -> AmbientOcclusion {
+        AmbientOcclusion { distance }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn sample(&self, ray: &Ray, scene: &Scene, _camera: &Camera, _film: &Film) -> CameraSample {
+        let mut rec = HitRecord::new();
+        if !scene.hit(ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return CameraSample {
+                lighting: Lighting::new(),
+                hit: None,
+            };
+        }
+
+        // Sampling in proportion to the cosine leaves just the visibility as the estimate
+        let direction = Onb::new(rec.normal)
+            .local(Vec3::random_cosine_direction())
+            .unit_vector();
+        // The open sky counts as light scattered once toward the camera
+        let mut lighting = Lighting::new();
+        if !scene.occluded(rec.p, direction, self.distance) {
+            lighting.add(1, ray.radiance(Color::new(1.0, 1.0, 1.0)));
+        }
+        CameraSample {
+            lighting,
+            hit: Some(rec),
+        }
+    }
+}
+
+// Samples how the ray scatters off the hit, returning the scattered ray and its attenuation at
+// the ray's wavelengths
+pub fn scatter(ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    let mut attenuation = Color::new(0.0, 0.0, 0.0);
+    if !rec
+        .material
+        .scatter(ray, rec, &mut attenuation, &mut scattered)
+    {
+        return None;
+    }
+
+    // A scattered ray inherits the path's wavelengths unless the material has changed them
+    let mut attenuation = ray.reflectance(&*rec.material, attenuation);
+    if let Some(wavelengths) = ray.wavelengths {
+        match scattered.wavelengths {
+            None => scattered.wavelengths = Some(wavelengths),
+            Some(next) if next.secondary_terminated && !wavelengths.secondary_terminated => {
+                attenuation = attenuation * SampledWavelengths::termination_weight();
+            }
+            Some(_) => {}
+        }
+    }
+    Some((scattered, attenuation))
+}
+
+// Light from the scene's lights reflected at the hit point back along the ray, found by picking
+// one light with the scene's light sampler, sampling it and tracing a shadow ray toward it.
+// Environments that can be sampled are gathered the same way. `sharing` says how lights that
+// rays can also hit are counted.
+fn direct_light(ray: &Ray, scene: &Scene, rec: &HitRecord, sharing: Sharing) -> Color {
+    weighted_direct_light(ray, scene, rec, |light_pdf, direction| match sharing {
+        Sharing::Mis => power_heuristic(light_pdf, rec.material.pdf(ray, rec, direction)),
+        Sharing::LightsOnly => 1.0,
+        Sharing::BsdfOnly => 0.0,
+    })
+}
+
+// Direct lighting with the weight given to a light sample of density light_pdf in a direction
+// that could also have been found by hitting the light
+fn weighted_direct_light(
+    ray: &Ray,
+    scene: &Scene,
+    rec: &HitRecord,
+    weight: impl Fn(f64, Vec3) -> f64,
+) -> Color {
+    let mut total = Color::new(0.0, 0.0, 0.0);
+    let picked = scene.light_sampler().sample(rec.p, rec.normal, random());
+    if let Some((index, probability)) = picked {
+        let light = &scene.lights[index];
+        if let Some(sample) = light.sample(rec.p).filter(|sample| sample.pdf > 0.0) {
+            let f = rec.material.eval(ray, rec, sample.direction);
+            if f.average() > 0.0 && !scene.occluded(rec.p, sample.direction, sample.distance) {
+                let light_pdf = probability * sample.pdf;
+                let weight = if light.pdf(rec.p, sample.direction) > 0.0 {
+                    weight(light_pdf, sample.direction)
+                } else {
+                    1.0
+                };
+                total += ray.reflectance(&*rec.material, f)
+                    * ray.radiance(sample.radiance)
+                    * (weight / light_pdf);
+            }
+        }
+    }
+
+    if let Some((direction, pdf)) = scene.environment.sample() {
+        let f = rec.material.eval(ray, rec, direction);
+        if f.average() > 0.0 && !scene.occluded(rec.p, direction, f64::INFINITY) {
+            total += ray.reflectance(&*rec.material, f)
+                * ray.radiance(scene.environment.radiance(direction))
+                * (weight(pdf, direction) / pdf);
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        disk::Disk,
+        hit::Hittables,
+        light::{DirectionalLight, Light, PointLight},
+        material::{Coated, Lambertian, Material},
+        sphere::Sphere,
+        subsurface::Subsurface,
+        test_util::grey,
+    };
+
+    // Direct light from the light reflected by whatever the ray sees first in the world
+    fn lit(object: Box<dyn Hittable>, light: Box<dyn Light>, ray: &Ray) -> Color {
+        let mut world = Hittables::new();
+        world.add(object);
+        let mut scene = Scene::new(world);
+        scene.add_light(light);
+
+        let mut rec = HitRecord::new();
+        assert!(scene.hit(ray, Interval::new(0.001, f64::INFINITY), &mut rec));
+        direct_light(ray, &scene, &rec, Sharing::Mis)
+    }
+
+    // Direct light from a point light above a disk of the given material at the origin
+    fn point_lit(material: Arc<dyn Material>) -> Color {
+        let disk = Disk::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            material,
+        );
+        let light = PointLight::new(Vec3::new(0.0, 0.0, 2.0), Color::new(4.0, 4.0, 4.0));
+        let ray = Ray::new(Vec3::new(0.6, 0.2, 2.0), Vec3::new(-0.3, -0.1, -1.0));
+        lit(Box::new(disk), Box::new(light), &ray)
+    }
+
+    #[test]
+    fn coated_surfaces_are_lit_by_point_lights() {
+        let base: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+        let coated = Arc::new(Coated::new(
+            Arc::clone(&base),
+            1.5,
+            0.3,
+            0.0,
+            Color::new(0.0, 0.0, 0.0),
+        ));
+
+        // Away from the coat's highlight, a clear coat only dims the base a little
+        let bare = point_lit(base).average();
+        let lit = point_lit(coated).average();
+        assert!(
+            lit > 0.5 * bare && lit < bare,
+            "coated {} against bare {}",
+            lit,
+            bare
+        );
+    }
+
+    #[test]
+    fn subsurface_objects_are_lit_by_directional_lights() {
+        let sphere = || Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, grey());
+        let light = || DirectionalLight::new(Vec3::new(0.0, 0.0, -1.0), Color::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(Vec3::new(0.3, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let subsurface = Subsurface::new(
+            Arc::new(sphere()),
+            1.3,
+            Color::new(0.01, 0.02, 0.05),
+            Color::new(0.99, 0.9, 0.8),
+        );
+        let lit_subsurface = lit(Box::new(subsurface), Box::new(light()), &ray);
+
+        // No brighter than a white diffuse surface, less what the boundary reflects away
+        let white = Lambertian::new(Color::new(1.0, 1.0, 1.0));
+        let white = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Arc::new(white));
+        let lit_white = lit(Box::new(white), Box::new(light()), &ray);
+        for (subsurface, white) in [
+            (lit_subsurface.r, lit_white.r),
+            (lit_subsurface.g, lit_white.g),
+            (lit_subsurface.b, lit_white.b),
+        ] {
+            assert!(
+                subsurface > 0.0 && subsurface < white,
+                "{:?}",
+                lit_subsurface
+            );
+        }
+    }
+}
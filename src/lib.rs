@@ -0,0 +1,44 @@
+pub mod aabb;
+pub mod adaptive;
+pub mod aov;
+pub mod bdpt;
+pub mod camera;
+pub mod cone;
+pub mod csg;
+pub mod cylinder;
+pub mod denoise;
+pub mod disk;
+pub mod distribution;
+pub mod environment;
+pub mod film;
+pub mod guiding;
+pub mod hit;
+pub mod integrator;
+pub mod light;
+pub mod light_sampler;
+pub mod material;
+pub mod microfacet;
+pub mod mlt;
+pub mod normal_map;
+pub mod onb;
+pub mod photon_map;
+pub mod plane;
+pub mod poly;
+pub mod principled;
+pub mod quad;
+pub mod ray;
+pub mod sampler;
+pub mod scene;
+pub mod sdf;
+pub mod sky;
+pub mod spectrum;
+pub mod sphere;
+pub mod subsurface;
+#[cfg(test)]
+mod test_util;
+pub mod texture;
+pub mod thin_film;
+pub mod torus;
+pub mod triangle;
+pub mod util;
+pub mod vec3;
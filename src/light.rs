@@ -0,0 +1,618 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    light_sampler::LightBounds,
+    material::DiffuseLight,
+    onb::Onb,
+    quad::Quad,
+    ray::Ray,
+    sampler::random,
+    util::{degrees_to_radians, Interval, PI},
+    vec3::{Color, Vec3},
+};
+
+type Point3 = Vec3;
+
+// Light arriving at a point from one sampled direction
+pub struct LightSample {
+    // Unit vector from the point toward the light
+    pub direction: Vec3,
+    // Distance to the light along direction, or infinity for lights that are infinitely far
+    pub distance: f64,
+    // Radiance arriving along direction. For lights that only shine from a single direction this
+    // is the irradiance they deliver instead.
+    pub radiance: Color,
+    // Density of sampling direction over solid angle, or 1 for lights that only shine from a
+    // single direction
+    pub pdf: f64,
+    // Normal of the light's surface at the sampled point, or zero for lights that aren't surfaces
+    pub normal: Vec3,
+}
+
+// Light leaving a light along one sampled ray, for tracing paths from the lights
+pub struct EmissionSample {
+    // Starts on the light and leaves along a unit vector. Lights infinitely far away start it on a
+    // disk facing the scene, just outside its bounds.
+    pub ray: Ray,
+    // Normal of the light's surface at the ray's origin, or zero for lights that aren't surfaces
+    pub normal: Vec3,
+    // Radiance carried along the ray. For lights at a single point this is their intensity, and
+    // for lights that shine from a single direction their irradiance.
+    pub radiance: Color,
+    // Density of the ray's origin over the light's area, or over the disk for lights infinitely
+    // far away, and of its direction over solid angle. Either is 1 for lights that have no choice.
+    pub pdf_position: f64,
+    pub pdf_direction: f64,
+}
+
+// Lights gathered by sampling them from each point being shaded and tracing a shadow ray. Most
+// aren't part of the scene's geometry, so rays can never hit them. Area lights are added to the
+// geometry too, and light found by hitting them is weighted against sampling them.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+
+    // Luminance of the total power given off, for picking lights in proportion to it. Lights that
+    // are infinitely far away give the power arriving per unit area instead.
+    fn power(&self) -> f64;
+
+    // Where the light is and which way it shines, or None for lights that are infinitely far away
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    // Density over solid angle with which sample picks the unit vector direction from p. Lights
+    // that rays can't hit return 0.
+    fn pdf(&self, _p: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    // Picks a ray leaving the light. scene_bounds places the disk that lights infinitely far away
+    // shine from.
+    fn sample_emission(&self, scene_bounds: &Aabb) -> Option<EmissionSample>;
+
+    // Densities with which sample_emission picks a ray from origin along the unit vector
+    // direction, as (position, direction)
+    fn emission_pdf(&self, origin: Point3, direction: Vec3, scene_bounds: &Aabb) -> (f64, f64);
+}
+
+// Center and radius of a sphere around the finite part of the scene's bounds, which lights
+// infinitely far away shine on. Unbounded axes, such as along an infinite plane, are left out.
+pub fn bounding_sphere(bounds: &Aabb) -> (Point3, f64) {
+    let axis = |interval: Interval| {
+        if interval.size().is_finite() && interval.size() >= 0.0 {
+            (0.5 * (interval.min + interval.max), interval.size())
+        } else {
+            (0.0, 0.0)
+        }
+    };
+    let (x, y, z) = (axis(bounds.x), axis(bounds.y), axis(bounds.z));
+    let center = Point3::new(x.0, y.0, z.0);
+    (center, 0.5 * Vec3::new(x.1, y.1, z.1).length())
+}
+
+// A uniformly distributed unit vector within cos_max of the z axis
+fn uniform_cone(cos_max: f64) -> Vec3 {
+    let cos_theta = 1.0 - random() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
+// Shines equally in all directions from a single point, falling off with the square of the
+// distance. Intensity is the power per unit solid angle.
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let offset = self.position - p;
+        let distance_squared = offset.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            direction: offset / distance,
+            distance,
+            radiance: self.intensity * (1.0 / distance_squared),
+            pdf: 1.0,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+        })
+    }
+
+    fn power(&self) -> f64 {
+        4.0 * PI * self.intensity.luminance()
+    }
+
+    fn sample_emission(&self, _scene_bounds: &Aabb) -> Option<EmissionSample> {
+        Some(EmissionSample {
+            ray: Ray::new(self.position, Vec3::random_unit_vector()),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            radiance: self.intensity,
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn emission_pdf(&self, _origin: Point3, _direction: Vec3, _scene_bounds: &Aabb) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.position, self.position),
+            w: Vec3::new(0.0, 0.0, 1.0),
+            phi: self.power(),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+// A point light restricted to a cone around `direction`. It shines at full intensity out to
+// `inner_angle` degrees from the axis, then falls off smoothly to nothing at `outer_angle`.
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> SpotLight {
+        let outer_angle = outer_angle.max(inner_angle);
+        SpotLight {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_inner: degrees_to_radians(inner_angle).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let offset = self.position - p;
+        let distance_squared = offset.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+
+        let distance = distance_squared.sqrt();
+        let direction = offset / distance;
+        let falloff = self.falloff((-direction).dot(self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * (falloff / distance_squared),
+            pdf: 1.0,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+        })
+    }
+
+    fn power(&self) -> f64 {
+        // The smooth falloff gives off about as much as a cone halfway between the two angles
+        2.0 * PI * (1.0 - 0.5 * (self.cos_inner + self.cos_outer)) * self.intensity.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let theta_e = self.cos_outer.acos() - self.cos_inner.acos();
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.position, self.position),
+            w: self.direction,
+            phi: 4.0 * PI * self.intensity.luminance(),
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: theta_e.cos(),
+            two_sided: false,
+        })
+    }
+
+    fn sample_emission(&self, _scene_bounds: &Aabb) -> Option<EmissionSample> {
+        // Directions are spread uniformly over the outer cone, weighted by the falloff
+        let local = uniform_cone(self.cos_outer);
+        let falloff = self.falloff(local.z);
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(EmissionSample {
+            ray: Ray::new(
+                self.position,
+                Onb::new(self.direction).local(local).unit_vector(),
+            ),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            radiance: self.intensity * falloff,
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (2.0 * PI * (1.0 - self.cos_outer)),
+        })
+    }
+
+    fn emission_pdf(&self, _origin: Point3, direction: Vec3, _scene_bounds: &Aabb) -> (f64, f64) {
+        if direction.dot(self.direction) < self.cos_outer {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / (2.0 * PI * (1.0 - self.cos_outer)))
+    }
+}
+
+// Light from infinitely far away travelling along `direction`, like sunlight. Irradiance is the
+// power per unit area arriving on a surface facing the light.
+//
+// By default it shines from a single direction and casts perfectly sharp shadows. Giving it an
+// angular size spreads it over a disk in the sky, which softens the shadows.
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+    cos_max: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.unit_vector(),
+            irradiance,
+            cos_max: 1.0,
+        }
+    }
+
+    // The sun, which is about half a degree across as seen from the earth
+    pub fn sun(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight::new(direction, irradiance).with_angular_diameter(0.53)
+    }
+
+    pub fn with_angular_diameter(mut self, degrees: f64) -> DirectionalLight {
+        self.cos_max = degrees_to_radians(0.5 * degrees).cos();
+        self
+    }
+
+    fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_max)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        let toward_light = -self.direction;
+        if self.cos_max >= 1.0 {
+            return Some(LightSample {
+                direction: toward_light,
+                distance: f64::INFINITY,
+                radiance: self.irradiance,
+                pdf: 1.0,
+                normal: Vec3::new(0.0, 0.0, 0.0),
+            });
+        }
+
+        // Sample the disk uniformly over solid angle, spreading the irradiance evenly across it
+        let solid_angle = self.solid_angle();
+        Some(LightSample {
+            direction: Onb::new(toward_light)
+                .local(uniform_cone(self.cos_max))
+                .unit_vector(),
+            distance: f64::INFINITY,
+            radiance: self.irradiance * (1.0 / solid_angle),
+            pdf: 1.0 / solid_angle,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+        })
+    }
+
+    fn power(&self) -> f64 {
+        self.irradiance.luminance()
+    }
+
+    fn sample_emission(&self, scene_bounds: &Aabb) -> Option<EmissionSample> {
+        let (center, radius) = bounding_sphere(scene_bounds);
+        if radius <= 0.0 {
+            return None;
+        }
+
+        let (direction, radiance, pdf_direction) = if self.cos_max >= 1.0 {
+            (self.direction, self.irradiance, 1.0)
+        } else {
+            let solid_angle = self.solid_angle();
+            let direction = Onb::new(self.direction)
+                .local(uniform_cone(self.cos_max))
+                .unit_vector();
+            (
+                direction,
+                self.irradiance * (1.0 / solid_angle),
+                1.0 / solid_angle,
+            )
+        };
+
+        // A uniform point on the disk that covers the scene as seen along direction
+        let frame = Onb::new(direction);
+        let r = radius * random().sqrt();
+        let phi = 2.0 * PI * random();
+        let origin =
+            center - direction * radius + frame.local(Vec3::new(r * phi.cos(), r * phi.sin(), 0.0));
+
+        Some(EmissionSample {
+            ray: Ray::new(origin, direction),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            radiance,
+            pdf_position: 1.0 / (PI * radius * radius),
+            pdf_direction,
+        })
+    }
+
+    fn emission_pdf(&self, _origin: Point3, direction: Vec3, scene_bounds: &Aabb) -> (f64, f64) {
+        let (_, radius) = bounding_sphere(scene_bounds);
+        let pdf_position = if radius > 0.0 {
+            1.0 / (PI * radius * radius)
+        } else {
+            0.0
+        };
+        let pdf_direction = if self.cos_max >= 1.0 {
+            1.0
+        } else if direction.dot(self.direction) >= self.cos_max {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        };
+        (pdf_position, pdf_direction)
+    }
+}
+
+// A one-sided quad that gives off `emit` from its front face, which is the side a counterclockwise
+// a, b, c, d faces. Add it with Scene::add_area_light, which also adds its shape to the geometry.
+pub struct QuadLight {
+    a: Point3,
+    b: Point3,
+    c: Point3,
+    d: Point3,
+    normal: Vec3,
+    emit: Color,
+    area: f64,
+    // Share of the area in the triangle a, b, c, with the rest in a, c, d
+    first_share: f64,
+    // The quad as geometry, built once since every MIS weight intersects it
+    shape: Quad,
+}
+
+impl QuadLight {
+    pub fn new(a: Point3, b: Point3, c: Point3, d: Point3, emit: Color) -> QuadLight {
+        let first = 0.5 * (b - a).cross(c - a).length();
+        let second = 0.5 * (c - a).cross(d - a).length();
+        let area = first + second;
+        QuadLight {
+            a,
+            b,
+            c,
+            d,
+            normal: (b - a).cross(c - a).unit_vector(),
+            emit,
+            area,
+            first_share: if area > 0.0 { first / area } else { 1.0 },
+            shape: Quad::new(a, b, c, d, Arc::new(DiffuseLight::new(emit))),
+        }
+    }
+
+    // The quad as geometry, for rays to hit
+    pub fn shape(&self) -> &Quad {
+        &self.shape
+    }
+
+    // Density over solid angle of sampling the point q, seen from p along the unit vector
+    // direction
+    fn solid_angle_pdf(&self, p: Point3, q: Point3, direction: Vec3) -> f64 {
+        let cos_light = -direction.dot(self.normal);
+        if cos_light <= 0.0 || self.area <= 0.0 {
+            return 0.0;
+        }
+        (q - p).length_squared() / (cos_light * self.area)
+    }
+
+    // A uniformly distributed point on the quad
+    fn sample_point(&self) -> Point3 {
+        // Pick one of the two triangles by area, then a uniform point within it
+        let (a, b, c) = if random() < self.first_share {
+            (self.a, self.b, self.c)
+        } else {
+            (self.a, self.c, self.d)
+        };
+        let mut u1 = random();
+        let mut u2 = random();
+        if u1 + u2 > 1.0 {
+            (u1, u2) = (1.0 - u1, 1.0 - u2);
+        }
+        a + (b - a) * u1 + (c - a) * u2
+    }
+}
+
+impl Light for QuadLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let q = self.sample_point();
+
+        let offset = q - p;
+        let distance = offset.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let direction = offset / distance;
+        let pdf = self.solid_angle_pdf(p, q, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emit,
+            pdf,
+            normal: self.normal,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        PI * self.area * self.emit.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::surrounding(
+                &Aabb::from_points(self.a, self.c),
+                &Aabb::from_points(self.b, self.d),
+            ),
+            w: self.normal,
+            phi: self.area * self.emit.luminance(),
+            cos_theta_o: 1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+
+    fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        let ray = Ray::new(p, direction);
+        if !self
+            .shape
+            .hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rec)
+        {
+            return 0.0;
+        }
+        self.solid_angle_pdf(p, rec.p, direction)
+    }
+
+    fn sample_emission(&self, _scene_bounds: &Aabb) -> Option<EmissionSample> {
+        if self.area <= 0.0 {
+            return None;
+        }
+
+        // Emitted radiance is the same in every direction, so directions are picked in proportion
+        // to the cosine to the normal
+        let local = Vec3::random_cosine_direction();
+        Some(EmissionSample {
+            ray: Ray::new(
+                self.sample_point(),
+                Onb::new(self.normal).local(local).unit_vector(),
+            ),
+            normal: self.normal,
+            radiance: self.emit,
+            pdf_position: 1.0 / self.area,
+            pdf_direction: local.z / PI,
+        })
+    }
+
+    fn emission_pdf(&self, _origin: Point3, direction: Vec3, _scene_bounds: &Aabb) -> (f64, f64) {
+        if self.area <= 0.0 {
+            return (0.0, 0.0);
+        }
+        (1.0 / self.area, direction.dot(self.normal).max(0.0) / PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_near;
+
+    #[test]
+    fn point_light_falls_off_with_inverse_square() {
+        let light = PointLight::new(Vec3::new(0.0, 3.0, 0.0), Color::new(8.0, 4.0, 2.0));
+        let near = light.sample(Vec3::new(0.0, 2.0, 0.0)).unwrap();
+        let far = light.sample(Vec3::new(0.0, 1.0, 0.0)).unwrap();
+
+        assert_near(near.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!((near.distance, near.pdf), (1.0, 1.0));
+        assert_eq!((near.radiance.r, near.radiance.g), (8.0, 4.0));
+        assert_eq!((far.radiance.r, far.radiance.b), (2.0, 0.5));
+    }
+
+    #[test]
+    fn spot_light_fades_smoothly_between_cones() {
+        let light = SpotLight::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            20.0,
+            40.0,
+        );
+        // The radiance one unit away at the given angle from the spot's axis
+        let at = |degrees: f64| {
+            let theta = degrees_to_radians(degrees);
+            light
+                .sample(Vec3::new(theta.sin(), -theta.cos(), 0.0))
+                .map_or(0.0, |sample| sample.radiance.r)
+        };
+
+        assert!((at(0.0) - 1.0).abs() < 1e-9 && (at(19.9) - 1.0).abs() < 1e-9);
+        assert_eq!(at(40.1), 0.0);
+        assert!(light.sample(Vec3::new(0.0, 1.0, 0.0)).is_none());
+
+        // Smoothstep in the cosine: continuous and flat at both edges, monotonic in between
+        assert!(at(20.1) > 0.999 && at(39.9) < 1e-3);
+        let cos_middle = 0.5 * (degrees_to_radians(20.0).cos() + degrees_to_radians(40.0).cos());
+        assert!((at(cos_middle.acos().to_degrees()) - 0.5).abs() < 1e-9);
+        let mut previous = 1.0;
+        for degrees in 20..=40 {
+            let radiance = at(degrees as f64);
+            assert!(radiance <= previous);
+            previous = radiance;
+        }
+    }
+
+    #[test]
+    fn sun_spreads_its_irradiance_over_its_disk() {
+        let direction = Vec3::new(1.0, -2.0, 0.5).unit_vector();
+        let irradiance = Color::new(3.0, 2.0, 1.0);
+        let sun = DirectionalLight::sun(direction, irradiance);
+        let cos_max = degrees_to_radians(0.5 * 0.53).cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+
+        let samples = 1000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let sample = sun.sample(Vec3::new(0.0, 0.0, 0.0)).unwrap();
+            assert!(sample.direction.dot(-direction) >= cos_max - 1e-12);
+            assert!((sample.pdf * solid_angle - 1.0).abs() < 1e-9);
+            assert_eq!(sample.distance, f64::INFINITY);
+            total += sample.radiance / sample.pdf;
+        }
+
+        // Each sample's estimate of the irradiance on a surface facing the sun is the whole of it
+        let total = total / samples as f64;
+        assert_near(
+            Vec3::new(total.r, total.g, total.b),
+            Vec3::new(irradiance.r, irradiance.g, irradiance.b),
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use crate::{
+    aabb::Aabb,
+    distribution::Distribution1D,
+    light::Light,
+    util::{Interval, PI},
+    vec3::Vec3,
+};
+
+type Point3 = Vec3;
+
+// How direct lighting picks the one light it samples at each hit point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightSampling {
+    // Every light is equally likely
+    Uniform,
+    // Lights are picked in proportion to their power, wherever the hit point is
+    Power,
+    // Lights are picked by walking down a bounding volume hierarchy over them, estimating at each
+    // node how much light each side could deliver to the hit point from its power, distance and
+    // the directions it shines in
+    Bvh,
+}
+
+// Where a light is, which way it shines and how much power it gives off, for estimating how much
+// it could contribute to a point. The light shines within cos_theta_e past a cone of normals
+// around w whose half angle has cosine cos_theta_o. A point light has cos_theta_o = -1 (the
+// whole sphere) and a one-sided area light has cos_theta_o = 1 and cos_theta_e = 0.
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub w: Vec3,
+    pub phi: f64,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    // The tightest bounds enclosing both a and b
+    pub fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+        if a.phi == 0.0 {
+            return *b;
+        }
+        if b.phi == 0.0 {
+            return *a;
+        }
+
+        let (w, cos_theta_o) = cone_union(a.w, a.cos_theta_o, b.w, b.cos_theta_o);
+        LightBounds {
+            bounds: Aabb::surrounding(&a.bounds, &b.bounds),
+            w,
+            phi: a.phi + b.phi,
+            cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+            two_sided: a.two_sided || b.two_sided,
+        }
+    }
+
+    // A conservative estimate of the light reaching p, on a surface with normal n, from lights
+    // within these bounds (Conty Estevez and Kulla 2018). n may be zero for points not on a
+    // surface.
+    pub fn importance(&self, p: Point3, n: Vec3) -> f64 {
+        let center = self.bounds.centroid();
+        let half_diagonal = 0.5 * self.diagonal().length();
+
+        // Clamp the distance so points inside or near the bounds don't blow up
+        let offset = p - center;
+        let distance_squared = offset.length_squared().max(half_diagonal);
+        let wi = offset.unit_vector();
+
+        let mut cos_theta_w = self.w.dot(wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Bound on the angle the bounds subtend as seen from p
+        let cos_theta_b = if offset.length_squared() < half_diagonal * half_diagonal {
+            -1.0
+        } else {
+            let sin2_theta_max = half_diagonal * half_diagonal / offset.length_squared();
+            safe_sqrt(1.0 - sin2_theta_max)
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // Smallest angle between the direction to p and the emitting normals, shrunk further by
+        // the extent of the bounds
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / distance_squared;
+
+        // Light arriving at a grazing angle to the surface delivers less
+        if !n.near_zero() {
+            let cos_theta_i = wi.dot(n).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+
+        importance.max(0.0)
+    }
+
+    fn diagonal(&self) -> Vec3 {
+        Vec3::new(
+            self.bounds.x.size(),
+            self.bounds.y.size(),
+            self.bounds.z.size(),
+        )
+    }
+}
+
+// Picks which light to sample for direct lighting. Lights infinitely far away have no bounds and
+// are picked uniformly with the same chance as the hierarchy over the rest.
+pub struct LightSampler {
+    strategy: LightSampling,
+    count: usize,
+    distribution: Option<Distribution1D>,
+    infinite: Vec<usize>,
+    nodes: Vec<LightNode>,
+    // For each light in the hierarchy, the index of its leaf and the choices leading to it from
+    // the root, one bit per level with the first choice in the lowest bit
+    trails: Vec<Option<(usize, u64)>>,
+}
+
+struct LightNode {
+    bounds: LightBounds,
+    kind: LightNodeKind,
+}
+
+enum LightNodeKind {
+    Leaf(usize),
+    // The first child directly follows its parent
+    Interior { second_child: usize },
+}
+
+impl LightSampler {
+    pub fn new(
+        lights: &[Box<dyn Light>],
+        strategy: LightSampling,
+        scene_bounds: Aabb,
+    ) -> LightSampler {
+        let mut sampler = LightSampler {
+            strategy,
+            count: lights.len(),
+            distribution: None,
+            infinite: Vec::new(),
+            nodes: Vec::new(),
+            trails: vec![None; lights.len()],
+        };
+
+        match strategy {
+            LightSampling::Uniform => {}
+            LightSampling::Power => {
+                // Lights infinitely far away give their power per unit area, which is spread
+                // over a disk as large as the scene
+                let radius = 0.5 * scene_diagonal(&scene_bounds);
+                let powers = lights
+                    .iter()
+                    .map(|light| match light.bounds() {
+                        Some(_) => light.power(),
+                        None => light.power() * PI * radius * radius,
+                    })
+                    .collect();
+                sampler.distribution = Some(Distribution1D::new(powers));
+            }
+            LightSampling::Bvh => {
+                let mut bounded = Vec::new();
+                for (i, light) in lights.iter().enumerate() {
+                    match light.bounds() {
+                        Some(bounds) if bounds.phi > 0.0 => bounded.push((i, bounds)),
+                        Some(_) => {}
+                        None => sampler.infinite.push(i),
+                    }
+                }
+                if !bounded.is_empty() {
+                    sampler.build(&mut bounded, 0, 0);
+                }
+            }
+        }
+
+        sampler
+    }
+
+    // Picks a light to sample for the point p on a surface with normal n, using u in [0,1).
+    // Returns its index and the probability of picking it.
+    pub fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+
+        match self.strategy {
+            LightSampling::Uniform => {
+                let i = ((u * self.count as f64) as usize).min(self.count - 1);
+                Some((i, 1.0 / self.count as f64))
+            }
+            LightSampling::Power => {
+                let (i, probability) = self.distribution.as_ref()?.sample_discrete(u);
+                (probability > 0.0).then_some((i, probability))
+            }
+            LightSampling::Bvh => self.sample_bvh(p, n, u),
+        }
+    }
+
+    // Probability that sample picks light i for the point p with normal n
+    pub fn pmf(&self, p: Point3, n: Vec3, i: usize) -> f64 {
+        if i >= self.count {
+            return 0.0;
+        }
+
+        match self.strategy {
+            LightSampling::Uniform => 1.0 / self.count as f64,
+            LightSampling::Power => self
+                .distribution
+                .as_ref()
+                .map_or(0.0, |distribution| distribution.discrete_pdf(i)),
+            LightSampling::Bvh => self.pmf_bvh(p, n, i),
+        }
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        let bvh = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        let infinite = self.infinite.len() as f64;
+        if infinite + bvh == 0.0 {
+            0.0
+        } else {
+            infinite / (infinite + bvh)
+        }
+    }
+
+    fn sample_bvh(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        let p_infinite = self.infinite_probability();
+        if u < p_infinite {
+            let count = self.infinite.len();
+            let i = ((u / p_infinite * count as f64) as usize).min(count - 1);
+            return Some((self.infinite[i], p_infinite / count as f64));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        // Reuse what's left of u at each level, rescaled to [0,1)
+        let mut u = ((u - p_infinite) / (1.0 - p_infinite)).min(1.0 - f64::EPSILON);
+        let mut pmf = 1.0 - p_infinite;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            match node.kind {
+                LightNodeKind::Leaf(light) => {
+                    // A lone light at the root hasn't been tested yet
+                    if index > 0 || node.bounds.importance(p, n) > 0.0 {
+                        return Some((light, pmf));
+                    }
+                    return None;
+                }
+                LightNodeKind::Interior { second_child } => {
+                    let first = self.nodes[index + 1].bounds.importance(p, n);
+                    let second = self.nodes[second_child].bounds.importance(p, n);
+                    if first == 0.0 && second == 0.0 {
+                        return None;
+                    }
+
+                    let p_first = first / (first + second);
+                    if u < p_first {
+                        index += 1;
+                        pmf *= p_first;
+                        u = (u / p_first).min(1.0 - f64::EPSILON);
+                    } else {
+                        index = second_child;
+                        pmf *= 1.0 - p_first;
+                        u = ((u - p_first) / (1.0 - p_first)).min(1.0 - f64::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf_bvh(&self, p: Point3, n: Vec3, i: usize) -> f64 {
+        let p_infinite = self.infinite_probability();
+        let (leaf, mut trail) = match self.trails[i] {
+            Some(trail) => trail,
+            None if self.infinite.contains(&i) => {
+                return p_infinite / self.infinite.len() as f64;
+            }
+            None => return 0.0,
+        };
+
+        let mut pmf = 1.0 - p_infinite;
+        let mut index = 0;
+        while index != leaf {
+            let LightNodeKind::Interior { second_child } = self.nodes[index].kind else {
+                return 0.0;
+            };
+            let first = self.nodes[index + 1].bounds.importance(p, n);
+            let second = self.nodes[second_child].bounds.importance(p, n);
+            if first == 0.0 && second == 0.0 {
+                return 0.0;
+            }
+
+            if trail & 1 == 0 {
+                pmf *= first / (first + second);
+                index += 1;
+            } else {
+                pmf *= second / (first + second);
+                index = second_child;
+            }
+            trail >>= 1;
+        }
+        pmf
+    }
+
+    // Builds the subtree over `lights` at the end of the node list, splitting where the surface
+    // area orientation heuristic is lowest. Returns the bounds of the subtree.
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        let index = self.nodes.len();
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(LightNode {
+                bounds,
+                kind: LightNodeKind::Leaf(light),
+            });
+            self.trails[light] = Some((index, trail));
+            return bounds;
+        }
+
+        // The trail has room for 64 levels, so deep subtrees split evenly instead
+        let split = if depth < 32 { best_split(lights) } else { None }.unwrap_or(lights.len() / 2);
+
+        self.nodes.push(LightNode {
+            bounds: lights[0].1,
+            kind: LightNodeKind::Interior { second_child: 0 },
+        });
+        let (first, second) = lights.split_at_mut(split);
+        let first = self.build(first, trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second = self.build(second, trail | (1 << depth), depth + 1);
+
+        let bounds = LightBounds::union(&first, &second);
+        self.nodes[index] = LightNode {
+            bounds,
+            kind: LightNodeKind::Interior { second_child },
+        };
+        bounds
+    }
+}
+
+// Sorts lights into buckets along each axis and returns where to split them, after reordering
+// them so that the first part comes first. Returns None when every split is equally bad, as when
+// all the lights are in the same place.
+fn best_split(lights: &mut [(usize, LightBounds)]) -> Option<usize> {
+    const BUCKETS: usize = 12;
+
+    let mut centroids =
+        Aabb::from_points(lights[0].1.bounds.centroid(), lights[0].1.bounds.centroid());
+    let mut bounds = lights[0].1;
+    for (_, light) in lights.iter() {
+        let c = light.bounds.centroid();
+        centroids = Aabb::surrounding(&centroids, &Aabb::from_points(c, c));
+        bounds = LightBounds::union(&bounds, light);
+    }
+    let extent = bounds.diagonal();
+    let max_extent = extent.x.max(extent.y).max(extent.z);
+
+    let bucket_of = |light: &LightBounds, axis: usize| {
+        let interval = centroids.axis_interval(axis);
+        let offset = (light.bounds.centroid()[axis] - interval.min) / interval.size();
+        ((offset * BUCKETS as f64) as usize).min(BUCKETS - 1)
+    };
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroids.axis_interval(axis).size() <= 0.0 || extent[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut buckets: [Option<LightBounds>; BUCKETS] = [None; BUCKETS];
+        for (_, light) in lights.iter() {
+            let b = &mut buckets[bucket_of(light, axis)];
+            *b = Some(b.map_or(*light, |b| LightBounds::union(&b, light)));
+        }
+
+        // Elongated bounds are split across their long axis
+        let kr = max_extent / extent[axis];
+        for split in 1..BUCKETS {
+            let union = |range: &[Option<LightBounds>]| {
+                range
+                    .iter()
+                    .flatten()
+                    .fold(None, |acc: Option<LightBounds>, b| {
+                        Some(acc.map_or(*b, |acc| LightBounds::union(&acc, b)))
+                    })
+            };
+            let (Some(below), Some(above)) = (union(&buckets[..split]), union(&buckets[split..]))
+            else {
+                continue;
+            };
+            let cost = kr * (split_cost(&below) + split_cost(&above));
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (_, axis, split) = best?;
+    lights.sort_by_key(|(_, light)| bucket_of(light, axis) >= split);
+    let mid = lights
+        .iter()
+        .position(|(_, light)| bucket_of(light, axis) >= split)?;
+    (mid > 0 && mid < lights.len()).then_some(mid)
+}
+
+// Power times the measure of the directions the bounds shine in, times their surface area
+fn split_cost(b: &LightBounds) -> f64 {
+    let theta_o = b.cos_theta_o.clamp(-1.0, 1.0).acos();
+    let theta_e = b.cos_theta_e.clamp(-1.0, 1.0).acos();
+    let theta_w = (theta_o + theta_e).min(PI);
+    let sin_theta_o = safe_sqrt(1.0 - b.cos_theta_o * b.cos_theta_o);
+    let m_omega = 2.0 * PI * (1.0 - b.cos_theta_o)
+        + PI / 2.0
+            * (2.0 * theta_w * sin_theta_o
+                - (theta_o - 2.0 * theta_w).cos()
+                - 2.0 * theta_o * sin_theta_o
+                + b.cos_theta_o);
+
+    let d = b.diagonal();
+    let area = 2.0 * (d.x * d.y + d.y * d.z + d.z * d.x);
+    b.phi * m_omega * area
+}
+
+// The smallest cone of directions containing the cones around wa and wb with half angles whose
+// cosines are cos_a and cos_b
+fn cone_union(wa: Vec3, cos_a: f64, wb: Vec3, cos_b: f64) -> (Vec3, f64) {
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = wa.dot(wb).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (wa, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (wb, cos_b);
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let axis = wa.cross(wb);
+    if theta_o >= PI || axis.near_zero() {
+        return (wa, -1.0);
+    }
+
+    // Rotate wa toward wb about their common perpendicular (Rodrigues' formula)
+    let theta_r = theta_o - theta_a;
+    let k = axis.unit_vector();
+    let w =
+        wa * theta_r.cos() + k.cross(wa) * theta_r.sin() + k * (k.dot(wa) * (1.0 - theta_r.cos()));
+    (w.unit_vector(), theta_o.cos())
+}
+
+// cos(theta_a - theta_b), or 1 when theta_a < theta_b
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    cos_a * cos_b + sin_a * sin_b
+}
+
+// sin(theta_a - theta_b), or 0 when theta_a < theta_b
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    sin_a * cos_b - cos_a * sin_b
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn scene_diagonal(bounds: &Aabb) -> f64 {
+    let size = |interval: Interval| {
+        if interval.size().is_finite() {
+            interval.size().max(0.0)
+        } else {
+            0.0
+        }
+    };
+    Vec3::new(size(bounds.x), size(bounds.y), size(bounds.z)).length()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        light::{DirectionalLight, PointLight, QuadLight, SpotLight},
+        vec3::Color,
+    };
+
+    // Lights of every kind at different distances and strengths, one of them below the surface
+    // being lit
+    fn lights() -> Vec<Box<dyn Light>> {
+        let point = |x: f64, y: f64, z: f64, intensity: f64| -> Box<dyn Light> {
+            Box::new(PointLight::new(
+                Vec3::new(x, y, z),
+                Color::new(intensity, intensity, intensity),
+            ))
+        };
+        vec![
+            point(1.0, 1.0, 0.0, 1.0),
+            point(-4.0, 3.0, 2.0, 10.0),
+            point(0.5, 6.0, -3.0, 4.0),
+            point(2.0, -2.0, 0.0, 5.0),
+            Box::new(SpotLight::new(
+                Vec3::new(0.0, 4.0, 0.0),
+                Vec3::new(0.0, -1.0, 0.0),
+                Color::new(20.0, 20.0, 20.0),
+                20.0,
+                30.0,
+            )),
+            Box::new(QuadLight::new(
+                Vec3::new(-1.0, 5.0, -1.0),
+                Vec3::new(-1.0, 5.0, 1.0),
+                Vec3::new(1.0, 5.0, 1.0),
+                Vec3::new(1.0, 5.0, -1.0),
+                Color::new(2.0, 2.0, 2.0),
+            )),
+            Box::new(DirectionalLight::new(
+                Vec3::new(0.3, -1.0, 0.2),
+                Color::new(1.0, 1.0, 1.0),
+            )),
+        ]
+    }
+
+    #[test]
+    fn pmf_matches_sample() {
+        let lights = lights();
+        let bounds = Aabb::from_points(Vec3::new(-10.0, -10.0, -10.0), Vec3::new(10.0, 10.0, 10.0));
+        let (p, n) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        for strategy in [
+            LightSampling::Uniform,
+            LightSampling::Power,
+            LightSampling::Bvh,
+        ] {
+            let sampler = LightSampler::new(&lights, strategy, bounds);
+            let pmf: Vec<f64> = (0..lights.len()).map(|i| sampler.pmf(p, n, i)).collect();
+            let total: f64 = pmf.iter().sum();
+            assert!(
+                (total - 1.0).abs() < 1e-9,
+                "{:?} sums to {}",
+                strategy,
+                total
+            );
+
+            let samples = 100000;
+            let mut counts = vec![0; lights.len()];
+            for k in 0..samples {
+                let u = (k as f64 + 0.5) / samples as f64;
+                let (i, probability) = sampler.sample(p, n, u).unwrap();
+                assert!(
+                    (probability - pmf[i]).abs() < 1e-9,
+                    "{:?} light {}",
+                    strategy,
+                    i
+                );
+                counts[i] += 1;
+            }
+            for (i, count) in counts.iter().enumerate() {
+                let frequency = *count as f64 / samples as f64;
+                assert!(
+                    (frequency - pmf[i]).abs() < 1e-3,
+                    "{:?} picked light {} {} of the time for a pmf of {}",
+                    strategy,
+                    i,
+                    frequency,
+                    pmf[i]
+                );
+            }
+        }
+    }
+}
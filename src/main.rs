@@ -1,17 +1,13 @@
 use std::sync::Arc;
 
-use camera::Camera;
-use hit::Hittables;
-use vec3::{Color, Vec3};
-
-pub mod camera;
-pub mod hit;
-pub mod material;
-pub mod quad;
-pub mod ray;
-pub mod sphere;
-pub mod util;
-pub mod vec3;
+use raytracer::adaptive::AdaptiveSampling;
+use raytracer::aov::{Aov, Aovs};
+use raytracer::camera::Camera;
+use raytracer::denoise::Denoiser;
+use raytracer::hit::Hittables;
+use raytracer::scene::Scene;
+use raytracer::vec3::{Color, Vec3};
+use raytracer::{integrator, material, quad, sphere};
 
 fn main() {
     let mut world = Hittables::new();
@@ -32,7 +28,7 @@ fn main() {
     world.add(Box::new(sphere::Sphere::new(
         Vec3::new(1.0, 0.0, -1.5),
         0.5,
-        Arc::new(material::Metal::new(Color::new(0.8, 0.3, 0.8), 0.1)),
+        Arc::new(material::Conductor::copper(0.1)),
     )));
 
     // floor
@@ -44,7 +40,62 @@ fn main() {
         Arc::new(material::Lambertian::new(Color::new(0.8, 0.8, 0.8))),
     )));
 
-    let camera = Camera::new(5.0 / 4.0, 400, 30, 10);
+    let max_depth = 10;
+    let mut camera = Camera::new(5.0 / 4.0, 400, 30, max_depth);
+    camera.spectral = std::env::args().any(|arg| arg == "--spectral");
+    for arg in std::env::args() {
+        if let Some(name) = arg.strip_prefix("--integrator=") {
+            match integrator::by_name(name, max_depth) {
+                Some(integrator) => camera.integrator = integrator,
+                None => {
+                    eprintln!(
+                        "unknown integrator {name}, expected path, naive, whitted, ao, bdpt, photon, mlt or guided"
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
-    camera.render(&world);
+    // Passes for compositing, by name or "all", written to aovs.exr unless --aov-output is given
+    let mut aovs = Vec::new();
+    let mut aov_output = String::from("aovs.exr");
+    for arg in std::env::args() {
+        if let Some(names) = arg.strip_prefix("--aovs=") {
+            for name in names.split(',') {
+                match Aov::by_name(name) {
+                    Some(aov) => aovs.push(aov),
+                    None if name == "all" => aovs.extend(Aov::ALL),
+                    None => {
+                        eprintln!(
+                            "unknown AOV {name}, expected all, albedo, normal, depth, position, object_id, material_id, direct, indirect or emission"
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+        } else if let Some(path) = arg.strip_prefix("--aov-output=") {
+            aov_output = path.to_string();
+        }
+    }
+    if !aovs.is_empty() {
+        camera.aovs = Some(Aovs::new(&aovs, aov_output));
+    }
+
+    // Between 8 samples and four times the usual number in each pixel, with how many were taken
+    // written to samples.ppm
+    if std::env::args().any(|arg| arg == "--adaptive") {
+        camera.adaptive = Some(AdaptiveSampling::new(
+            8,
+            4 * camera.samples_per_pixel,
+            "samples.ppm",
+        ));
+    }
+
+    // The noisy image still goes to stdout, with the denoised one written to denoised.ppm
+    if std::env::args().any(|arg| arg == "--denoise") {
+        camera.denoiser = Some(Denoiser::new("denoised.ppm"));
+    }
+
+    camera.render(&Scene::new(world));
 }
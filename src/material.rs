@@ -1,10 +1,29 @@
+use std::sync::Arc;
+
 use crate::{
     hit::HitRecord,
+    microfacet::{
+        eval_dielectric, fresnel_conductor, fresnel_dielectric, internal_reflectance,
+        sample_dielectric, Ggx,
+    },
     ray::Ray,
+    sampler::random,
+    spectrum::{rgb_curve, Dispersion, LAMBDA_REFERENCE},
+    texture::Texture,
+    thin_film::ThinFilm,
+    util::PI,
     vec3::{Color, Vec3},
 };
 
-pub trait Material {
+// The kind of scattering event that produced a ray, for limiting how many of each a path may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bounce {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+pub trait Material: Send + Sync {
     fn scatter(
         &self,
         r_in: &Ray,
@@ -12,6 +31,62 @@ pub trait Material {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
+
+    // Probability that a ray hitting this point stops here rather than passing through, as for
+    // the cutout parts of a leaf or fence texture. Scene::hit skips over hits that fail this test,
+    // and is the only place it's made, so that hits nested inside lists of objects aren't tested
+    // more than once.
+    fn opacity(&self, _rec: &HitRecord) -> f64 {
+        1.0
+    }
+
+    // Whether scatter() computes attenuation at the incoming ray's wavelengths in spectral mode,
+    // instead of in RGB to be upsampled
+    fn is_spectral(&self) -> bool {
+        false
+    }
+
+    // Light given off by the surface back along the incoming ray
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // The BSDF times the cosine of the angle to the normal, for light arriving from the unit
+    // direction wi and leaving back along r_in. Lights that are sampled explicitly are gathered
+    // through this. Materials that only scatter in discrete directions return black and only pass
+    // on the light that scatter() finds.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Density over solid angle with which scatter() picks the unit direction wi, for weighting it
+    // against light sampling. Every material whose eval() isn't black must give this.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f64 {
+        0.0
+    }
+
+    // The kind of bounce that produced `scattered` from a call to scatter(). By default rays that
+    // pass through the surface are transmission and the rest are specular.
+    fn bounce(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        if scattered.direction.dot(rec.normal) < 0.0 {
+            Bounce::Transmission
+        } else {
+            Bounce::Specular
+        }
+    }
+
+    // The normal the surface is shaded with, which materials that perturb it, like normal maps,
+    // override. Used for normal passes rather than for lighting.
+    fn shading_normal(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        rec.normal
+    }
+
+    // The surface's color at the hit in RGB, for albedo passes and the denoiser's features. It
+    // is looked up rather than sampled, so it draws no random numbers. Clear and mirror-like
+    // surfaces are white.
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
 }
 
 pub struct Lambertian {
@@ -37,23 +112,133 @@ impl Material for Lambertian {
         *attenuation = self.albedo;
         true
     }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.albedo * (rec.normal.dot(wi).max(0.0) / PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        rec.normal.dot(wi).max(0.0) / PI
+    }
+
+    fn bounce(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Bounce {
+        Bounce::Diffuse
+    }
+
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
-pub struct Metal {
-    albedo: Color,
-    fuzz: f64,
+// A surface that gives off light in front of it, for area lights. It doesn't reflect anything.
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
 }
 
-impl Metal {
-    pub fn new(albedo: Color, fuzz: f64) -> Metal {
-        Metal {
-            albedo,
-            fuzz: fuzz.min(1.0),
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            self.emit
+        } else {
+            Color::new(0.0, 0.0, 0.0)
         }
     }
 }
 
-impl Material for Metal {
+// A physically based metal: GGX microfacets with Smith masking, reflecting with the Fresnel
+// reflectance of a complex index of refraction eta + ik given per color channel
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+    thin_film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor::anisotropic(eta, k, roughness, roughness)
+    }
+
+    // Roughness is given separately along the two tangent directions of the surface
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness_u, roughness_v),
+            thin_film: None,
+        }
+    }
+
+    // An oxide or other transparent film over the metal, as on anodized titanium or heat-tinted
+    // steel
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Conductor {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.143119, 0.374957, 1.44248),
+            Color::new(3.98316, 2.38572, 1.60322),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.200438, 0.924033, 1.10221),
+            Color::new(3.91295, 2.45285, 2.14219),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(0.155265, 0.116723, 0.138342),
+            Color::new(4.82835, 3.12225, 2.14696),
+            roughness,
+        )
+    }
+
+    pub fn aluminum(roughness: f64) -> Conductor {
+        Conductor::new(
+            Color::new(1.65746, 0.880369, 0.521229),
+            Color::new(9.22387, 6.26952, 4.837),
+            roughness,
+        )
+    }
+
+    fn fresnel(&self, r_in: &Ray, rec: &HitRecord, cos_i: f64) -> Color {
+        match &self.thin_film {
+            Some(thin_film) => thin_film.reflectance(r_in, rec, cos_i, 1.0, |lambda| {
+                (rgb_curve(self.eta, lambda), rgb_curve(self.k, lambda))
+            }),
+            None => Color::new(
+                fresnel_conductor(cos_i, self.eta.r, self.k.r),
+                fresnel_conductor(cos_i, self.eta.g, self.k.g),
+                fresnel_conductor(cos_i, self.eta.b, self.k.b),
+            ),
+        }
+    }
+}
+
+impl Material for Conductor {
     fn scatter(
         &self,
         r_in: &Ray,
@@ -61,26 +246,134 @@ impl Material for Metal {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let reflected = r_in.direction.unit_vector().reflect(rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_unit_vector());
-        *attenuation = self.albedo;
-        scattered.direction.dot(rec.normal) > 0.0
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        // Sampling visible normals makes the estimator weight F * G2 / G1, since D and the
+        // Jacobian of the reflection cancel with the sampling density
+        let h = self
+            .distribution
+            .sample_visible_normal(wo, random(), random());
+        let wi = (-wo).reflect(h);
+        if wi.z <= 0.0 {
+            return false;
+        }
+
+        *scattered = Ray::new(rec.p, frame.local(wi));
+        *attenuation = self.fresnel(r_in, rec, wo.dot(h))
+            * (self.distribution.g(wo, wi) / self.distribution.g1(wo));
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        // D * G * F / (4 cos_o cos_i), times cos_i
+        let h = (wo + wi).unit_vector();
+        self.fresnel(r_in, rec, wo.dot(h))
+            * (self.distribution.d(h) * self.distribution.g(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        // The density of the visible normal times the Jacobian of the reflection
+        let h = (wo + wi).unit_vector();
+        self.distribution.visible_d(wo, h) / (4.0 * wo.dot(h))
+    }
+
+    fn is_spectral(&self) -> bool {
+        self.thin_film.is_some()
+    }
+
+    // Reflectance at normal incidence, in RGB even in spectral mode
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.fresnel(&Ray::new(rec.p, r_in.direction), rec, 1.0)
     }
 }
 
 pub struct Dielectric {
     refraction_index: f64,
+    dispersion: Option<Dispersion>,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Dielectric {
-        Dielectric { refraction_index }
+        Dielectric {
+            refraction_index,
+            dispersion: None,
+            thin_film: None,
+        }
+    }
+
+    // A dispersive dielectric, whose index of refraction varies with wavelength. Outside of
+    // spectral mode it behaves like plain glass with the index at LAMBDA_REFERENCE.
+    pub fn dispersive(dispersion: Dispersion) -> Dielectric {
+        Dielectric {
+            refraction_index: dispersion.refraction_index(LAMBDA_REFERENCE),
+            dispersion: Some(dispersion),
+            thin_film: None,
+        }
+    }
+
+    // A film on the outside of the surface. A soap bubble is a thin film of water on a dielectric
+    // with an index of refraction of 1.
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Dielectric {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    // Schott N-BK7 borosilicate crown glass
+    pub fn bk7() -> Dielectric {
+        Dielectric::dispersive(Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        })
+    }
+
+    // Schott SF11 dense flint glass, which disperses much more strongly than BK7
+    pub fn sf11() -> Dielectric {
+        Dielectric::dispersive(Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        })
+    }
+
+    pub fn diamond() -> Dielectric {
+        Dielectric::dispersive(Dispersion::Sellmeier {
+            b: [4.3356, 0.3306, 0.0],
+            c: [0.011236, 0.030625, 0.0],
+        })
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powi(2);
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
+
+    // Randomly decides whether light reflects off a smooth interface rather than refracting
+    // through it, with the probability given by the Fresnel reflectance. ri is the ratio of the
+    // indices of refraction on the incident and far sides, and the normal faces the incoming ray.
+    pub fn reflects(unit_direction: Vec3, normal: Vec3, ri: f64) -> bool {
+        let cos_theta = (-unit_direction).dot(normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let cannot_refract = ri * sin_theta > 1.0;
+        cannot_refract || Dielectric::reflectance(cos_theta, ri) > random()
+    }
 }
 
 impl Material for Dielectric {
@@ -92,10 +385,22 @@ impl Material for Dielectric {
         scattered: &mut Ray,
     ) -> bool {
         *attenuation = Color::new(1.0, 1.0, 1.0);
+
+        // A dispersive interface sends each wavelength in a different direction, so only the hero
+        // wavelength carries on past it
+        let mut wavelengths = r_in.wavelengths;
+        let refraction_index = match (self.dispersion, wavelengths.as_mut()) {
+            (Some(dispersion), Some(wavelengths)) => {
+                wavelengths.terminate_secondary();
+                dispersion.refraction_index(wavelengths.lambda[0])
+            }
+            _ => self.refraction_index,
+        };
+
         let ri = if rec.front_face {
-            1.0 / self.refraction_index
+            1.0 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
 
         let unit_direction = r_in.direction.unit_vector();
@@ -103,14 +408,474 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Dielectric::reflectance(cos_theta, ri) > rand::random::<f64>() {
-                unit_direction.reflect(rec.normal)
-            } else {
-                unit_direction.refract(&rec.normal, ri)
-            };
+        let reflect = match &self.thin_film {
+            // The film's reflectance varies by channel, so reflection is chosen with the average
+            // and each channel is weighted by how far it differs from that
+            Some(thin_film) => {
+                let (n_incident, n_substrate) = if rec.front_face {
+                    (1.0, refraction_index)
+                } else {
+                    (refraction_index, 1.0)
+                };
+                let reflectance =
+                    thin_film.reflectance(r_in, rec, cos_theta, n_incident, |_| (n_substrate, 0.0));
+                let probability = reflectance.average().clamp(0.0, 1.0);
+                let reflect = cannot_refract || random() < probability;
+                *attenuation = if reflect {
+                    reflectance / probability
+                } else {
+                    (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability)
+                };
+                reflect
+            }
+            None => Dielectric::reflects(unit_direction, rec.normal, ri),
+        };
+        let direction = if reflect {
+            unit_direction.reflect(rec.normal)
+        } else {
+            unit_direction.refract(&rec.normal, ri)
+        };
 
         *scattered = Ray::new(rec.p, direction);
+        scattered.wavelengths = wavelengths;
         true
     }
+
+    fn is_spectral(&self) -> bool {
+        self.thin_film.is_some()
+    }
+}
+
+// Frosted glass: a dielectric interface made of GGX microfacets that both reflect and transmit
+// (Walter et al. 2007). The medium inside can optionally absorb light with the Beer-Lambert law,
+// where `absorption` is the absorption coefficient per unit distance for each color channel.
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distribution: Ggx,
+    absorption: Option<Color>,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            refraction_index,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            absorption: None,
+        }
+    }
+
+    pub fn with_absorption(mut self, absorption: Color) -> RoughDielectric {
+        self.absorption = Some(absorption);
+        self
+    }
+
+    // A ray hitting the inside of the surface has travelled through the medium
+    fn absorbed(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match (self.absorption, rec.front_face) {
+            (Some(absorption), false) => beer_lambert(absorption, rec.t * r_in.direction.length()),
+            _ => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // The normal always faces the incoming ray, so eta is the ratio across the interface in
+        // the direction of travel
+        let eta = if rec.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let (wi, weight) = match sample_dielectric(&self.distribution, wo, eta) {
+            Some(sample) => sample,
+            None => return false,
+        };
+
+        *scattered = Ray::new(rec.p, frame.local(wi));
+        *attenuation = weight * self.absorbed(r_in, rec);
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let eta = if rec.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (f, _) = eval_dielectric(&self.distribution, wo, frame.to_local(wi), eta);
+        Color::new(f, f, f) * self.absorbed(r_in, rec)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let eta = if rec.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        eval_dielectric(&self.distribution, wo, frame.to_local(wi), eta).1
+    }
+}
+
+// Fraction of light transmitted through `distance` of a medium with the given absorption
+// coefficients
+pub fn beer_lambert(absorption: Color, distance: f64) -> Color {
+    Color::new(
+        (-absorption.r * distance).exp(),
+        (-absorption.g * distance).exp(),
+        (-absorption.b * distance).exp(),
+    )
+}
+
+// A clear dielectric layer of the given thickness over any other material, for car paint,
+// varnished wood or lacquered metal. The coat absorbs light on its way through according to the
+// Beer-Lambert law, with `absorption` given per unit of thickness.
+//
+// Light is traced stochastically through the layers: it either reflects off the coat or refracts
+// into it, then bounces between the base and the underside of the coat until it refracts back
+// out. Each event is chosen with the probability of its Fresnel term, so the weights that remain
+// are the base's attenuation and the absorption along each pass through the coat. Lights that are
+// sampled explicitly are gathered through a closed form of the same thing instead.
+pub struct Coated {
+    inner: Arc<dyn Material>,
+    refraction_index: f64,
+    distribution: Ggx,
+    thickness: f64,
+    absorption: Color,
+    // Fraction of light scattered evenly by the base that the underside of the coat reflects back
+    internal_reflectance: f64,
+}
+
+impl Coated {
+    // The most bounces inside the coat before the path is considered absorbed
+    const MAX_BOUNCES: u32 = 16;
+
+    pub fn new(
+        inner: Arc<dyn Material>,
+        refraction_index: f64,
+        roughness: f64,
+        thickness: f64,
+        absorption: Color,
+    ) -> Coated {
+        Coated {
+            inner,
+            refraction_index,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            thickness: thickness.max(0.0),
+            absorption,
+            internal_reflectance: internal_reflectance(refraction_index),
+        }
+    }
+
+    // Under a spectral base the whole attenuation is at the ray's wavelengths, so the coat's RGB
+    // colors are upsampled to match
+    fn at_base_wavelengths(&self, r_in: &Ray, color: Color) -> Color {
+        match r_in.wavelengths {
+            Some(wavelengths) if self.inner.is_spectral() => wavelengths.albedo(color),
+            _ => color,
+        }
+    }
+
+    // Transmittance of a single pass through the coat at the given cosine with the normal
+    fn pass_through(&self, r_in: &Ray, cos: f64) -> Color {
+        let transmittance = beer_lambert(self.absorption, self.thickness / cos.abs().max(1e-4));
+        self.at_base_wavelengths(r_in, transmittance)
+    }
+
+    // The direction inside the coat that refracts out along the local direction w, as though the
+    // coat were smooth
+    fn refracted(&self, w: Vec3) -> Vec3 {
+        let eta = self.refraction_index;
+        let sin2_t = (1.0 - w.z * w.z) / (eta * eta);
+        Vec3::new(w.x / eta, w.y / eta, (1.0 - sin2_t).max(0.0).sqrt())
+    }
+
+    // The closed form behind eval() and pdf(): reflection off the coat, plus light that goes
+    // down through it, off the base and back up. The base sees both directions refracted by a
+    // smooth coat, so it is weighted by the Fresnel transmittance at each crossing, the absorption
+    // along each pass, and the change in solid angle between the two sides. Light the underside of
+    // the coat reflects back down is assumed to scatter off the base as evenly as a diffuse one
+    // would, which adds up to a geometric series in the base's albedo.
+    //
+    // The base's density is spread over the whole hemisphere outside, so a diffuse base comes out
+    // cosine weighted, as the light scatter() brings back out through the coat does.
+    fn eval_and_pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> (Color, f64) {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0.0 {
+            return (Color::new(0.0, 0.0, 0.0), 0.0);
+        }
+        let eta = self.refraction_index;
+
+        let (mut f, mut pdf) = if wi.z > 0.0 {
+            let (f, pdf) = eval_dielectric(&self.distribution, wo, wi, eta);
+            (Color::new(f, f, f), pdf)
+        } else {
+            (Color::new(0.0, 0.0, 0.0), 0.0)
+        };
+
+        let wo_inside = self.refracted(wo);
+        let entering = 1.0 - fresnel_dielectric(wo.z, eta);
+        let mut weight = self.pass_through(r_in, wo_inside.z) * entering;
+        // Light the base transmits leaves below without crossing the coat again
+        let (wi_inside, jacobian, spread) = if wi.z > 0.0 {
+            let wi_inside = self.refracted(wi);
+            weight = weight
+                * self.pass_through(r_in, wi_inside.z)
+                * (1.0 - fresnel_dielectric(wi.z, eta));
+            (wi_inside, wi.z / (eta * eta * wi_inside.z), eta * eta)
+        } else {
+            (wi, 1.0, 1.0)
+        };
+
+        let mut down = Ray::new(rec.p + frame.local(wo_inside), -frame.local(wo_inside));
+        down.wavelengths = r_in.wavelengths;
+        let albedo = self.at_base_wavelengths(r_in, self.inner.albedo(&down, rec));
+        let trapped =
+            |albedo: f64| 1.0 / (1.0 - albedo.clamp(0.0, 1.0) * self.internal_reflectance);
+        let trapped = Color::new(trapped(albedo.r), trapped(albedo.g), trapped(albedo.b));
+
+        let wi_inside = frame.local(wi_inside);
+        f += weight * trapped * self.inner.eval(&down, rec, wi_inside) * jacobian;
+        pdf += entering * self.inner.pdf(&down, rec, wi_inside) * jacobian * spread;
+        (f, pdf)
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // The coat is on the outside of the surface only
+        if !rec.front_face {
+            return self.inner.scatter(r_in, rec, attenuation, scattered);
+        }
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let (mut w, weight) = match sample_dielectric(&self.distribution, wo, self.refraction_index)
+        {
+            Some(sample) => sample,
+            None => return false,
+        };
+        let mut throughput = Color::new(1.0, 1.0, 1.0) * weight;
+        // The base may drop the secondary wavelengths, as a dispersive one does
+        let mut wavelengths = r_in.wavelengths;
+
+        // Reflected off the top of the coat
+        if w.z > 0.0 {
+            *scattered = Ray::new(rec.p, frame.local(w));
+            *attenuation = throughput;
+            return true;
+        }
+
+        // The layers are treated as infinitely thin and wide, so every event happens at rec.p
+        for _ in 0..Coated::MAX_BOUNCES {
+            // Down through the coat to the base
+            throughput = throughput * self.pass_through(r_in, w.z);
+            let mut base_attenuation = Color::new(0.0, 0.0, 0.0);
+            let mut base_scattered = Ray::new(rec.p, frame.local(w));
+            let mut down = Ray::new(rec.p - frame.local(w), frame.local(w));
+            down.wavelengths = wavelengths;
+            if !self
+                .inner
+                .scatter(&down, rec, &mut base_attenuation, &mut base_scattered)
+            {
+                return false;
+            }
+            throughput = throughput * base_attenuation;
+            wavelengths = base_scattered.wavelengths.or(wavelengths);
+
+            // The base transmitted the light instead, so the coat no longer matters
+            let up = frame.to_local(base_scattered.direction.unit_vector());
+            if up.z <= 0.0 {
+                *scattered = base_scattered;
+                scattered.wavelengths = wavelengths;
+                *attenuation = throughput;
+                return true;
+            }
+
+            // Up through the coat to its underside, seen from below by flipping the frame
+            throughput = throughput * self.pass_through(r_in, up.z);
+            let from_below = Vec3::new(-up.x, -up.y, up.z);
+            let (inside, weight) = match sample_dielectric(
+                &self.distribution,
+                from_below,
+                1.0 / self.refraction_index,
+            ) {
+                Some(sample) => sample,
+                None => return false,
+            };
+            throughput = throughput * weight;
+
+            // Flip back: refracted light leaves upwards, internally reflected light heads down
+            w = Vec3::new(inside.x, inside.y, -inside.z);
+            if w.z > 0.0 {
+                *scattered = Ray::new(rec.p, frame.local(w));
+                scattered.wavelengths = wavelengths;
+                *attenuation = throughput;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        if !rec.front_face {
+            return self.inner.eval(r_in, rec, wi);
+        }
+        self.eval_and_pdf(r_in, rec, wi).0
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        if !rec.front_face {
+            return self.inner.pdf(r_in, rec, wi);
+        }
+        self.eval_and_pdf(r_in, rec, wi).1
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.inner.opacity(rec)
+    }
+
+    fn is_spectral(&self) -> bool {
+        self.inner.is_spectral()
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+
+    // Which event scatter() ended with isn't known any more, but most light leaving the coat has
+    // been through the base, so the base decides
+    fn bounce(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        self.inner.bounce(r_in, rec, scattered)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.inner.shading_normal(r_in, rec)
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.albedo(r_in, rec)
+    }
+}
+
+// Cuts holes in another material wherever the red channel of the opacity texture is below 1.
+// Fractional opacity lets that fraction of rays through, for soft edges.
+pub struct AlphaMask {
+    inner: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+}
+
+impl AlphaMask {
+    pub fn new(inner: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> AlphaMask {
+        AlphaMask { inner, opacity }
+    }
+}
+
+impl Material for AlphaMask {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner.scatter(r_in, rec, attenuation, scattered)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.opacity.value(rec.u, rec.v, rec.p).r.clamp(0.0, 1.0) * self.inner.opacity(rec)
+    }
+
+    fn is_spectral(&self) -> bool {
+        self.inner.is_spectral()
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, rec, wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        self.inner.pdf(r_in, rec, wi)
+    }
+
+    fn bounce(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        self.inner.bounce(r_in, rec, scattered)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.inner.shading_normal(r_in, rec)
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.albedo(r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_scatter_matches_eval, hit_at_origin};
+
+    #[test]
+    fn conductor_scatter_matches_eval() {
+        for roughness in [0.1, 0.4, 0.8] {
+            let (r_in, rec) = hit_at_origin(Arc::new(Conductor::gold(roughness)), true);
+            let (reflected, transmitted) = assert_scatter_matches_eval(&r_in, &rec, 1000);
+            assert!(reflected > 0 && transmitted == 0);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_scatter_matches_eval() {
+        let glass = RoughDielectric::new(1.5, 0.4).with_absorption(Color::new(0.5, 0.2, 0.1));
+        let glass: Arc<dyn Material> = Arc::new(glass);
+        // From outside, and from inside where the absorption applies and light can be totally
+        // internally reflected
+        for front_face in [true, false] {
+            let (r_in, rec) = hit_at_origin(Arc::clone(&glass), front_face);
+            let (reflected, transmitted) = assert_scatter_matches_eval(&r_in, &rec, 2000);
+            assert!(reflected > 0 && transmitted > 0);
+        }
+    }
+
+    #[test]
+    fn bk7_matches_catalog() {
+        // Schott's catalog gives n_d = 1.5168 at the helium d line
+        let dispersion = Dielectric::bk7().dispersion.unwrap();
+        assert!((dispersion.refraction_index(587.6) - 1.5168).abs() < 1e-4);
+    }
 }
@@ -0,0 +1,351 @@
+use crate::{sampler::random, util::PI, vec3::Vec3};
+
+// The GGX (Trowbridge-Reitz) microfacet distribution with Smith masking-shadowing.
+//
+// All directions are in the local shading frame, where the macrosurface normal is +z, and point
+// away from the surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Ggx {
+        // A perfectly smooth distribution is a delta function, which the sampling routines below
+        // approach closely enough at this roughness while avoiding divisions by zero
+        Ggx {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    // Maps artist friendly roughness in [0,1] to alpha, which looks more perceptually linear
+    pub fn from_roughness(roughness_x: f64, roughness_y: f64) -> Ggx {
+        Ggx::new(roughness_x * roughness_x, roughness_y * roughness_y)
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    // Density of microfacet normals h
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x / self.alpha_x;
+        let y = h.y / self.alpha_y;
+        let e = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // Smith's auxiliary function, the ratio of hidden to visible microfacet area in direction w
+    pub fn lambda(&self, w: Vec3) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY;
+        }
+        let a2_tan2 = (self.alpha_x * self.alpha_x * w.x * w.x
+            + self.alpha_y * self.alpha_y * w.y * w.y)
+            / (w.z * w.z);
+        0.5 * (-1.0 + (1.0 + a2_tan2).sqrt())
+    }
+
+    // Fraction of microfacets visible from w
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction of microfacets visible from both wo and wi (height-correlated)
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of microfacet normals h as seen from wo
+    pub fn visible_d(&self, wo: Vec3, h: Vec3) -> f64 {
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z.abs()
+    }
+
+    // Samples a microfacet normal from the distribution of normals visible from wo (Heitz 2018),
+    // so that no samples are wasted on backfacing or masked microfacets
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Flip to the upper hemisphere; the distribution is symmetric
+        let wo = if wo.z < 0.0 { -wo } else { wo };
+
+        // Stretch the view direction so the distribution becomes the hemisphere
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // Sample the projected area of the hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        // Unstretch back to the ellipsoid
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+}
+
+// Fresnel reflectance at the boundary of a conductor with complex index of refraction eta + ik,
+// relative to the outside medium
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// Unpolarized Fresnel reflectance at a dielectric boundary, where eta is the index of refraction
+// on the far side divided by the one on the incident side. Returns 1 under total internal
+// reflection.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+// Fraction of light arriving evenly from inside a medium with index of refraction eta relative to
+// the outside that its boundary reflects back in: the cosine-weighted average of the Fresnel
+// reflectance, by the midpoint rule
+pub fn internal_reflectance(eta: f64) -> f64 {
+    let steps = 256;
+    (0..steps)
+        .map(|i| {
+            let cos = (i as f64 + 0.5) / steps as f64;
+            2.0 * cos * fresnel_dielectric(cos, 1.0 / eta) / steps as f64
+        })
+        .sum()
+}
+
+// Samples a direction scattered by a rough dielectric interface, either reflected or refracted
+// through a microfacet sampled from the visible normals. wo must be in the upper hemisphere and
+// eta is the ratio of the indices of refraction below and above the surface.
+//
+// Choosing reflection with probability F cancels F out of the weight, which leaves G2 / G1 for
+// both lobes. Returns None when the sampled direction ends up on the wrong side of the
+// macrosurface.
+pub fn sample_dielectric(distribution: &Ggx, wo: Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    let h = distribution.sample_visible_normal(wo, random(), random());
+
+    let reflect = random() < fresnel_dielectric(wo.dot(h), eta);
+    let wi = if reflect {
+        (-wo).reflect(h)
+    } else {
+        (-wo).refract(&h, 1.0 / eta)
+    };
+    if (wi.z > 0.0) != reflect {
+        return None;
+    }
+
+    Some((wi, distribution.g(wo, wi) / distribution.g1(wo)))
+}
+
+// The BSDF times cos_i of the interface that sample_dielectric samples, for light arriving from
+// wi and leaving toward wo, along with the density of sample_dielectric choosing wi. Directions
+// and eta are as for sample_dielectric. The BTDF leaves out the (1 / eta)^2 radiance scaling, as
+// the sampling weights do.
+pub fn eval_dielectric(distribution: &Ggx, wo: Vec3, wi: Vec3, eta: f64) -> (f64, f64) {
+    let cos_o = wo.z;
+    let cos_i = wi.z;
+    if cos_o <= 0.0 || cos_i == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    if cos_i > 0.0 {
+        // D * G * F / (4 cos_o cos_i), times cos_i
+        let h = (wo + wi).unit_vector();
+        let fresnel = fresnel_dielectric(wo.dot(h), eta);
+        let f = fresnel * distribution.d(h) * distribution.g(wo, wi) / (4.0 * cos_o);
+        let pdf = fresnel * distribution.visible_d(wo, h) / (4.0 * wo.dot(h));
+        return (f, pdf);
+    }
+
+    // Refraction through the microfacet with the generalized half vector (Walter et al.)
+    let mut h = (wo + eta * wi).unit_vector();
+    if h.z < 0.0 {
+        h = -h;
+    }
+    let cos_oh = wo.dot(h);
+    let cos_ih = wi.dot(h);
+    if cos_oh <= 0.0 || cos_ih >= 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let denom = cos_ih + cos_oh / eta;
+    let denom = denom * denom;
+    let transmittance = 1.0 - fresnel_dielectric(cos_oh, eta);
+    let f = transmittance * distribution.d(h) * distribution.g(wo, wi) * (cos_ih * cos_oh).abs()
+        / (cos_o * denom);
+    let pdf = transmittance * distribution.visible_d(wo, h) * cos_ih.abs() / denom;
+    (f, pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    // Stratified Monte Carlo estimate of the integral of f over the upper hemisphere, with one
+    // jittered sample per cell of a grid over (theta, phi)
+    fn integrate_hemisphere(f: impl Fn(Vec3) -> f64) -> f64 {
+        let (n_theta, n_phi) = (512, 256);
+        let mut rng = StdRng::seed_from_u64(7);
+        let d_theta = 0.5 * PI / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            for j in 0..n_phi {
+                let theta = (i as f64 + rng.gen::<f64>()) * d_theta;
+                let phi = (j as f64 + rng.gen::<f64>()) * d_phi;
+                let w = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                sum += f(w) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        sum
+    }
+
+    fn directions() -> [Vec3; 3] {
+        [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.5, 0.2, 0.8).unit_vector(),
+            Vec3::new(0.9, -0.3, 0.2).unit_vector(),
+        ]
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        for distribution in [Ggx::new(0.3, 0.3), Ggx::new(0.5, 0.2), Ggx::new(1.0, 1.0)] {
+            for wo in directions() {
+                let total = integrate_hemisphere(|h| distribution.visible_d(wo, h));
+                assert!(
+                    (total - 1.0).abs() < 1e-2,
+                    "{:?} {:?}: {}",
+                    distribution,
+                    wo,
+                    total
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reflection_pdf_integrates_to_at_most_one() {
+        // Reflected directions that end up below the surface are lost, so only at normal
+        // incidence on a fairly smooth surface is the density close to a full 1
+        for distribution in [Ggx::new(0.3, 0.3), Ggx::new(0.5, 0.2), Ggx::new(1.0, 1.0)] {
+            for wo in directions() {
+                let total = integrate_hemisphere(|wi| {
+                    let h = (wo + wi).unit_vector();
+                    distribution.visible_d(wo, h) / (4.0 * wo.dot(h))
+                });
+                assert!(
+                    total <= 1.0 + 1e-2,
+                    "{:?} {:?}: {}",
+                    distribution,
+                    wo,
+                    total
+                );
+            }
+        }
+        let smooth = Ggx::new(0.1, 0.1);
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let total = integrate_hemisphere(|wi| {
+            let h = (wo + wi).unit_vector();
+            smooth.visible_d(wo, h) / (4.0 * wo.dot(h))
+        });
+        assert!((total - 1.0).abs() < 1e-2, "{}", total);
+    }
+
+    #[test]
+    fn white_furnace_reflects_at_most_everything() {
+        // With F = 1 the BRDF times cos_i integrates to the fraction of light reflected, which
+        // single-scattering microfacets can only lose to masking
+        for roughness in [0.2, 0.5, 1.0] {
+            let distribution = Ggx::from_roughness(roughness, roughness);
+            for wo in directions() {
+                let reflected = integrate_hemisphere(|wi| {
+                    let h = (wo + wi).unit_vector();
+                    distribution.d(h) * distribution.g(wo, wi) / (4.0 * wo.z)
+                });
+                assert!(
+                    0.0 < reflected && reflected <= 1.0 + 1e-2,
+                    "roughness {} {:?}: {}",
+                    roughness,
+                    wo,
+                    reflected
+                );
+            }
+        }
+
+        // Little is lost to masking on a smooth surface seen head on
+        let distribution = Ggx::from_roughness(0.2, 0.2);
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let reflected = integrate_hemisphere(|wi| {
+            let h = (wo + wi).unit_vector();
+            distribution.d(h) * distribution.g(wo, wi) / (4.0 * wo.z)
+        });
+        assert!(reflected > 0.95, "{}", reflected);
+    }
+
+    #[test]
+    fn smith_masking_is_a_fraction() {
+        let distribution = Ggx::new(0.5, 0.2);
+        for wo in directions() {
+            for wi in directions() {
+                let g1 = distribution.g1(wo);
+                let g = distribution.g(wo, wi);
+                assert!(0.0 < g && g <= g1 && g1 <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_limits() {
+        // A perfect conductor reflects everything, and real metals reflect more at grazing angles
+        assert!((fresnel_conductor(0.5, 1.0, 1e6) - 1.0).abs() < 1e-6);
+        let gold_red = |cos| fresnel_conductor(cos, 0.143119, 3.98316);
+        assert!(gold_red(1.0) > 0.9 && gold_red(1.0) <= 1.0);
+        assert!((gold_red(0.0) - 1.0).abs() < 1e-9);
+        // Without absorption it matches a dielectric of the same index
+        assert!((fresnel_conductor(0.7, 1.5, 0.0) - fresnel_dielectric(0.7, 1.5)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    camera::{progress_bar, Camera},
+    distribution::Distribution1D,
+    film::Film,
+    integrator::{CameraSample, DepthLimits, Integrator, PathTracer},
+    ray::Ray,
+    sampler::{random, with_sampler, PrimarySampleSpace},
+    scene::Scene,
+    util::parallel_for,
+};
+
+// Primary sample space Metropolis light transport, after Kelemen et al. Each path traced from the
+// camera is a function of the random numbers it consumes, so rather than drawing fresh numbers
+// for every sample, Markov chains wander through the space of those numbers, mostly by nudging
+// the ones behind the last path a little. Chains linger where paths carry a lot of light, which
+// finds narrow paths, like caustics seen through glass, far more often than independent samples.
+//
+// A bootstrap pass of independent samples estimates the image's overall brightness and picks the
+// chains' starting points. The chains then make as many mutations in total as the camera's
+// samples per pixel would make samples, splatting each onto the film.
+pub struct Mlt {
+    tracer: PathTracer,
+    bootstrap_samples: u32,
+    chains: u32,
+    // Standard deviation of the small steps that nudge each number
+    sigma: f64,
+    // Chance of a mutation that replaces every number instead
+    large_step_probability: f64,
+}
+
+impl Mlt {
+    pub fn new(limits: DepthLimits) -> Mlt {
+        Mlt {
+            tracer: PathTracer::new(limits),
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    pub fn with_chains(mut self, bootstrap_samples: u32, chains: u32) -> Mlt {
+        self.bootstrap_samples = bootstrap_samples.max(1);
+        self.chains = chains.max(1);
+        self
+    }
+
+    pub fn with_mutation(mut self, sigma: f64, large_step_probability: f64) -> Mlt {
+        self.sigma = sigma;
+        self.large_step_probability = large_step_probability;
+        self
+    }
+
+    // Traces a path through a pixel picked with the sample's first two numbers, returning the
+    // pixel, the camera ray and what was found along it
+    fn trace(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        film: &Film,
+    ) -> ((u32, u32), Ray, CameraSample) {
+        let x = ((random() * film.width() as f64) as u32).min(film.width() - 1);
+        let y = ((random() * film.height() as f64) as u32).min(film.height() - 1);
+        let (ray, sample) = camera.sample_pixel(x, y, |ray| self.tracer.trace(ray, scene));
+        ((x, y), ray, sample)
+    }
+
+    fn sampler(&self, seed: u32) -> PrimarySampleSpace {
+        PrimarySampleSpace::new(seed as u64, self.sigma, self.large_step_probability)
+    }
+}
+
+impl Integrator for Mlt {
+    fn sample(&self, ray: &Ray, scene: &Scene, camera: &Camera, film: &Film) -> CameraSample {
+        self.tracer.sample(ray, scene, camera, film)
+    }
+
+    fn render(&self, scene: &Scene, camera: &Camera, film: &Film) -> f64 {
+        // The brightness of independent samples, each from a seed that replays it
+        let weights: Vec<f64> = (0..self.bootstrap_samples)
+            .map(|seed| {
+                with_sampler(Box::new(self.sampler(seed)), || {
+                    self.trace(scene, camera, film)
+                        .2
+                        .lighting
+                        .total()
+                        .luminance()
+                })
+            })
+            .collect();
+        let bootstrap = Distribution1D::new(weights);
+        let brightness = bootstrap.integral();
+        if brightness <= 0.0 {
+            return 0.0;
+        }
+
+        let pixels = film.width() as u64 * film.height() as u64;
+        let mutations = (pixels * camera.samples_per_pixel as u64).div_ceil(self.chains as u64);
+        let bar = progress_bar(self.chains as u64);
+        parallel_for(self.chains, |chain| {
+            // Start from a bootstrap sample picked by brightness, so the chains begin spread out
+            // as they would be after running for a long time
+            let u = (chain as f64 + 0.5) / self.chains as f64;
+            let seed = bootstrap.sample_discrete(u).0 as u32;
+            let sampler = Rc::new(RefCell::new(self.sampler(seed)));
+
+            with_sampler(Box::new(sampler.clone()), || {
+                let (mut pixel, _, first) = self.trace(scene, camera, film);
+                let mut light = first.lighting;
+                for _ in 0..mutations {
+                    sampler.borrow_mut().start_iteration();
+                    let (proposed_pixel, ray, sample) = self.trace(scene, camera, film);
+                    let proposed = sample.lighting;
+                    // The surface passes take each proposal as a sample of its pixel
+                    if let Some(aovs) = film.aovs() {
+                        let (x, y) = proposed_pixel;
+                        aovs.add_surface(x, y, &ray, sample.hit.as_ref());
+                    }
+
+                    // Both the proposal and the current path are splatted, weighted by how
+                    // likely the chain is to move to the one and stay at the other, which
+                    // averages in rejected proposals rather than wasting them
+                    let f = light.total().luminance();
+                    let proposed_f = proposed.total().luminance();
+                    let accept = if f > 0.0 {
+                        (proposed_f / f).min(1.0)
+                    } else {
+                        1.0
+                    };
+                    if proposed_f > 0.0 {
+                        film.add_lighting_splat(
+                            proposed_pixel.0,
+                            proposed_pixel.1,
+                            &proposed.map(|color| color * (accept / proposed_f)),
+                        );
+                    }
+                    if f > 0.0 {
+                        let scale = (1.0 - accept) / f;
+                        film.add_lighting_splat(
+                            pixel.0,
+                            pixel.1,
+                            &light.map(|color| color * scale),
+                        );
+                    }
+
+                    let mut sampler = sampler.borrow_mut();
+                    if sampler.uniform() < accept {
+                        sampler.accept();
+                        pixel = proposed_pixel;
+                        light = proposed;
+                    } else {
+                        sampler.reject();
+                    }
+                }
+            });
+            bar.inc(1);
+        });
+        bar.finish_with_message("Done!");
+
+        // Every splat has a total weight of one over the path's brightness, so the image is scaled
+        // back up by the brightness and spread over the mutations made for each pixel
+        brightness * pixels as f64 / (mutations * self.chains as u64) as f64
+    }
+}
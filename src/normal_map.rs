@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use crate::{
+    hit::HitRecord,
+    material::{Bounce, Material},
+    ray::Ray,
+    texture::Texture,
+    vec3::{Color, Vec3},
+};
+
+// Wraps a material with a tangent space normal map, which is decoded from [0,1] colors to
+// [-1,1] with +z along the surface normal, +x along the tangent (increasing u) and +y along
+// increasing v. Strength scales the tangent components to exaggerate or flatten the detail.
+pub struct NormalMap {
+    inner: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+    strength: f64,
+}
+
+impl NormalMap {
+    pub fn new(inner: Arc<dyn Material>, map: Arc<dyn Texture>, strength: f64) -> NormalMap {
+        NormalMap {
+            inner,
+            map,
+            strength,
+        }
+    }
+
+    // The hit record with its normal perturbed by the map
+    fn shaded(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let texel = self.map.value(rec.u, rec.v, rec.p);
+        let local = Vec3::new(
+            self.strength * (2.0 * texel.r - 1.0),
+            self.strength * (2.0 * texel.g - 1.0),
+            2.0 * texel.b - 1.0,
+        );
+        let normal = rec.shading_frame().local(local).unit_vector();
+
+        let mut shaded = rec.clone();
+        shaded.normal = ensure_valid_normal(rec.normal, normal, -r_in.direction.unit_vector());
+        shaded
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner
+            .scatter(r_in, &self.shaded(r_in, rec), attenuation, scattered)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.inner.opacity(rec)
+    }
+
+    fn is_spectral(&self) -> bool {
+        self.inner.is_spectral()
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, &self.shaded(r_in, rec), wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        self.inner.pdf(r_in, &self.shaded(r_in, rec), wi)
+    }
+
+    fn bounce(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        self.inner.bounce(r_in, &self.shaded(r_in, rec), scattered)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.inner.shading_normal(r_in, &self.shaded(r_in, rec))
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.albedo(r_in, &self.shaded(r_in, rec))
+    }
+}
+
+// Wraps a material with a height map, whose red channel raises the surface along its normal by
+// up to `scale`. Only the shading normal changes; the geometry stays flat.
+pub struct BumpMap {
+    inner: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMap {
+    // Step in texture space for the finite differences of the height
+    const DELTA: f64 = 1.0 / 1024.0;
+
+    pub fn new(inner: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> BumpMap {
+        BumpMap {
+            inner,
+            height,
+            scale,
+        }
+    }
+
+    // The hit record with its normal perturbed by the height map
+    fn shaded(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let height = |u: f64, v: f64| self.height.value(u, v, rec.p).r;
+        let h = height(rec.u, rec.v);
+        let dh_du = (height(rec.u + BumpMap::DELTA, rec.v) - h) / BumpMap::DELTA;
+        let dh_dv = (height(rec.u, rec.v + BumpMap::DELTA) - h) / BumpMap::DELTA;
+
+        // Tilt the normal against the slope of the height field
+        let normal = rec
+            .shading_frame()
+            .local(Vec3::new(-self.scale * dh_du, -self.scale * dh_dv, 1.0))
+            .unit_vector();
+
+        let mut shaded = rec.clone();
+        shaded.normal = ensure_valid_normal(rec.normal, normal, -r_in.direction.unit_vector());
+        shaded
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner
+            .scatter(r_in, &self.shaded(r_in, rec), attenuation, scattered)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.inner.opacity(rec)
+    }
+
+    fn is_spectral(&self) -> bool {
+        self.inner.is_spectral()
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.inner.eval(r_in, &self.shaded(r_in, rec), wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        self.inner.pdf(r_in, &self.shaded(r_in, rec), wi)
+    }
+
+    fn bounce(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        self.inner.bounce(r_in, &self.shaded(r_in, rec), scattered)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.inner.shading_normal(r_in, &self.shaded(r_in, rec))
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.albedo(r_in, &self.shaded(r_in, rec))
+    }
+}
+
+// Perturbed shading normals can face away from the viewer, or reflect light into the surface,
+// which renders as black patches. This bends the shading normal back towards the geometric
+// normal just far enough that a mirror reflection of the view direction stays above the
+// geometric surface.
+pub fn ensure_valid_normal(geometric: Vec3, shading: Vec3, view: Vec3) -> Vec3 {
+    // The geometric normal itself always passes, since its reflection makes the same angle with
+    // the surface as the view direction does
+    let threshold = (0.9 * view.dot(geometric)).min(0.01);
+    let is_valid = |n: Vec3| {
+        let reflected = 2.0 * n.dot(view) * n - view;
+        n.dot(view) > 0.0 && reflected.dot(geometric) >= threshold
+    };
+    if is_valid(shading) {
+        return shading;
+    }
+
+    // Bisect on the blend between the shading and geometric normals
+    let blend = |t: f64| ((1.0 - t) * shading + t * geometric).unit_vector();
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..12 {
+        let mid = 0.5 * (lo + hi);
+        if is_valid(blend(mid)) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    blend(hi)
+}
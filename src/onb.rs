@@ -0,0 +1,49 @@
+use crate::vec3::Vec3;
+
+// An orthonormal basis, with w aligned to the vector it was built from
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Onb {
+        let w = n.unit_vector();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    // A basis around normal n whose u axis follows the surface tangent t, so that anisotropic
+    // and tangent space effects line up with the surface's parameterization. v = n x t points
+    // along increasing surface v.
+    pub fn from_tangent(n: Vec3, t: Vec3) -> Onb {
+        let w = n.unit_vector();
+        let u = (t - t.dot(w) * w).unit_vector();
+        if u.near_zero() {
+            return Onb::new(n);
+        }
+        Onb {
+            u,
+            v: w.cross(u),
+            w,
+        }
+    }
+
+    // Transforms a vector expressed in this basis into world space
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    // Transforms a world space vector into this basis
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
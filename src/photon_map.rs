@@ -0,0 +1,357 @@
+use std::sync::Mutex;
+
+use crate::{
+    hit::{HitRecord, Hittable},
+    integrator::scatter,
+    ray::Ray,
+    sampler::random,
+    scene::Scene,
+    spectrum::SampledWavelengths,
+    util::{parallel_for, Interval, PI},
+    vec3::{Color, Vec3},
+};
+
+type Point3 = Vec3;
+
+// A packet of light from one of the scene's lights, stored where it landed on a surface
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub p: Point3,
+    // Unit vector back the way the photon came
+    pub direction: Vec3,
+    pub power: Color,
+    // Normal of the surface it landed on, on the side it arrived from
+    pub normal: Vec3,
+}
+
+// Photons in a balanced kd-tree, for finding the ones near a point. Each subtree occupies a range
+// of the array with its root in the middle, which splits the rest along axes[middle].
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    // Photons traced by each thread at a time
+    const BATCH: usize = 4096;
+
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    // Traces `count` photons from the scene's lights, picked by power, and keeps those that reach
+    // a diffuse surface only by way of specular ones, such as through glass or off a mirror. These
+    // are the caustics, which paths from the camera struggle to find. Materials without a density
+    // for the direction they scatter in count as specular.
+    //
+    // When `spectral` is set each photon carries its own sampled wavelengths, so dispersive glass
+    // splits its caustics into colors, and is stored as its estimate in RGB.
+    pub fn trace_caustics(
+        scene: &Scene,
+        count: usize,
+        max_depth: u32,
+        spectral: bool,
+    ) -> PhotonMap {
+        let photons = Mutex::new(Vec::new());
+        parallel_for(count.div_ceil(Self::BATCH) as u32, |batch| {
+            let start = batch as usize * Self::BATCH;
+            let traced: Vec<Photon> = (start..count.min(start + Self::BATCH))
+                .filter_map(|_| trace_caustic(scene, count, max_depth, spectral))
+                .collect();
+            photons.lock().unwrap().extend(traced);
+        });
+        PhotonMap::new(photons.into_inner().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    // Up to `count` of the photons nearest to p within max_distance, closest first, with the
+    // squared distance that the search ended up covering
+    pub fn nearest(&self, p: Point3, count: usize, max_distance: f64) -> (Vec<&Photon>, f64) {
+        let mut found = Vec::with_capacity(count + 1);
+        let mut max_squared = max_distance * max_distance;
+        if count > 0 {
+            self.search(
+                p,
+                0,
+                self.photons.len(),
+                count,
+                &mut found,
+                &mut max_squared,
+            );
+        }
+        let photons = found.iter().map(|&(_, i)| &self.photons[i]).collect();
+        (photons, max_squared)
+    }
+
+    // Estimates the light the photons near the hit carry back along the ray, from the density of
+    // the nearest `count` within max_distance
+    pub fn radiance(&self, ray: &Ray, rec: &HitRecord, count: usize, max_distance: f64) -> Color {
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        let (photons, radius_squared) = self.nearest(rec.p, count, max_distance);
+        if photons.is_empty() {
+            return total;
+        }
+
+        for photon in photons {
+            // Skip photons on surfaces facing another way, like a wall where it meets the floor
+            let cos_theta = rec.normal.dot(photon.direction).abs();
+            if photon.normal.dot(rec.normal) < 0.7 || cos_theta <= 0.0 {
+                continue;
+            }
+            let f = rec.material.eval(ray, rec, photon.direction) / cos_theta;
+            total += ray.reflectance(&*rec.material, f) * ray.radiance(photon.power);
+        }
+        total / (PI * radius_squared)
+    }
+
+    // Collects the nearest photons in the subtree spanning start..end into found, sorted by
+    // squared distance and shrinking max_squared once it's full
+    fn search(
+        &self,
+        p: Point3,
+        start: usize,
+        end: usize,
+        count: usize,
+        found: &mut Vec<(f64, usize)>,
+        max_squared: &mut f64,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        let axis = self.axes[middle] as usize;
+
+        // Search the side of the split containing p first, then the other side if it's close
+        let delta = p[axis] - photon.p[axis];
+        let (near, far) = if delta < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search(p, near.0, near.1, count, found, max_squared);
+
+        let distance_squared = (photon.p - p).length_squared();
+        if distance_squared < *max_squared {
+            let at = found.partition_point(|&(d, _)| d <= distance_squared);
+            found.insert(at, (distance_squared, middle));
+            if found.len() > count {
+                found.pop();
+            }
+            if found.len() == count {
+                *max_squared = found[count - 1].0;
+            }
+        }
+
+        if delta * delta < *max_squared {
+            self.search(p, far.0, far.1, count, found, max_squared);
+        }
+    }
+}
+
+// Traces one of `count` photons, returning it if it lands on a diffuse surface as a caustic
+fn trace_caustic(scene: &Scene, count: usize, max_depth: u32, spectral: bool) -> Option<Photon> {
+    let bounds = scene.world.bounding_box();
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+    let (index, pmf) = scene.emission_sampler().sample(zero, zero, random())?;
+    let emission = scene.lights[index]
+        .sample_emission(&bounds)
+        .filter(|emission| emission.pdf_position > 0.0 && emission.pdf_direction > 0.0)?;
+
+    let mut ray = emission.ray;
+    if spectral {
+        ray.wavelengths = Some(SampledWavelengths::sample(random()));
+    }
+    let cos_theta = if emission.normal.near_zero() {
+        1.0
+    } else {
+        emission.normal.dot(ray.direction).abs()
+    };
+    let mut power = ray.radiance(emission.radiance)
+        * (cos_theta / (pmf * emission.pdf_position * emission.pdf_direction * count as f64));
+
+    let mut specular = false;
+    for _ in 0..max_depth {
+        let mut rec = HitRecord::new();
+        if !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return None;
+        }
+        let (scattered, attenuation) = scatter(&ray, &rec)?;
+
+        if rec
+            .material
+            .pdf(&ray, &rec, scattered.direction.unit_vector())
+            > 0.0
+        {
+            // Light reaching a diffuse surface straight from a light is direct lighting, which is
+            // sampled from the camera instead
+            return specular.then(|| Photon {
+                p: rec.p,
+                direction: -ray.direction.unit_vector(),
+                power: match ray.wavelengths {
+                    Some(wavelengths) => wavelengths.to_rgb(power),
+                    None => power,
+                },
+                normal: rec.normal,
+            });
+        }
+
+        specular = true;
+        power = power * attenuation;
+        if power.average() <= 0.0 {
+            return None;
+        }
+        ray = scattered;
+    }
+    None
+}
+
+// Arranges photons into a balanced kd-tree, splitting each range at its median along the axis
+// where it's widest
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let mut min = photons[0].p;
+    let mut max = photons[0].p;
+    for photon in photons.iter() {
+        min = Vec3::new(
+            min.x.min(photon.p.x),
+            min.y.min(photon.p.y),
+            min.z.min(photon.p.z),
+        );
+        max = Vec3::new(
+            max.x.max(photon.p.x),
+            max.y.max(photon.p.y),
+            max.z.max(photon.p.z),
+        );
+    }
+    let extent = max - min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[middle] = axis as u8;
+
+    let (left, rest) = photons.split_at_mut(middle);
+    let (left_axes, rest_axes) = axes.split_at_mut(middle);
+    build(left, left_axes);
+    build(&mut rest[1..], &mut rest_axes[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        disk::Disk, hit::Hittables, light::SpotLight, material::Dielectric, sphere::Sphere,
+        test_util::grey,
+    };
+
+    #[test]
+    fn spectral_caustics_carry_the_same_power() {
+        // A glass ball focusing a spot light that only just covers it onto the floor below
+        let mut world = Hittables::new();
+        world.add(Box::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        world.add(Box::new(Disk::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            10.0,
+            grey(),
+        )));
+        let mut scene = Scene::new(world);
+        scene.add_light(Box::new(SpotLight::new(
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 0.5, 0.25),
+            15.0,
+            15.0,
+        )));
+
+        let power = |spectral: bool| {
+            let map = PhotonMap::trace_caustics(&scene, 50_000, 8, spectral);
+            assert!(!map.is_empty());
+            let mut total = Color::new(0.0, 0.0, 0.0);
+            for photon in &map.photons {
+                total += photon.power;
+            }
+            total
+        };
+        let (rgb, spectral) = (power(false), power(true));
+        for (rgb, spectral) in [
+            (rgb.r, spectral.r),
+            (rgb.g, spectral.g),
+            (rgb.b, spectral.b),
+        ] {
+            assert!(
+                (spectral / rgb - 1.0).abs() < 0.03,
+                "{} against {}",
+                spectral,
+                rgb
+            );
+        }
+    }
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut point = || Vec3::new(rng.gen(), rng.gen(), rng.gen());
+        let photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon {
+                p: point(),
+                direction: Vec3::new(0.0, 1.0, 0.0),
+                power: Color::new(1.0, 1.0, 1.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+
+        for _ in 0..50 {
+            let p = point();
+            for (count, max_distance) in [(0, 1.0), (1, 1.0), (20, 1.0), (20, 0.05), (5000, 0.2)] {
+                let mut expected: Vec<f64> = photons
+                    .iter()
+                    .map(|photon| (photon.p - p).length_squared())
+                    .filter(|&d| d < max_distance * max_distance)
+                    .collect();
+                expected.sort_by(f64::total_cmp);
+                expected.truncate(count);
+
+                let (found, max_squared) = map.nearest(p, count, max_distance);
+                let found: Vec<f64> = found
+                    .iter()
+                    .map(|photon| (photon.p - p).length_squared())
+                    .collect();
+                assert_eq!(found, expected);
+                // A full search narrows to the furthest photon found, and otherwise covers it all
+                if count > 0 && found.len() == count {
+                    assert_eq!(max_squared, found[count - 1]);
+                } else {
+                    assert_eq!(max_squared, max_distance * max_distance);
+                }
+            }
+        }
+    }
+}
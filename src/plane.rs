@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::{Aabb, UNIVERSE_AABB},
+    csg::{Solid, Span},
+    hit::{HitRecord, Hittable},
+    material::Material,
+    onb::Onb,
+    ray::Ray,
+    util::Interval,
+    vec3::Vec3,
+};
+
+// An infinite plane through `point` with the given normal. As a solid it is the half-space behind
+// the plane, opposite the normal.
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Arc<dyn Material>,
+    frame: Onb,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Arc<dyn Material>) -> Plane {
+        let frame = Onb::new(normal);
+        Plane {
+            point,
+            normal: frame.w,
+            material,
+            frame,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let denom = r.direction.dot(self.normal);
+        if denom.abs() < 1e-12 {
+            return false;
+        }
+
+        let t = (self.point - r.origin).dot(self.normal) / denom;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.set_face_normal(r, self.normal);
+        // The plane has no natural extent, so UVs are in world units and textures tile
+        let local = self.frame.to_local(rec.p - self.point);
+        rec.u = local.x;
+        rec.v = local.y;
+        rec.material = Arc::clone(&self.material);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // Only an axis-aligned plane is bounded along any axis
+        let mut bbox = UNIVERSE_AABB;
+        for axis in 0..3 {
+            if self.normal[axis].abs() > 1.0 - 1e-9 {
+                let at = Interval::new(self.point[axis], self.point[axis]).expand(0.0001);
+                match axis {
+                    0 => bbox.x = at,
+                    1 => bbox.y = at,
+                    _ => bbox.z = at,
+                }
+            }
+        }
+        bbox
+    }
+}
+
+impl Solid for Plane {
+    fn solid_bounding_box(&self) -> Aabb {
+        UNIVERSE_AABB
+    }
+
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        let boundary = |t: f64, p: Vec3| {
+            let local = self.frame.to_local(p - self.point);
+            HitRecord {
+                p,
+                normal: self.normal,
+                material: Arc::clone(&self.material),
+                t,
+                u: local.x,
+                v: local.y,
+                ..HitRecord::new()
+            }
+        };
+
+        let denom = r.direction.dot(self.normal);
+        let height = (r.origin - self.point).dot(self.normal);
+        if denom.abs() < 1e-12 {
+            // Parallel to the plane: either always inside or never
+            if height >= 0.0 {
+                return Vec::new();
+            }
+            return vec![Span {
+                enter: boundary(f64::NEG_INFINITY, r.origin),
+                exit: boundary(f64::INFINITY, r.origin),
+            }];
+        }
+
+        let t = -height / denom;
+        let crossing = boundary(t, r.at(t));
+        if denom < 0.0 {
+            vec![Span {
+                enter: crossing,
+                exit: boundary(f64::INFINITY, r.origin),
+            }]
+        } else {
+            vec![Span {
+                enter: boundary(f64::NEG_INFINITY, r.origin),
+                exit: crossing,
+            }]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    // The plane z = 0 facing +z
+    fn plane() -> Plane {
+        Plane::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), grey())
+    }
+
+    #[test]
+    fn ray_parallel_to_plane() {
+        let above = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 1.0, 0.0));
+        assert!(hit(&plane(), &above).is_none());
+        let within = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&plane(), &within).is_none());
+    }
+
+    #[test]
+    fn ray_at_grazing_angle() {
+        // Descending 1 unit over 10000 hits far away, with the normal facing the ray
+        let above = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1e-4));
+        let rec = hit(&plane(), &above).expect("grazing ray from above should hit");
+        assert!((rec.t - 1e4).abs() < 1e-6);
+        assert!((rec.p.x - 1e4).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        let below = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1e-4));
+        let rec = hit(&plane(), &below).expect("grazing ray from below should hit");
+        assert!((rec.t - 1e4).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(!rec.front_face);
+    }
+}
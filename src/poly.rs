@@ -0,0 +1,101 @@
+// Real root finders for the low order polynomials that come up in ray-surface intersection.
+// Roots are returned in ascending order.
+
+// Solves a*x^2 + b*x + c = 0
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+
+    // Avoids the catastrophic cancellation of the textbook formula when b^2 >> 4ac
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (x0, x1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    if x0 < x1 {
+        vec![x0, x1]
+    } else {
+        vec![x1, x0]
+    }
+}
+
+// Solves x^3 + a*x^2 + b*x + c = 0
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let q3 = q * q * q;
+
+    let mut roots = if r * r < q3 {
+        let theta = (r / q3.sqrt()).clamp(-1.0, 1.0).acos();
+        let m = -2.0 * q.sqrt();
+        let tau = 2.0 * std::f64::consts::PI;
+        vec![
+            m * (theta / 3.0).cos() - a / 3.0,
+            m * ((theta + tau) / 3.0).cos() - a / 3.0,
+            m * ((theta - tau) / 3.0).cos() - a / 3.0,
+        ]
+    } else {
+        let big_a = -r.signum() * (r.abs() + (r * r - q3).sqrt()).cbrt();
+        let big_b = if big_a != 0.0 { q / big_a } else { 0.0 };
+        vec![big_a + big_b - a / 3.0]
+    };
+
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
+
+// Solves x^4 + a*x^3 + b*x^2 + c*x + d = 0 using Ferrari's method, then polishes each root with
+// a few Newton iterations since the closed form loses precision for nearly tangent rays.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depress the quartic with x = y - a/4, giving y^4 + p*y^2 + q*y + r = 0
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut ys = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // Biquadratic: solve for y^2
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                let s = z.sqrt();
+                ys.push(-s);
+                ys.push(s);
+            }
+        }
+    } else {
+        // Any positive root of the resolvent cubic splits the quartic into two quadratics
+        let resolvent = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0);
+        let m = match resolvent.last() {
+            Some(&m) if m > 0.0 => m,
+            _ => return Vec::new(),
+        };
+        let s = (2.0 * m).sqrt();
+        ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+        ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+    }
+
+    let mut roots: Vec<f64> = ys
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..3 {
+                let f = (((x + a) * x + b) * x + c) * x + d;
+                let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+                if df.abs() < 1e-12 {
+                    break;
+                }
+                x -= f / df;
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(|x, y| x.total_cmp(y));
+    roots
+}
@@ -0,0 +1,484 @@
+use std::sync::Arc;
+
+use crate::{
+    hit::HitRecord,
+    material::{Bounce, Material},
+    microfacet::{fresnel_dielectric, Ggx},
+    ray::Ray,
+    sampler::random,
+    texture::{SolidColor, Texture},
+    util::PI,
+    vec3::{Color, Vec3},
+};
+
+// A Disney style "principled" BSDF: a single material whose intuitive parameters blend between
+// diffuse, metallic, glossy, clearcoated and transmissive looks. Every parameter is a texture;
+// scalar parameters read the texture's red channel.
+//
+// Lobes are importance sampled with one-sample MIS: a lobe is picked in proportion to its
+// estimated contribution, a direction is sampled from it, and the result is weighted by the full
+// BSDF over the combined density of all lobes.
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    specular_tint: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    sheen_tint: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_gloss: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    ior: Arc<dyn Texture>,
+}
+
+impl Principled {
+    // A rough, non-metallic dielectric with the given base color. Other parameters start at the
+    // defaults of the Disney BRDF and can be changed with the with_* methods.
+    pub fn new(base_color: Arc<dyn Texture>) -> Principled {
+        let constant = |value: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::gray(value)) };
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Principled {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Principled {
+        self.roughness = roughness;
+        self
+    }
+
+    // Reflectance of the dielectric specular lobe at normal incidence; 0.5 maps to 4%
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>) -> Principled {
+        self.specular = specular;
+        self
+    }
+
+    // Tints dielectric specular reflection towards the base color
+    pub fn with_specular_tint(mut self, specular_tint: Arc<dyn Texture>) -> Principled {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    // Extra grazing retroreflection, as seen on cloth
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>) -> Principled {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_sheen_tint(mut self, sheen_tint: Arc<dyn Texture>) -> Principled {
+        self.sheen_tint = sheen_tint;
+        self
+    }
+
+    // Strength of a second, colorless specular layer
+    pub fn with_clearcoat(mut self, clearcoat: Arc<dyn Texture>) -> Principled {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn with_clearcoat_gloss(mut self, clearcoat_gloss: Arc<dyn Texture>) -> Principled {
+        self.clearcoat_gloss = clearcoat_gloss;
+        self
+    }
+
+    // Fraction of the non-metallic part that transmits like rough glass, tinted by the base color
+    pub fn with_transmission(mut self, transmission: Arc<dyn Texture>) -> Principled {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn with_ior(mut self, ior: Arc<dyn Texture>) -> Principled {
+        self.ior = ior;
+        self
+    }
+
+    // Looks up every parameter at the hit point
+    fn lobes(&self, rec: &HitRecord) -> Lobes {
+        let scalar = |texture: &Arc<dyn Texture>| texture.value(rec.u, rec.v, rec.p).r;
+
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let metallic = scalar(&self.metallic).clamp(0.0, 1.0);
+        let roughness = scalar(&self.roughness).clamp(0.0, 1.0);
+        let specular = scalar(&self.specular).max(0.0);
+        let specular_tint = scalar(&self.specular_tint).clamp(0.0, 1.0);
+        let sheen = scalar(&self.sheen).max(0.0);
+        let sheen_tint = scalar(&self.sheen_tint).clamp(0.0, 1.0);
+        let clearcoat = scalar(&self.clearcoat).max(0.0);
+        let clearcoat_gloss = scalar(&self.clearcoat_gloss).clamp(0.0, 1.0);
+        let transmission = scalar(&self.transmission).clamp(0.0, 1.0);
+        let ior = scalar(&self.ior).max(1.0);
+
+        // Hue and saturation of the base color, without its luminance
+        let white = Color::new(1.0, 1.0, 1.0);
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            white
+        };
+
+        let dielectric_spec = 0.08 * specular * white.lerp(tint, specular_tint);
+        Lobes {
+            base_color,
+            roughness,
+            specular_color: dielectric_spec.lerp(base_color, metallic),
+            sheen_color: sheen * white.lerp(tint, sheen_tint),
+            clearcoat,
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * clearcoat_gloss,
+            ior,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            specular_weight: 1.0 - (1.0 - metallic) * transmission,
+            transmission_weight: (1.0 - metallic) * transmission,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let lobes = self.lobes(rec);
+        let eta = if rec.front_face {
+            lobes.ior
+        } else {
+            1.0 / lobes.ior
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        let probabilities = lobes.probabilities(wo);
+        let total: f64 = probabilities.iter().sum();
+        if total <= 0.0 {
+            return false;
+        }
+
+        // Pick a lobe in proportion to its estimated contribution
+        let mut choice = random() * total;
+        let mut lobe = probabilities.len() - 1;
+        for (i, p) in probabilities.iter().enumerate() {
+            if choice < *p {
+                lobe = i;
+                break;
+            }
+            choice -= p;
+        }
+
+        let wi = match lobe {
+            DIFFUSE => Vec3::random_cosine_direction(),
+            SPECULAR => {
+                let h = lobes
+                    .distribution
+                    .sample_visible_normal(wo, random(), random());
+                (-wo).reflect(h)
+            }
+            TRANSMISSION => {
+                let h = lobes
+                    .distribution
+                    .sample_visible_normal(wo, random(), random());
+                if random() < fresnel_dielectric(wo.dot(h), eta) {
+                    (-wo).reflect(h)
+                } else {
+                    (-wo).refract(&h, 1.0 / eta)
+                }
+            }
+            _ => {
+                let h = sample_gtr1(lobes.clearcoat_alpha);
+                (-wo).reflect(h)
+            }
+        };
+        if wi.z == 0.0 {
+            return false;
+        }
+
+        let (f, pdf) = lobes.eval(wo, wi, eta, &probabilities, total);
+        if pdf <= 0.0 {
+            return false;
+        }
+
+        *scattered = Ray::new(rec.p, frame.local(wi));
+        *attenuation = f / pdf;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let lobes = self.lobes(rec);
+        let eta = if rec.front_face {
+            lobes.ior
+        } else {
+            1.0 / lobes.ior
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let probabilities = lobes.probabilities(wo);
+        let total: f64 = probabilities.iter().sum();
+        let (f, _) = lobes.eval(wo, wi, eta, &probabilities, total);
+        f
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let lobes = self.lobes(rec);
+        let eta = if rec.front_face {
+            lobes.ior
+        } else {
+            1.0 / lobes.ior
+        };
+
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        let probabilities = lobes.probabilities(wo);
+        let total: f64 = probabilities.iter().sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let (_, pdf) = lobes.eval(wo, wi, eta, &probabilities, total);
+        pdf
+    }
+
+    fn bounce(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(-r_in.direction.unit_vector());
+        let wi = frame.to_local(scattered.direction.unit_vector());
+        if wi.z < 0.0 {
+            return Bounce::Transmission;
+        }
+
+        // Which lobe sampled wi isn't known any more, so pick one with the probability that it
+        // was the diffuse lobe
+        let lobes = self.lobes(rec);
+        let eta = if rec.front_face {
+            lobes.ior
+        } else {
+            1.0 / lobes.ior
+        };
+        let probabilities = lobes.probabilities(wo);
+        let total: f64 = probabilities.iter().sum();
+        let (_, pdf) = lobes.eval(wo, wi, eta, &probabilities, total);
+        let diffuse_pdf = probabilities[DIFFUSE] / total * wi.z / PI;
+        if pdf > 0.0 && random() * pdf < diffuse_pdf {
+            Bounce::Diffuse
+        } else {
+            Bounce::Specular
+        }
+    }
+
+    fn albedo(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base_color.value(rec.u, rec.v, rec.p)
+    }
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const TRANSMISSION: usize = 2;
+
+// The principled parameters at a single hit point
+struct Lobes {
+    base_color: Color,
+    roughness: f64,
+    specular_color: Color,
+    sheen_color: Color,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    ior: f64,
+    distribution: Ggx,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    transmission_weight: f64,
+}
+
+impl Lobes {
+    // Unnormalized probabilities of sampling the diffuse, specular, transmission and clearcoat
+    // lobes, estimated from their albedo as seen from wo
+    fn probabilities(&self, wo: Vec3) -> [f64; 4] {
+        let specular_albedo = schlick(self.specular_color, wo.z).luminance();
+        let clearcoat_albedo = 0.25 * self.clearcoat * schlick_scalar(0.04, wo.z);
+        [
+            self.diffuse_weight * (self.base_color.luminance() + self.sheen_color.luminance()),
+            self.specular_weight * specular_albedo,
+            self.transmission_weight,
+            clearcoat_albedo,
+        ]
+    }
+
+    // Returns the BSDF times the cosine of wi with the normal, and the density of sampling wi
+    // across all of the lobes
+    fn eval(
+        &self,
+        wo: Vec3,
+        wi: Vec3,
+        eta: f64,
+        probabilities: &[f64; 4],
+        total: f64,
+    ) -> (Color, f64) {
+        let mut f = Color::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+        let cos_o = wo.z;
+        let cos_i = wi.z;
+
+        if cos_i > 0.0 {
+            let h = (wo + wi).unit_vector();
+            let cos_d = wi.dot(h);
+
+            // Diffuse with Disney's roughness dependent retroreflection, plus sheen
+            if self.diffuse_weight > 0.0 {
+                let fl = schlick_weight(cos_i);
+                let fv = schlick_weight(cos_o);
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let diffuse =
+                    self.base_color / PI * ((1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv));
+                let sheen = self.sheen_color * schlick_weight(cos_d);
+                f += self.diffuse_weight * cos_i * (diffuse + sheen);
+            }
+            pdf += probabilities[DIFFUSE] / total * cos_i / PI;
+
+            // GGX reflection; D * G * F / (4 cos_o cos_i), times cos_i
+            let d = self.distribution.d(h);
+            let g = self.distribution.g(wo, wi);
+            let specular_pdf = self.distribution.visible_d(wo, h) / (4.0 * wo.dot(h));
+            if self.specular_weight > 0.0 {
+                let fresnel = schlick(self.specular_color, cos_d);
+                f += self.specular_weight * d * g / (4.0 * cos_o) * fresnel;
+            }
+            pdf += probabilities[SPECULAR] / total * specular_pdf;
+
+            // The reflected part of the transmission lobe
+            let fresnel = fresnel_dielectric(wo.dot(h), eta);
+            if self.transmission_weight > 0.0 {
+                f += Color::new(1.0, 1.0, 1.0)
+                    * (self.transmission_weight * fresnel * d * g / (4.0 * cos_o));
+            }
+            pdf += probabilities[TRANSMISSION] / total * fresnel * specular_pdf;
+
+            // Clearcoat: GTR1 distribution with a fixed 4% reflectance and fixed roughness masking
+            if self.clearcoat > 0.0 {
+                let coat = Ggx::new(0.25, 0.25);
+                let dc = gtr1(h.z, self.clearcoat_alpha);
+                let gc = coat.g1(wo) * coat.g1(wi);
+                let fc = schlick_scalar(0.04, cos_d);
+                f += Color::new(1.0, 1.0, 1.0)
+                    * (0.25 * self.clearcoat * dc * gc * fc / (4.0 * cos_o));
+                pdf += probabilities[3] / total * dc * h.z / (4.0 * wo.dot(h));
+            }
+        } else if self.transmission_weight > 0.0 {
+            // Refraction through the microfacet with the generalized half vector (Walter et al.)
+            let mut h = (wo + eta * wi).unit_vector();
+            if h.z < 0.0 {
+                h = -h;
+            }
+            let cos_oh = wo.dot(h);
+            let cos_ih = wi.dot(h);
+            if cos_oh <= 0.0 || cos_ih >= 0.0 {
+                return (f, pdf);
+            }
+
+            let denom = cos_ih + cos_oh / eta;
+            let denom = denom * denom;
+            let transmittance = 1.0 - fresnel_dielectric(cos_oh, eta);
+            let d = self.distribution.d(h);
+            let g = self.distribution.g(wo, wi);
+            let ft = transmittance * d * g * (cos_ih * cos_oh).abs() / (cos_o * denom);
+            f += self.transmission_weight * ft * self.base_color;
+
+            let dh_dwi = cos_ih.abs() / denom;
+            pdf += probabilities[TRANSMISSION] / total
+                * transmittance
+                * self.distribution.visible_d(wo, h)
+                * dh_dwi;
+        }
+
+        (f, pdf)
+    }
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+fn schlick(f0: Color, cos: f64) -> Color {
+    f0.lerp(Color::new(1.0, 1.0, 1.0), schlick_weight(cos))
+}
+
+fn schlick_scalar(f0: f64, cos: f64) -> f64 {
+    f0 + (1.0 - f0) * schlick_weight(cos)
+}
+
+// The "generalized Trowbridge-Reitz" distribution with gamma = 1, which has the long tails used
+// for the clearcoat highlight
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    if alpha >= 1.0 {
+        return 1.0 / PI;
+    }
+    let a2 = alpha * alpha;
+    let t = 1.0 + (a2 - 1.0) * cos_h * cos_h;
+    (a2 - 1.0) / (PI * a2.ln() * t)
+}
+
+// Samples a half vector proportional to gtr1(h.z) * h.z
+fn sample_gtr1(alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let u1 = random();
+    let u2 = random();
+
+    let cos_theta = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_scatter_matches_eval, hit_at_origin};
+
+    #[test]
+    fn scatter_matches_eval_across_lobes() {
+        let constant = |value: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::gray(value)) };
+        // Every lobe gets some weight, so each sample is weighted by the mixture of all of them
+        let principled = Principled::new(Arc::new(SolidColor::new(Color::new(0.8, 0.3, 0.2))))
+            .with_metallic(constant(0.3))
+            .with_roughness(constant(0.5))
+            .with_sheen(constant(0.5))
+            .with_clearcoat(constant(1.0))
+            .with_transmission(constant(0.5));
+        let principled: Arc<dyn Material> = Arc::new(principled);
+        for front_face in [true, false] {
+            let (r_in, rec) = hit_at_origin(Arc::clone(&principled), front_face);
+            let (reflected, transmitted) = assert_scatter_matches_eval(&r_in, &rec, 4000);
+            assert!(reflected > 0 && transmitted > 0);
+        }
+    }
+}
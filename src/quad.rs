@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use crate::{
+    aabb::Aabb,
     hit::{HitRecord, Hittable},
-    material::{Lambertian, Material},
+    material::Material,
     ray::Ray,
     util::Interval,
-    vec3::{Color, Vec3},
+    vec3::Vec3,
 };
 
+#[derive(Clone)]
 pub struct Quad {
     pub a: Vec3,
     pub b: Vec3,
@@ -29,6 +31,18 @@ impl Quad {
             material,
         }
     }
+
+    // Projects p onto the a->b and a->d edges, so that a maps to (0,0) and c to (1,1) for
+    // parallelograms
+    fn get_quad_uv(&self, p: Vec3) -> (f64, f64) {
+        let edge_u = self.b - self.a;
+        let edge_v = self.d - self.a;
+        let offset = p - self.a;
+        (
+            offset.dot(edge_u) / edge_u.length_squared(),
+            offset.dot(edge_v) / edge_v.length_squared(),
+        )
+    }
 }
 
 impl Hittable for Quad {
@@ -57,10 +71,19 @@ impl Hittable for Quad {
             rec.p = p;
             rec.normal = normal;
             rec.set_face_normal(r, normal);
+            (rec.u, rec.v) = self.get_quad_uv(p);
+            rec.tangent = (self.b - self.a).unit_vector();
             rec.material = Arc::clone(&self.material);
             return true;
         }
 
         false
     }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::surrounding(
+            &Aabb::from_points(self.a, self.c),
+            &Aabb::from_points(self.b, self.d),
+        )
+    }
 }
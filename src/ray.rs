@@ -1,6 +1,6 @@
 use crate::{
-    hit::{HitRecord, Hittable},
-    util::Interval,
+    material::Material,
+    spectrum::SampledWavelengths,
     vec3::{Color, Vec3},
 };
 
@@ -12,11 +12,18 @@ type Point3 = Vec3;
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    // Set in spectral mode, in which case colors along the ray's path are values at these
+    // wavelengths rather than RGB
+    pub wavelengths: Option<SampledWavelengths>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelengths: None,
+        }
     }
 
     // Returns the point along the ray at time/scalar t
@@ -24,30 +31,22 @@ impl Ray {
         self.origin + self.direction * t
     }
 
-    pub fn color(&self, world: &dyn Hittable, depth: u32) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            return Color::new(0.0, 1.0, 0.0);
+    // Most materials work in RGB, so in spectral mode their colors are upsampled to the path's
+    // wavelengths
+    pub fn reflectance(&self, material: &dyn Material, rgb: Color) -> Color {
+        match self.wavelengths {
+            Some(wavelengths) if !material.is_spectral() => wavelengths.albedo(rgb),
+            _ => rgb,
         }
+    }
 
-        let mut rec = HitRecord::new();
-
-        // If the ray hits an object, scatter the ray and return the color of the scattered ray
-        if world.hit(self, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
-            let mut attenuation = Color::new(0.0, 0.0, 0.0);
-            if rec
-                .material
-                .scatter(self, &rec, &mut attenuation, &mut scattered)
-            {
-                return attenuation * scattered.color(world, depth - 1);
-            }
-            return Color::new(0.0, 0.0, 0.0);
+    // Light sources and environments are given in RGB, upsampled to the path's wavelengths in
+    // spectral mode
+    pub fn radiance(&self, rgb: Color) -> Color {
+        match self.wavelengths {
+            Some(wavelengths) => wavelengths.illuminant(rgb),
+            None => rgb,
         }
-
-        let unit_direction = self.direction.unit_vector();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
     }
 
     pub fn hit_sphere(&self, center: Point3, radius: f64) -> f64 {
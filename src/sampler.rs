@@ -0,0 +1,220 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::util::PI;
+
+// A stream of random numbers. Everything that makes a random choice, from the camera's jitter to
+// the materials, lights and integrators, calls `random`, which draws from the sampler installed on
+// the current thread, or from the thread's own generator when there isn't one. Integrators that
+// need to replay or perturb the choices made for a sample, like Metropolis light transport,
+// install their own.
+pub trait Sampler {
+    // The next number, in [0,1)
+    fn next(&mut self) -> f64;
+}
+
+// Shared so that whoever installs a sampler can keep a handle to it
+impl<S: Sampler> Sampler for Rc<RefCell<S>> {
+    fn next(&mut self) -> f64 {
+        self.borrow_mut().next()
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Box<dyn Sampler>>> = const { RefCell::new(None) };
+}
+
+// A uniform random number in [0,1) from the current thread's sampler
+pub fn random() -> f64 {
+    CURRENT.with(|current| match current.borrow_mut().as_mut() {
+        Some(sampler) => sampler.next(),
+        None => rand::random::<f64>(),
+    })
+}
+
+// Runs f with every call to `random` on this thread drawing from sampler, then puts back whatever
+// was installed before
+pub fn with_sampler<R>(sampler: Box<dyn Sampler>, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT.replace(Some(sampler));
+    let result = f();
+    CURRENT.set(previous);
+    result
+}
+
+// The numbers consumed by one sample, as a point in primary sample space for Kelemen-style
+// Metropolis light transport. Each iteration proposes a new point, either a large step that
+// replaces every number or a small step that nudges each one, which is then accepted or rolled
+// back. Numbers are only made or mutated when first asked for in an iteration, so samples that use
+// more of them than the last one did still work.
+//
+// The first point comes from a large step seeded by `seed`, so it can be found again later.
+pub struct PrimarySampleSpace {
+    rng: StdRng,
+    // Standard deviation of small steps
+    sigma: f64,
+    large_step_probability: f64,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    // Iteration that last changed the value
+    modified: u64,
+    value_backup: f64,
+    modified_backup: u64,
+}
+
+impl PrimarySampleSpace {
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> PrimarySampleSpace {
+        PrimarySampleSpace {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            samples: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    // Starts proposing a mutation of the current point
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    // Goes back to the point before the last start_iteration
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.value_backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    // A random number for the integrator's own choices, like whether to accept a proposal, that
+    // isn't part of the point
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+
+    // Brings the number at index up to date with the current iteration
+    fn ensure_ready(&mut self, index: usize) {
+        // Numbers never asked for before are independent of the point so far, so start out as
+        // though the last large step had made them
+        while index >= self.samples.len() {
+            self.samples.push(PrimarySample {
+                value: self.rng.gen::<f64>(),
+                modified: self.last_large_step,
+                ..PrimarySample::default()
+            });
+        }
+        let sample = &mut self.samples[index];
+
+        // Numbers last set before the latest accepted large step were replaced by it
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen::<f64>();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.value_backup = sample.value;
+        sample.modified_backup = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.gen::<f64>();
+        } else {
+            // Small steps missed while the number wasn't used add up to one wider step
+            let steps = (self.iteration - sample.modified) as f64;
+            let u1 = 1.0 - self.rng.gen::<f64>();
+            let u2 = self.rng.gen::<f64>();
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            let value = sample.value + normal * self.sigma * steps.sqrt();
+            sample.value = value - value.floor();
+        }
+        sample.modified = self.iteration;
+    }
+}
+
+impl Sampler for PrimarySampleSpace {
+    fn next(&mut self) -> f64 {
+        self.ensure_ready(self.index);
+        self.index += 1;
+        self.samples[self.index - 1].value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The numbers making up the current point. Those left over from before the last accepted
+    // large step have been replaced by it, but don't get their new values until they're used.
+    fn values(space: &PrimarySampleSpace) -> Vec<Option<f64>> {
+        space
+            .samples
+            .iter()
+            .map(|sample| (sample.modified >= space.last_large_step).then_some(sample.value))
+            .collect()
+    }
+
+    #[test]
+    fn seed_replays_the_first_sample() {
+        let first = |seed: u64| {
+            let mut space = PrimarySampleSpace::new(seed, 0.01, 0.3);
+            (0..16).map(|_| space.next()).collect::<Vec<f64>>()
+        };
+        assert_eq!(first(7), first(7));
+        assert_ne!(first(7), first(8));
+    }
+
+    #[test]
+    fn reject_restores_the_point() {
+        // Only small steps, then only large ones
+        for large_step_probability in [0.0, 1.0] {
+            let mut space = PrimarySampleSpace::new(1, 0.01, large_step_probability);
+            for _ in 0..8 {
+                space.next();
+            }
+            let mut point = values(&space);
+
+            for iteration in 0..20 {
+                space.start_iteration();
+                // Some iterations use fewer numbers than the point has, some more
+                let used = 4 + iteration % 8;
+                for _ in 0..used {
+                    space.next();
+                }
+                let proposed = values(&space);
+                assert_ne!(proposed[..4], point[..4]);
+
+                if iteration % 3 == 0 {
+                    space.accept();
+                    point = values(&space);
+                } else {
+                    space.reject();
+                    let restored = values(&space);
+                    for (restored, value) in restored.iter().zip(&point) {
+                        if value.is_some() {
+                            assert_eq!(restored, value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::OnceLock;
+
+use crate::{
+    aabb::Aabb,
+    environment::{Environment, GradientSky},
+    hit::{HitRecord, Hittable, Hittables},
+    light::{Light, QuadLight},
+    light_sampler::{LightSampler, LightSampling},
+    ray::Ray,
+    util::Interval,
+    vec3::Vec3,
+};
+
+// Everything that is rendered: the geometry, the lights that aren't part of it, and the
+// environment surrounding it all
+pub struct Scene {
+    pub world: Hittables,
+    pub lights: Vec<Box<dyn Light>>,
+    pub environment: Box<dyn Environment>,
+    // How direct lighting picks a light at each hit point
+    pub light_sampling: LightSampling,
+    // Built from the lights the first time it's needed, so lights added after rendering starts
+    // are never sampled
+    light_sampler: OnceLock<LightSampler>,
+    emission_sampler: OnceLock<LightSampler>,
+}
+
+impl Scene {
+    pub fn new(world: Hittables) -> Scene {
+        Scene {
+            world,
+            lights: Vec::new(),
+            environment: Box::new(GradientSky),
+            light_sampling: LightSampling::Bvh,
+            light_sampler: OnceLock::new(),
+            emission_sampler: OnceLock::new(),
+        }
+    }
+
+    pub fn add_light(&mut self, light: Box<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    // Adds an emissive quad both as geometry and as a light, so that it's found by sampling it as
+    // well as by rays that happen to hit it
+    pub fn add_area_light(&mut self, light: QuadLight) {
+        self.world.add(Box::new(LightShape {
+            shape: Box::new(light.shape().clone()),
+            light: self.lights.len(),
+        }));
+        self.lights.push(Box::new(light));
+    }
+
+    pub fn light_sampler(&self) -> &LightSampler {
+        self.light_sampler.get_or_init(|| {
+            LightSampler::new(&self.lights, self.light_sampling, self.world.bounding_box())
+        })
+    }
+
+    // Picks lights in proportion to their power, for integrators that start paths on the lights
+    // rather than picking a light for each point being lit
+    pub fn emission_sampler(&self) -> &LightSampler {
+        self.emission_sampler.get_or_init(|| {
+            LightSampler::new(
+                &self.lights,
+                LightSampling::Power,
+                self.world.bounding_box(),
+            )
+        })
+    }
+
+    // Density over solid angle of direct lighting sampling the unit vector direction from p, on a
+    // surface with normal n, toward light
+    pub fn light_pdf(&self, p: Vec3, n: Vec3, light: usize, direction: Vec3) -> f64 {
+        self.light_sampler().pmf(p, n, light) * self.lights[light].pdf(p, direction)
+    }
+
+    // The closest hit on the world along r within ray_t. A hit on a masked out part of a surface
+    // doesn't count, so the search continues beyond it. Lists of objects nested in the world
+    // don't alpha test, so each hit is tested exactly once.
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let mut min = ray_t.min;
+        while self.world.hit(r, Interval::new(min, ray_t.max), rec) {
+            if !rec.is_masked() {
+                return true;
+            }
+            min = rec.t;
+        }
+        false
+    }
+
+    // Whether anything blocks the path from p along the unit vector direction, up to distance
+    pub fn occluded(&self, p: Vec3, direction: Vec3, distance: f64) -> bool {
+        let mut rec = HitRecord::new();
+        self.hit(
+            &Ray::new(p, direction),
+            Interval::new(0.001, distance - 0.001),
+            &mut rec,
+        )
+    }
+}
+
+// The geometry of one of the scene's lights, which marks its hits with the light's index
+struct LightShape {
+    shape: Box<dyn Hittable>,
+    light: usize,
+}
+
+impl Hittable for LightShape {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.shape.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.light = Some(self.light);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        disk::Disk,
+        material::{AlphaMask, Lambertian},
+        texture::SolidColor,
+        vec3::Color,
+    };
+
+    #[test]
+    fn nested_alpha_is_tested_once() {
+        let opacity = 0.3;
+        let material = Arc::new(AlphaMask::new(
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            Arc::new(SolidColor::new(Color::new(opacity, opacity, opacity))),
+        ));
+        let disk = Disk::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            material,
+        );
+
+        // A disk in a list in a list, as a mesh added to the world would be
+        let mut inner = Hittables::new();
+        inner.add(Box::new(disk));
+        let mut group = Hittables::new();
+        group.add(Box::new(inner));
+        let mut world = Hittables::new();
+        world.add(Box::new(group));
+        let scene = Scene::new(world);
+
+        let rays = 20000;
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let passed = (0..rays)
+            .filter(|_| {
+                let mut rec = HitRecord::new();
+                !scene.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec)
+            })
+            .count();
+
+        // Testing at every level would let 1 - opacity^3 of the rays through
+        let rate = passed as f64 / rays as f64;
+        assert!(
+            (rate - (1.0 - opacity)).abs() < 0.02,
+            "pass-through rate {}",
+            rate
+        );
+    }
+}
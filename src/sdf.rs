@@ -0,0 +1,533 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    sphere::Sphere,
+    util::Interval,
+    vec3::Vec3,
+};
+
+// A signed distance field: negative inside the surface, positive outside. The distance may
+// underestimate the true distance to the surface but must never overestimate it, or sphere
+// tracing will step through the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: Vec3) -> f64;
+
+    fn bounding_box(&self) -> Aabb;
+}
+
+pub struct SdfSphere {
+    pub center: Vec3,
+    pub radius: f64,
+}
+
+impl SdfSphere {
+    pub fn new(center: Vec3, radius: f64) -> SdfSphere {
+        SdfSphere { center, radius }
+    }
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Vec3) -> f64 {
+        (p - self.center).length() - self.radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let rvec = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(self.center - rvec, self.center + rvec)
+    }
+}
+
+// An axis-aligned box given by its center and half the length of each side
+pub struct SdfBox {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl SdfBox {
+    pub fn new(center: Vec3, half_extents: Vec3) -> SdfBox {
+        SdfBox {
+            center,
+            half_extents,
+        }
+    }
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Vec3) -> f64 {
+        let p = p - self.center;
+        let q = Vec3::new(
+            p.x.abs() - self.half_extents.x,
+            p.y.abs() - self.half_extents.y,
+            p.z.abs() - self.half_extents.z,
+        );
+        let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(
+            self.center - self.half_extents,
+            self.center + self.half_extents,
+        )
+    }
+}
+
+// A torus around the y axis
+pub struct SdfTorus {
+    pub center: Vec3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl SdfTorus {
+    pub fn new(center: Vec3, major_radius: f64, minor_radius: f64) -> SdfTorus {
+        SdfTorus {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Vec3) -> f64 {
+        let p = p - self.center;
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        (ring * ring + p.y * p.y).sqrt() - self.minor_radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(outer, self.minor_radius, outer);
+        Aabb::from_points(self.center - extent, self.center + extent)
+    }
+}
+
+// The Mandelbulb fractal, using the distance estimator from the running derivative of the
+// iterated function
+pub struct Mandelbulb {
+    pub center: Vec3,
+    pub scale: f64,
+    pub power: f64,
+    pub iterations: u32,
+}
+
+impl Mandelbulb {
+    pub fn new(center: Vec3, scale: f64, power: f64, iterations: u32) -> Mandelbulb {
+        Mandelbulb {
+            center,
+            scale,
+            power,
+            iterations,
+        }
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f64 {
+        let c = (p - self.center) / self.scale;
+        let mut z = c;
+        let mut dr = 1.0;
+        let mut r = 0.0;
+
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            let zr = r.powf(self.power);
+            z =
+                zr * Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ) + c;
+        }
+
+        if r == 0.0 {
+            return -self.scale;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let extent = 1.2 * self.scale;
+        let rvec = Vec3::new(extent, extent, extent);
+        Aabb::from_points(self.center - rvec, self.center + rvec)
+    }
+}
+
+// Blends two fields together with a polynomial smooth minimum. k is the distance over which
+// the surfaces blend; k = 0 is a hard union.
+pub struct SmoothUnion {
+    pub a: Box<dyn Sdf>,
+    pub b: Box<dyn Sdf>,
+    pub k: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: Box<dyn Sdf>, b: Box<dyn Sdf>, k: f64) -> SmoothUnion {
+        SmoothUnion { a, b, k }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Vec3) -> f64 {
+        let da = self.a.distance(p);
+        let db = self.b.distance(p);
+        if self.k <= 0.0 {
+            return da.min(db);
+        }
+        let h = (self.k - (da - db).abs()).max(0.0) / self.k;
+        da.min(db) - h * h * self.k * 0.25
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // The blend can bulge out by at most k/4 from either surface
+        let bbox = Aabb::surrounding(&self.a.bounding_box(), &self.b.bounding_box());
+        let pad = 0.5 * self.k;
+        Aabb::new(bbox.x.expand(pad), bbox.y.expand(pad), bbox.z.expand(pad))
+    }
+}
+
+// Repeats a field on a grid with the given spacing, `count` copies out from the original in each
+// direction along each axis. The field should fit inside a single cell for the result to be
+// a valid distance.
+pub struct Repeat {
+    pub sdf: Box<dyn Sdf>,
+    pub spacing: Vec3,
+    pub count: [u32; 3],
+}
+
+impl Repeat {
+    pub fn new(sdf: Box<dyn Sdf>, spacing: Vec3, count: [u32; 3]) -> Repeat {
+        Repeat {
+            sdf,
+            spacing,
+            count,
+        }
+    }
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: Vec3) -> f64 {
+        let cell = |v: f64, spacing: f64, count: u32| {
+            if spacing <= 0.0 {
+                return v;
+            }
+            let n = (v / spacing).round().clamp(-(count as f64), count as f64);
+            v - spacing * n
+        };
+        let q = Vec3::new(
+            cell(p.x, self.spacing.x, self.count[0]),
+            cell(p.y, self.spacing.y, self.count[1]),
+            cell(p.z, self.spacing.z, self.count[2]),
+        );
+        self.sdf.distance(q)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bbox = self.sdf.bounding_box();
+        let reach = |axis: usize| 2.0 * self.spacing[axis].max(0.0) * self.count[axis] as f64;
+        Aabb::new(
+            bbox.x.expand(reach(0)),
+            bbox.y.expand(reach(1)),
+            bbox.z.expand(reach(2)),
+        )
+    }
+}
+
+// Twists a field around the y axis by `rate` radians per unit of height
+pub struct Twist {
+    pub sdf: Box<dyn Sdf>,
+    pub rate: f64,
+    radius: f64,
+    lipschitz: f64,
+}
+
+impl Twist {
+    pub fn new(sdf: Box<dyn Sdf>, rate: f64) -> Twist {
+        // The farthest any part of the field gets from the twist axis
+        let bbox = sdf.bounding_box();
+        let x = bbox.x.min.abs().max(bbox.x.max.abs());
+        let z = bbox.z.min.abs().max(bbox.z.max.abs());
+        let radius = (x * x + z * z).sqrt();
+
+        // Twisting stretches space, so the distances have to be scaled down to remain bounds
+        let lipschitz = (1.0 + (rate * radius).powi(2)).sqrt();
+        Twist {
+            sdf,
+            rate,
+            radius,
+            lipschitz,
+        }
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: Vec3) -> f64 {
+        let angle = -self.rate * p.y;
+        let (sin, cos) = angle.sin_cos();
+        let q = Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+        self.sdf.distance(q) / self.lipschitz
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bbox = self.sdf.bounding_box();
+        let r = Interval::new(-self.radius, self.radius);
+        Aabb::new(r, bbox.y, r)
+    }
+}
+
+// Inflates a field by `radius`, rounding off its edges
+pub struct Round {
+    pub sdf: Box<dyn Sdf>,
+    pub radius: f64,
+}
+
+impl Round {
+    pub fn new(sdf: Box<dyn Sdf>, radius: f64) -> Round {
+        Round { sdf, radius }
+    }
+}
+
+impl Sdf for Round {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.sdf.distance(p) - self.radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bbox = self.sdf.bounding_box();
+        let pad = 2.0 * self.radius;
+        Aabb::new(bbox.x.expand(pad), bbox.y.expand(pad), bbox.z.expand(pad))
+    }
+}
+
+// Renders an Sdf by sphere tracing: stepping along the ray by the distance to the nearest surface
+// until that distance falls below epsilon, or the step budget runs out
+pub struct SphereTraced {
+    pub sdf: Box<dyn Sdf>,
+    pub material: Arc<dyn Material>,
+    pub epsilon: f64,
+    pub max_steps: u32,
+    bbox: Aabb,
+}
+
+impl SphereTraced {
+    pub fn new(sdf: Box<dyn Sdf>, material: Arc<dyn Material>) -> SphereTraced {
+        SphereTraced::with_limits(sdf, material, 1e-4, 256)
+    }
+
+    pub fn with_limits(
+        sdf: Box<dyn Sdf>,
+        material: Arc<dyn Material>,
+        epsilon: f64,
+        max_steps: u32,
+    ) -> SphereTraced {
+        let bbox = sdf.bounding_box();
+        SphereTraced {
+            sdf,
+            material,
+            epsilon,
+            max_steps,
+            bbox,
+        }
+    }
+
+    // Central differences of the field, which point away from the surface
+    fn gradient(&self, p: Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let dx = Vec3::new(h, 0.0, 0.0);
+        let dy = Vec3::new(0.0, h, 0.0);
+        let dz = Vec3::new(0.0, 0.0, h);
+        Vec3::new(
+            self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+            self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+            self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+        )
+    }
+}
+
+impl Hittable for SphereTraced {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Only march through the part of the ray inside the field's bounds
+        let span = match self.bbox.clip(r, ray_t) {
+            Some(span) => span,
+            None => return false,
+        };
+
+        // Distances are in world units, while t is in multiples of the direction's length
+        let dir_length = r.direction.length();
+        let mut t = span.min;
+        // Which side of the surface the ray starts on. A ray entering the bounds from outside
+        // must start outside the surface, but one that starts within the bounds may be within
+        // epsilon of it, as rays scattered off the surface are, and then it's unknown until the
+        // ray has moved away.
+        let mut side = if span.min > ray_t.min { 1.0 } else { 0.0 };
+
+        for _ in 0..self.max_steps {
+            if t > span.max {
+                return false;
+            }
+
+            let distance = self.sdf.distance(r.at(t));
+            if side == 0.0 {
+                if distance.abs() < self.epsilon {
+                    t += self.epsilon / dir_length;
+                    continue;
+                }
+                side = distance.signum();
+            }
+
+            let distance = side * distance;
+            if distance < self.epsilon {
+                if !ray_t.surrounds(t) {
+                    return false;
+                }
+                rec.t = t;
+                rec.p = r.at(t);
+                let outward_normal = self.gradient(rec.p).unit_vector();
+                rec.set_face_normal(r, outward_normal);
+                // Fields have no natural parameterization, so map UVs by normal direction
+                (rec.u, rec.v) = Sphere::get_sphere_uv(outward_normal);
+                rec.material = Arc::clone(&self.material);
+                return true;
+            }
+            t += distance / dir_length;
+        }
+
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    fn assert_distance(sdf: &dyn Sdf, p: Vec3, expected: f64) {
+        let distance = sdf.distance(p);
+        assert!(
+            (distance - expected).abs() < 1e-9,
+            "{} != {}",
+            distance,
+            expected
+        );
+    }
+
+    // A unit sphere that counts how often its field is evaluated
+    struct Counted {
+        calls: Arc<AtomicU32>,
+    }
+
+    impl Sdf for Counted {
+        fn distance(&self, p: Vec3) -> f64 {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            p.length() - 1.0
+        }
+
+        fn bounding_box(&self) -> Aabb {
+            SdfSphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0).bounding_box()
+        }
+    }
+
+    #[test]
+    fn sphere_matches_analytic_distance_and_hit() {
+        let sphere = SdfSphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0);
+        assert_distance(&sphere, Vec3::new(0.0, 3.0, 0.0), 2.0);
+        assert_distance(&sphere, Vec3::new(0.0, 0.0, 0.0), -1.0);
+
+        let traced = SphereTraced::new(Box::new(sphere), grey());
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = hit(&traced, &r).expect("ray through the center should hit");
+        assert!((rec.t - 4.0).abs() < 1e-3);
+        assert_near(rec.p, Vec3::new(0.0, 0.0, -1.0));
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn box_matches_analytic_distance() {
+        let b = SdfBox::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        assert_distance(&b, Vec3::new(3.0, 0.0, 0.0), 2.0);
+        assert_distance(&b, Vec3::new(2.0, 2.0, 0.0), 2.0_f64.sqrt());
+        assert_distance(&b, Vec3::new(0.5, 0.0, 0.0), -0.5);
+    }
+
+    #[test]
+    fn ray_missing_bounds_never_evaluates_field() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let traced = SphereTraced::new(
+            Box::new(Counted {
+                calls: Arc::clone(&calls),
+            }),
+            grey(),
+        );
+        let r = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(hit(&traced, &r).is_none());
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        // A ray through the bounds does march
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(hit(&traced, &r).is_some());
+        assert!(calls.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn repeat_copies_field_up_to_count() {
+        let sphere = SdfSphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5);
+        let repeat = Repeat::new(Box::new(sphere), Vec3::new(2.0, 0.0, 0.0), [1, 0, 0]);
+        assert_distance(&repeat, Vec3::new(2.0, 0.0, 0.0), -0.5);
+        assert_distance(&repeat, Vec3::new(-2.0, 0.0, 0.0), -0.5);
+        // Past the last copy the field is the distance to that copy
+        assert_distance(&repeat, Vec3::new(4.0, 0.0, 0.0), 1.5);
+    }
+
+    #[test]
+    fn twist_rotates_field_with_height() {
+        // A slab that is thin along z, twisted a quarter turn per unit of height
+        let slab = SdfBox::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 0.1));
+        let twist = Twist::new(Box::new(slab), std::f64::consts::FRAC_PI_2);
+        assert!(twist.distance(Vec3::new(0.0, 0.0, 0.9)) > 0.0);
+        assert!(twist.distance(Vec3::new(0.0, 1.0, 0.9)) < 0.0);
+    }
+
+    #[test]
+    fn round_inflates_field() {
+        let b = SdfBox::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let round = Round::new(Box::new(b), 0.25);
+        assert_distance(&round, Vec3::new(2.0, 0.0, 0.0), 0.75);
+    }
+
+    #[test]
+    fn smooth_union_blends_only_near_both_surfaces() {
+        let spheres = |k| {
+            SmoothUnion::new(
+                Box::new(SdfSphere::new(Vec3::new(-1.5, 0.0, 0.0), 1.0)),
+                Box::new(SdfSphere::new(Vec3::new(1.5, 0.0, 0.0), 1.0)),
+                k,
+            )
+        };
+        // Halfway between, both surfaces are 0.5 away and the blend pulls the surface in by k/4
+        assert_distance(&spheres(1.0), Vec3::new(0.0, 0.0, 0.0), 0.25);
+        assert_distance(&spheres(0.0), Vec3::new(0.0, 0.0, 0.0), 0.5);
+        // Far from one sphere the blend has no effect
+        assert_distance(&spheres(1.0), Vec3::new(3.5, 0.0, 0.0), 1.0);
+    }
+}
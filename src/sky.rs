@@ -0,0 +1,226 @@
+use crate::{
+    environment::Environment,
+    light::DirectionalLight,
+    util::{degrees_to_radians, radians_to_degrees, PI},
+    vec3::{Color, Vec3},
+};
+
+// The analytic daylight model of Preetham, Shirley and Smits (1999). The sky's luminance and
+// chromaticity are each the value at the zenith scaled by a Perez distribution, which depends on
+// the angle from the zenith and the angle from the sun. Turbidity is the haziness of the
+// atmosphere, from about 2 for a very clear sky to 10 for a hazy one.
+//
+// The sky doesn't include the sun itself. Add the light from sun() to the scene for that.
+//
+// Directions have y up. Radiance is in kcd/m^2 multiplied by `scale`, which by default brings a
+// white diffuse surface in the midday sun to about 1.
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    turbidity: f64,
+    scale: f64,
+    // Luminance (kcd/m^2) and chromaticity at the zenith
+    zenith: [f64; 3],
+    // Perez coefficients A to E, for luminance and for each chromaticity coordinate
+    perez: [[f64; 5]; 3],
+}
+
+impl PreethamSky {
+    const DEFAULT_SCALE: f64 = 0.025;
+
+    // Extraterrestrial illuminance of the sun in klux, matching the sky's kcd/m^2
+    const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+    // sun_direction points from the ground toward the sun
+    pub fn new(sun_direction: Vec3, turbidity: f64) -> PreethamSky {
+        // The model was fitted for these turbidities
+        let t = turbidity.clamp(1.7, 10.0);
+        let sun_direction = sun_direction.unit_vector();
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s * theta_s, theta_s, 1.0];
+            let ts = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| ts[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        PreethamSky {
+            sun_direction,
+            turbidity: t,
+            scale: PreethamSky::DEFAULT_SCALE,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> PreethamSky {
+        self.scale = scale;
+        self
+    }
+
+    // A sun disk light matching the sky, dimmed and reddened by the atmosphere along its path.
+    // The sun shines at full strength overhead and sets at the horizon.
+    pub fn sun(&self) -> DirectionalLight {
+        let cos_theta = self.sun_direction.y;
+        if cos_theta <= 0.0 {
+            return DirectionalLight::sun(-self.sun_direction, Color::new(0.0, 0.0, 0.0));
+        }
+
+        // Relative optical air mass (Kasten and Young 1989)
+        let zenith_degrees = radians_to_degrees(cos_theta.acos());
+        let air_mass = 1.0 / (cos_theta + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+
+        // Rayleigh scattering and aerosol extinction at representative wavelengths for each
+        // channel, in micrometers, with the Angstrom turbidity coefficient from Preetham et al.
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        };
+
+        let irradiance = Color::new(
+            transmittance(0.680),
+            transmittance(0.550),
+            transmittance(0.440),
+        ) * (PreethamSky::SOLAR_ILLUMINANCE * self.scale);
+        DirectionalLight::sun(-self.sun_direction, irradiance)
+    }
+
+    fn perez(&self, coefficients: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = coefficients;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, direction: Vec3) -> Color {
+        // Below the horizon, the sky continues as it is at the horizon
+        let cos_theta = direction.y.max(0.001);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun_direction.y.clamp(0.0, 1.0).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * self.perez(self.perez[i], cos_theta, gamma)
+                / self.perez(self.perez[i], 1.0, theta_s)
+        });
+        if y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        // xyY to XYZ to linear sRGB
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let rgb = Color::new(
+            3.2404542 * big_x - 1.5371385 * luminance - 0.4985314 * big_z,
+            -0.9692660 * big_x + 1.8760108 * luminance + 0.0415560 * big_z,
+            0.0556434 * big_x - 0.2040259 * luminance + 1.0572252 * big_z,
+        );
+        Color::new(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0)) * self.scale
+    }
+}
+
+// Unit vector toward the sun, or anything else in the sky, at `elevation` degrees above the
+// horizon and `azimuth` degrees clockwise from north. The scene's axes are y up, -z north and +x
+// east.
+pub fn sky_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let elevation = degrees_to_radians(elevation);
+    let azimuth = degrees_to_radians(azimuth);
+    Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    )
+}
+
+// Direction toward the sun as seen from `latitude` degrees north and `longitude` degrees east, at
+// `hour` o'clock local time on the given date, where local time is `utc_offset` hours ahead of
+// UTC. Uses the NOAA approximations of the equation of time and solar declination, which are
+// good to a fraction of a degree.
+pub fn sun_direction(
+    latitude: f64,
+    longitude: f64,
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: f64,
+    utc_offset: f64,
+) -> Vec3 {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_before_month = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let month = month.clamp(1, 12) as usize;
+    let mut day_of_year = days_before_month[month - 1] + day;
+    if leap && month > 2 {
+        day_of_year += 1;
+    }
+    let days_in_year = if leap { 366.0 } else { 365.0 };
+
+    // Fractional year in radians
+    let g =
+        2.0 * PI / days_in_year * (day_of_year as f64 - 1.0 + (hour - utc_offset - 12.0) / 24.0);
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * g.cos()
+            - 0.032077 * g.sin()
+            - 0.014615 * (2.0 * g).cos()
+            - 0.040849 * (2.0 * g).sin());
+    let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin()
+        - 0.006758 * (2.0 * g).cos()
+        + 0.000907 * (2.0 * g).sin()
+        - 0.002697 * (3.0 * g).cos()
+        + 0.00148 * (3.0 * g).sin();
+
+    // True solar time in minutes, and the hour angle in radians
+    let solar_minutes = hour * 60.0 + equation_of_time + 4.0 * longitude - 60.0 * utc_offset;
+    let hour_angle = degrees_to_radians(solar_minutes / 4.0 - 180.0);
+
+    let latitude = degrees_to_radians(latitude);
+    let sin_elevation =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+
+    // Azimuth clockwise from north
+    let azimuth = (-hour_angle.sin() * declination.cos()).atan2(
+        declination.sin() * latitude.cos() - declination.cos() * latitude.sin() * hour_angle.cos(),
+    );
+
+    sky_direction(radians_to_degrees(elevation), radians_to_degrees(azimuth))
+}
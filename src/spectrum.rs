@@ -0,0 +1,330 @@
+use std::sync::OnceLock;
+
+use crate::vec3::{Color, Vec3};
+
+// Spectral rendering traces a few wavelengths per camera ray ("hero wavelength" sampling): one
+// is sampled uniformly over the visible range and the others are spaced evenly from it. RGB
+// colors in the scene are upsampled to spectra at those wavelengths, and the radiance that comes
+// back is projected onto the CIE color matching functions and converted to linear sRGB.
+//
+// In spectral mode the three channels of a Color hold values at the three sampled wavelengths
+// rather than red, green and blue.
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// Wavelength at which non-spectral rendering evaluates dispersive materials (the sodium D line)
+pub const LAMBDA_REFERENCE: f64 = 589.3;
+
+const SAMPLED_COUNT: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    // In nanometers
+    pub lambda: [f64; SAMPLED_COUNT],
+    // Set once the path has interacted with something whose behavior depends on wavelength, such
+    // as a dispersive dielectric, after which only the hero wavelength lambda[0] is traced
+    pub secondary_terminated: bool,
+}
+
+impl SampledWavelengths {
+    // Picks the hero wavelength from a uniform random number u in [0,1)
+    pub fn sample(u: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; SAMPLED_COUNT];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + i as f64 * range / SAMPLED_COUNT as f64;
+            if *l >= LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        SampledWavelengths {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        self.secondary_terminated = true;
+    }
+
+    // Once the secondary wavelengths are dropped, the hero wavelength alone has to account for
+    // the whole estimate, which averages over all of the wavelengths
+    pub fn termination_weight() -> Color {
+        Color::new(SAMPLED_COUNT as f64, 0.0, 0.0)
+    }
+
+    // Reflectance spectrum for an RGB albedo, sampled at these wavelengths
+    pub fn albedo(&self, rgb: Color) -> Color {
+        let tables = tables();
+        let weights = tables.basis_weights(rgb);
+        self.map(|lambda| basis_spectrum(weights, lambda).max(0.0))
+    }
+
+    // Emission spectrum for an RGB radiance, sampled at these wavelengths. An RGB of (1,1,1) maps
+    // to the D65 white point of sRGB.
+    pub fn illuminant(&self, rgb: Color) -> Color {
+        let tables = tables();
+        let weights = tables.basis_weights(rgb);
+        self.map(|lambda| basis_spectrum(weights, lambda).max(0.0) * d65(lambda) / tables.d65_y)
+    }
+
+    // Converts radiance sampled at these wavelengths to linear sRGB
+    pub fn to_rgb(&self, radiance: Color) -> Color {
+        let tables = tables();
+        let values = [radiance.r, radiance.g, radiance.b];
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for (value, lambda) in values.iter().zip(self.lambda) {
+            xyz = xyz + cie_xyz(lambda) * (value / pdf);
+        }
+        tables.xyz_to_rgb(xyz / SAMPLED_COUNT as f64)
+    }
+
+    // Evaluates a spectral function at each of these wavelengths
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> Color {
+        Color::new(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+}
+
+// Color of a reflectance spectrum lit by D65, for wavelength-dependent effects when rendering in
+// RGB. A reflectance of 1 everywhere gives white.
+pub fn reflectance_to_rgb<F: Fn(f64) -> f64>(reflectance: F) -> Color {
+    let tables = tables();
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    for &(lambda, weight) in &tables.reflectance_weights {
+        xyz = xyz + weight * reflectance(lambda);
+    }
+    tables.xyz_to_rgb(xyz)
+}
+
+// Reads the channels of an RGB triple as samples of a smooth curve at 650, 550 and 450nm, for
+// spectral quantities given per channel that aren't colors, like a conductor's index of
+// refraction
+pub fn rgb_curve(rgb: Color, lambda: f64) -> f64 {
+    let (far, t) = if lambda >= 550.0 {
+        (rgb.r, (lambda - 550.0) / 100.0)
+    } else {
+        (rgb.b, (550.0 - lambda) / 100.0)
+    };
+    let t = t.min(1.0);
+    (1.0 - t) * rgb.g + t * far
+}
+
+// The CIE 1931 2 degree color matching functions, using the multi-lobe Gaussian fit of Wyman,
+// Sloan and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+// Relative spectral power of the CIE standard illuminant D65, at 10nm steps from 380nm
+const D65: [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81,
+    109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60,
+    87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09,
+    63.59, 46.42, 66.81, 63.38,
+];
+
+pub fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let i = (x.floor() as usize).min(D65.len() - 2);
+    let t = x - i as f64;
+    (1.0 - t) * D65[i] + t * D65[i + 1]
+}
+
+// RGB colors are upsampled as a weighted sum of three smooth bands covering short, middle and
+// long wavelengths. The bands sum to one everywhere, so white becomes a flat spectrum, and the
+// weights are solved for so that the spectrum converts back to exactly the RGB it came from.
+fn basis(lambda: f64) -> [f64; 3] {
+    let sigmoid = |x: f64| 1.0 / (1.0 + (-x).exp());
+    let long = sigmoid((lambda - 590.0) / 12.0);
+    let short = sigmoid((490.0 - lambda) / 12.0);
+    [long, 1.0 - long - short, short]
+}
+
+fn basis_spectrum(weights: [f64; 3], lambda: f64) -> f64 {
+    let b = basis(lambda);
+    weights[0] * b[0] + weights[1] * b[1] + weights[2] * b[2]
+}
+
+struct SpectralTables {
+    // Y of the D65 spectrum, which normalizes illuminants to unit luminance
+    d65_y: f64,
+    // sRGB of D65 through the fitted matching functions, divided out so it maps to exactly white
+    white: Color,
+    // Maps an RGB color to the weights of the basis bands
+    rgb_to_basis: [[f64; 3]; 3],
+    // Matching functions weighted by D65 at 10nm steps, normalized so that a white reflectance
+    // has unit luminance
+    reflectance_weights: Vec<(f64, Vec3)>,
+}
+
+impl SpectralTables {
+    fn build() -> SpectralTables {
+        // Integrate at 1nm steps over the visible range
+        let integrate = |f: &dyn Fn(f64) -> Vec3| {
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            let mut lambda = LAMBDA_MIN + 0.5;
+            while lambda < LAMBDA_MAX {
+                sum = sum + f(lambda);
+                lambda += 1.0;
+            }
+            sum
+        };
+
+        let d65_xyz = integrate(&|lambda| cie_xyz(lambda) * d65(lambda));
+        let mut tables = SpectralTables {
+            d65_y: d65_xyz.y,
+            white: Color::new(1.0, 1.0, 1.0),
+            rgb_to_basis: [[0.0; 3]; 3],
+            reflectance_weights: Vec::new(),
+        };
+        tables.white = tables.xyz_to_rgb(d65_xyz / d65_xyz.y);
+
+        // Color of each band as a reflectance lit by D65
+        let columns = [0, 1, 2].map(|k| {
+            let xyz = integrate(&|lambda| cie_xyz(lambda) * (basis(lambda)[k] * d65(lambda)));
+            tables.xyz_to_rgb(xyz / tables.d65_y)
+        });
+        tables.rgb_to_basis = invert([
+            columns.map(|rgb| rgb.r),
+            columns.map(|rgb| rgb.g),
+            columns.map(|rgb| rgb.b),
+        ]);
+
+        let steps = ((LAMBDA_MAX - LAMBDA_MIN) / 10.0) as usize;
+        let weights: Vec<(f64, Vec3)> = (0..steps)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + 10.0 * (i as f64 + 0.5);
+                (lambda, cie_xyz(lambda) * d65(lambda))
+            })
+            .collect();
+        let y: f64 = weights.iter().map(|(_, weight)| weight.y).sum();
+        tables.reflectance_weights = weights
+            .into_iter()
+            .map(|(lambda, weight)| (lambda, weight / y))
+            .collect();
+        tables
+    }
+
+    fn xyz_to_rgb(&self, xyz: Vec3) -> Color {
+        let r = 3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z;
+        let g = -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z;
+        let b = 0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z;
+        Color::new(r / self.white.r, g / self.white.g, b / self.white.b)
+    }
+
+    fn basis_weights(&self, rgb: Color) -> [f64; 3] {
+        let m = &self.rgb_to_basis;
+        let c = [rgb.r, rgb.g, rgb.b];
+        [0, 1, 2].map(|row| m[row][0] * c[0] + m[row][1] * c[1] + m[row][2] * c[2])
+    }
+}
+
+fn tables() -> &'static SpectralTables {
+    static TABLES: OnceLock<SpectralTables> = OnceLock::new();
+    TABLES.get_or_init(SpectralTables::build)
+}
+
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    let inv_det = 1.0 / det;
+    [
+        [
+            cofactor(1, 2, 1, 2) * inv_det,
+            -cofactor(0, 2, 1, 2) * inv_det,
+            cofactor(0, 1, 1, 2) * inv_det,
+        ],
+        [
+            -cofactor(1, 2, 0, 2) * inv_det,
+            cofactor(0, 2, 0, 2) * inv_det,
+            -cofactor(0, 1, 0, 2) * inv_det,
+        ],
+        [
+            cofactor(1, 2, 0, 1) * inv_det,
+            -cofactor(0, 2, 0, 1) * inv_det,
+            cofactor(0, 1, 0, 1) * inv_det,
+        ],
+    ]
+}
+
+// Index of refraction as a function of wavelength, for dispersive dielectrics
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / lambda^2, with lambda in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i), with lambda in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub fn refraction_index(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Averages to_rgb over hero wavelengths spread evenly across the visible range
+    fn round_trip(spectrum: impl Fn(&SampledWavelengths) -> Color) -> Color {
+        let samples = 10000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for i in 0..samples {
+            let wavelengths = SampledWavelengths::sample((i as f64 + 0.5) / samples as f64);
+            total += wavelengths.to_rgb(spectrum(&wavelengths));
+        }
+        total / samples as f64
+    }
+
+    fn assert_color_near(a: Color, b: Color) {
+        let error = (a.r - b.r)
+            .abs()
+            .max((a.g - b.g).abs())
+            .max((a.b - b.b).abs());
+        assert!(error < 0.02, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn illuminants_round_trip() {
+        for rgb in [Color::new(1.0, 1.0, 1.0), Color::new(0.9, 0.1, 0.05)] {
+            assert_color_near(round_trip(|w| w.illuminant(rgb)), rgb);
+        }
+    }
+
+    #[test]
+    fn albedos_under_white_light_round_trip() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for rgb in [white, Color::new(0.1, 0.8, 0.2)] {
+            assert_color_near(round_trip(|w| w.albedo(rgb) * w.illuminant(white)), rgb);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
 use crate::{
+    aabb::Aabb,
+    csg::{Solid, Span},
     hit::{HitRecord, Hittable},
     material::Material,
     ray::Ray,
-    util::Interval,
+    util::{Interval, PI},
     vec3::Vec3,
 };
 
@@ -16,20 +18,37 @@ impl Hittable for Sphere {
         let c = oc.length_squared() - self.radius * self.radius;
         let discriminant = half_b * half_b - a * c;
 
-        if discriminant > 0.0 {
-            let root = (-half_b - discriminant.sqrt()) / a;
-            if ray_t.surrounds(root) {
-                rec.t = root;
-                rec.p = r.at(rec.t);
-                let outward_normal = (rec.p - self.center) / self.radius;
-                rec.set_face_normal(r, outward_normal);
-                rec.material = Arc::clone(&self.material);
-                return true;
+        if discriminant < 0.0 {
+            return false;
+        }
+
+        // Find the nearest root that lies in the acceptable range, falling back to the far root
+        // for rays that start inside the sphere
+        let sqrtd = discriminant.sqrt();
+        let mut root = (-half_b - sqrtd) / a;
+        if !ray_t.surrounds(root) {
+            root = (-half_b + sqrtd) / a;
+            if !ray_t.surrounds(root) {
+                return false;
             }
         }
-        false
+
+        rec.t = root;
+        rec.p = r.at(rec.t);
+        let outward_normal = (rec.p - self.center) / self.radius;
+        rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = Sphere::get_sphere_uv(outward_normal);
+        rec.tangent = Sphere::get_sphere_tangent(outward_normal);
+        rec.material = Arc::clone(&self.material);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let rvec = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(self.center - rvec, self.center + rvec)
     }
 }
+
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f64,
@@ -44,4 +63,75 @@ impl Sphere {
             material: Arc::clone(&material),
         }
     }
+
+    // p is a point on the unit sphere centered at the origin.
+    // u is the angle around the Y axis from X=-1, v is the angle from Y=-1 to Y=+1,
+    // both normalized to [0,1].
+    pub fn get_sphere_uv(p: Vec3) -> (f64, f64) {
+        let theta = (-p.y).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z).atan2(p.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // Direction of increasing u at point p on the unit sphere, which runs around the Y axis. It is
+    // undefined at the poles, where any direction in the XZ plane will do.
+    pub fn get_sphere_tangent(p: Vec3) -> Vec3 {
+        if p.x.abs() < 1e-8 && p.z.abs() < 1e-8 {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        Vec3::new(p.z, 0.0, -p.x).unit_vector()
+    }
+}
+
+impl Solid for Sphere {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        let oc = r.origin - self.center;
+        let a = r.direction.length_squared();
+        let half_b = oc.dot(r.direction);
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+
+        if discriminant <= 0.0 {
+            return Vec::new();
+        }
+
+        let sqrtd = discriminant.sqrt();
+        let crossing = |t: f64| {
+            let p = r.at(t);
+            let outward_normal = (p - self.center) / self.radius;
+            let (u, v) = Sphere::get_sphere_uv(outward_normal);
+            HitRecord {
+                p,
+                normal: outward_normal,
+                material: Arc::clone(&self.material),
+                t,
+                u,
+                v,
+                tangent: Sphere::get_sphere_tangent(outward_normal),
+                ..HitRecord::new()
+            }
+        };
+        vec![Span {
+            enter: crossing((-half_b - sqrtd) / a),
+            exit: crossing((-half_b + sqrtd) / a),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::assert_near;
+
+    #[test]
+    fn tangent_at_poles_is_finite() {
+        for y in [1.0, -1.0] {
+            let tangent = Sphere::get_sphere_tangent(Vec3::new(0.0, y, 0.0));
+            assert_near(tangent, Vec3::new(1.0, 0.0, 0.0));
+        }
+        assert_near(
+            Sphere::get_sphere_tangent(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+    }
 }
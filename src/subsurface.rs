@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    material::{beer_lambert, Bounce, Dielectric, Material},
+    microfacet::{fresnel_dielectric, internal_reflectance},
+    ray::Ray,
+    sampler::random,
+    util::{Interval, PI},
+    vec3::{Color, Vec3},
+};
+
+// Translucent objects like skin, wax, marble and milk, where light refracts in through a smooth
+// dielectric surface, scatters around inside and comes out somewhere else. The boundary must be
+// closed, such as a Sphere or a watertight mesh with outward facing triangles, and its own
+// material is ignored.
+//
+// The medium is homogeneous and scatters isotropically. `mean_free_path` is the average distance
+// light travels between collisions for each color channel, and `albedo` is the fraction of light
+// that scatters rather than being absorbed at each collision.
+//
+// Lights that are sampled explicitly are gathered where light enters, as though the object were a
+// diffuse surface of the color light scattering many times inside a deep medium ends up, under
+// the dielectric boundary. Light that comes out elsewhere, through thin parts or round corners,
+// is only found by the walk.
+pub struct Subsurface {
+    boundary: Arc<dyn Hittable>,
+    material: Arc<dyn Material>,
+}
+
+impl Subsurface {
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        refraction_index: f64,
+        mean_free_path: Color,
+        albedo: Color,
+    ) -> Subsurface {
+        let material = Arc::new(RandomWalk {
+            boundary: boundary.clone(),
+            refraction_index,
+            extinction: Color::new(
+                1.0 / mean_free_path.r,
+                1.0 / mean_free_path.g,
+                1.0 / mean_free_path.b,
+            ),
+            albedo,
+            diffuse_albedo: Color::new(
+                diffuse_albedo(albedo.r),
+                diffuse_albedo(albedo.g),
+                diffuse_albedo(albedo.b),
+            ),
+            internal_reflectance: internal_reflectance(refraction_index),
+        });
+        Subsurface { boundary, material }
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.boundary.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.material = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+// The surface of a subsurface object. Scattering traces the whole walk through the interior, so
+// the ray it returns has already left the object.
+struct RandomWalk {
+    boundary: Arc<dyn Hittable>,
+    refraction_index: f64,
+    extinction: Color,
+    albedo: Color,
+    // Fraction of light reflected by a half-space of the medium with no boundary, for eval()
+    diffuse_albedo: Color,
+    internal_reflectance: f64,
+}
+
+// Diffuse reflectance of a half-space of isotropically scattering medium with the given single
+// scattering albedo, with van de Hulst's fit
+fn diffuse_albedo(albedo: f64) -> f64 {
+    let s = (1.0 - albedo.clamp(0.0, 1.0)).sqrt();
+    (1.0 - s) * (1.0 - 0.139 * s) / (1.0 + 1.17 * s)
+}
+
+impl RandomWalk {
+    // Walks that haven't found their way out after this many collisions are absorbed
+    const MAX_COLLISIONS: u32 = 1024;
+
+    // Follows light refracted into the medium from the boundary until it refracts back out.
+    //
+    // The channels travel different mean free paths, so the whole walk is sampled with the
+    // extinction of one randomly chosen channel, and each channel is weighted by the balance
+    // heuristic over the densities of sampling that walk with any of them. This keeps the
+    // weights bounded, unlike weighting per collision.
+    fn walk(&self, mut ray: Ray, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+        let sigma = [self.extinction.r, self.extinction.g, self.extinction.b];
+        let channel = sigma[((random() * 3.0) as usize).min(2)];
+
+        // Densities of the walk so far for each channel, up to a common scale
+        let mut path_pdf = Color::new(1.0, 1.0, 1.0);
+        let mut albedo = Color::new(1.0, 1.0, 1.0);
+
+        // Rays leaving the boundary need an offset to avoid hitting it again, but collisions
+        // happen strictly inside and can be arbitrarily close to it
+        let mut t_min = 0.001;
+
+        for _ in 0..Self::MAX_COLLISIONS {
+            let mut rec = HitRecord::new();
+            if !self
+                .boundary
+                .hit(&ray, Interval::new(t_min, f64::INFINITY), &mut rec)
+            {
+                // Leaked out through a crack in the boundary
+                return false;
+            }
+            let boundary_distance = rec.t * ray.direction.length();
+            let distance = -(1.0 - random()).ln() / channel;
+
+            if distance < boundary_distance {
+                path_pdf = path_pdf * self.extinction * beer_lambert(self.extinction, distance);
+                path_pdf = path_pdf / path_pdf.r.max(path_pdf.g).max(path_pdf.b);
+                albedo = albedo * self.albedo;
+
+                let origin = ray.at(distance / ray.direction.length());
+                ray = Ray::new(origin, Vec3::random_unit_vector());
+                t_min = 0.0;
+                continue;
+            }
+
+            path_pdf = path_pdf * beer_lambert(self.extinction, boundary_distance);
+            path_pdf = path_pdf / path_pdf.r.max(path_pdf.g).max(path_pdf.b);
+            t_min = 0.001;
+
+            // Reached the boundary from inside, where light can reflect back in
+            let unit_direction = ray.direction.unit_vector();
+            if Dielectric::reflects(unit_direction, rec.normal, self.refraction_index) {
+                ray = Ray::new(rec.p, unit_direction.reflect(rec.normal));
+                continue;
+            }
+
+            *attenuation = albedo * path_pdf / path_pdf.average();
+            *scattered = Ray::new(
+                rec.p,
+                unit_direction.refract(&rec.normal, self.refraction_index),
+            );
+            return true;
+        }
+        false
+    }
+}
+
+impl Material for RandomWalk {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let unit_direction = r_in.direction.unit_vector();
+
+        // Rays starting inside the object just refract out
+        if !rec.front_face {
+            *attenuation = Color::new(1.0, 1.0, 1.0);
+            let direction =
+                if Dielectric::reflects(unit_direction, rec.normal, self.refraction_index) {
+                    unit_direction.reflect(rec.normal)
+                } else {
+                    unit_direction.refract(&rec.normal, self.refraction_index)
+                };
+            *scattered = Ray::new(rec.p, direction);
+            return true;
+        }
+
+        let ri = 1.0 / self.refraction_index;
+        if Dielectric::reflects(unit_direction, rec.normal, ri) {
+            *attenuation = Color::new(1.0, 1.0, 1.0);
+            *scattered = Ray::new(rec.p, unit_direction.reflect(rec.normal));
+            return true;
+        }
+
+        let inside = Ray::new(rec.p, unit_direction.refract(&rec.normal, ri));
+        self.walk(inside, attenuation, scattered)
+    }
+
+    // The boundary transmits light in and out at the Fresnel transmittance, changing its solid
+    // angle by the square of the index of refraction each way. Diffuse light inside that the
+    // boundary reflects back in scatters off the medium again, which adds up to a geometric series.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let cos_o = -r_in.direction.unit_vector().dot(rec.normal);
+        let cos_i = wi.dot(rec.normal);
+        if !rec.front_face || cos_i <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let eta = self.refraction_index;
+        let transmittance =
+            (1.0 - fresnel_dielectric(cos_o, eta)) * (1.0 - fresnel_dielectric(cos_i, eta));
+        let trapped = |albedo: f64| albedo / (1.0 - albedo * self.internal_reflectance);
+        let albedo = self.diffuse_albedo;
+        Color::new(trapped(albedo.r), trapped(albedo.g), trapped(albedo.b))
+            * (transmittance * cos_i / (PI * eta * eta))
+    }
+
+    // Light that refracts in comes back out spread roughly like a cosine
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let cos_o = -r_in.direction.unit_vector().dot(rec.normal);
+        let cos_i = wi.dot(rec.normal);
+        if !rec.front_face || cos_i <= 0.0 {
+            return 0.0;
+        }
+        (1.0 - fresnel_dielectric(cos_o, self.refraction_index)) * cos_i / PI
+    }
+
+    fn bounce(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Bounce {
+        // Light that went for a walk comes out somewhere else, spread out like diffuse reflection
+        if !(scattered.origin - rec.p).near_zero() {
+            Bounce::Diffuse
+        } else if scattered.direction.dot(rec.normal) < 0.0 {
+            Bounce::Transmission
+        } else {
+            Bounce::Specular
+        }
+    }
+
+    // The color of a single collision, which light that scatters many times ends up darker and
+    // more saturated than
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
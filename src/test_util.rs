@@ -0,0 +1,84 @@
+// Helpers shared by the intersection and material tests
+
+use std::sync::Arc;
+
+use crate::{
+    hit::{HitRecord, Hittable},
+    material::{Lambertian, Material},
+    ray::Ray,
+    util::Interval,
+    vec3::{Color, Vec3},
+};
+
+// A plain grey diffuse material for shapes under test
+pub fn grey() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+}
+
+// The nearest hit of r on object past the usual self-intersection offset, if any
+pub fn hit(object: &dyn Hittable, r: &Ray) -> Option<HitRecord> {
+    let mut rec = HitRecord::new();
+    object
+        .hit(r, Interval::new(0.001, f64::INFINITY), &mut rec)
+        .then_some(rec)
+}
+
+pub fn assert_near(a: Vec3, b: Vec3) {
+    assert!((a - b).length() < 1e-2, "{:?} != {:?}", a, b);
+}
+
+// A ray arriving at the origin from above and to the side, and its hit on the plane z = 0 with
+// the given material, seen from the front or from behind
+pub fn hit_at_origin(material: Arc<dyn Material>, front_face: bool) -> (Ray, HitRecord) {
+    let z = if front_face { -1.0 } else { 1.0 };
+    let direction = Vec3::new(0.3, 0.1, z);
+    let r_in = Ray::new(-direction, direction);
+    let mut rec = HitRecord {
+        material,
+        t: 1.0,
+        ..HitRecord::new()
+    };
+    rec.set_face_normal(&r_in, Vec3::new(0.0, 0.0, 1.0));
+    (r_in, rec)
+}
+
+// Scatters `samples` rays off rec, checking that each attenuation is eval / pdf for the direction
+// picked, as light sampling relies on. Returns how many rays were reflected and transmitted.
+pub fn assert_scatter_matches_eval(r_in: &Ray, rec: &HitRecord, samples: u32) -> (u32, u32) {
+    let (mut reflected, mut transmitted) = (0, 0);
+    for _ in 0..samples {
+        let mut attenuation = Color::new(0.0, 0.0, 0.0);
+        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        if !rec
+            .material
+            .scatter(r_in, rec, &mut attenuation, &mut scattered)
+        {
+            continue;
+        }
+        let wi = scattered.direction.unit_vector();
+        if wi.dot(rec.normal) > 0.0 {
+            reflected += 1;
+        } else {
+            transmitted += 1;
+        }
+
+        let f = rec.material.eval(r_in, rec, wi);
+        let pdf = rec.material.pdf(r_in, rec, wi);
+        assert!(pdf > 0.0, "sampled {:?} with zero density", wi);
+        let expected = f / pdf;
+        for (got, want) in [
+            (attenuation.r, expected.r),
+            (attenuation.g, expected.g),
+            (attenuation.b, expected.b),
+        ] {
+            assert!(
+                (got - want).abs() <= 1e-6 * want.abs().max(1.0),
+                "{:?} != {:?} for {:?}",
+                attenuation,
+                expected,
+                wi
+            );
+        }
+    }
+    (reflected, transmitted)
+}
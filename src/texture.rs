@@ -0,0 +1,325 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use crate::vec3::{Color, Vec3};
+
+// A color that varies over a surface. Materials that take scalar parameters from a texture read
+// its red channel.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Color;
+}
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> SolidColor {
+        SolidColor { albedo }
+    }
+
+    // A constant scalar parameter
+    pub fn gray(value: f64) -> SolidColor {
+        SolidColor::new(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Vec3) -> Color {
+        self.albedo
+    }
+}
+
+// A 3D checkerboard of two textures, with cells `scale` units wide
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Color {
+        let x = (self.inv_scale * p.x).floor() as i64;
+        let y = (self.inv_scale * p.y).floor() as i64;
+        let z = (self.inv_scale * p.z).floor() as i64;
+
+        if (x + y + z) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// A texture read from a PPM image (P3 or P6) or a Radiance HDR image, sampled bilinearly and
+// repeated outside [0,1]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    // Loads an image of colors, decoding the same gamma that Color::write_color encodes
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        let mut image = ImageTexture::load_linear(path)?;
+        for pixel in image.pixels.iter_mut() {
+            *pixel = *pixel * *pixel;
+        }
+        Ok(image)
+    }
+
+    // Loads an image of data, such as a normal or height map, whose values are used as is
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        let bytes = fs::read(path)?;
+        ImageTexture::parse_ppm(&bytes)
+    }
+
+    // Loads a high dynamic range image in the Radiance RGBE format (.hdr), whose values are
+    // linear radiance
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> io::Result<ImageTexture> {
+        let bytes = fs::read(path)?;
+        ImageTexture::parse_hdr(&bytes)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn parse_ppm(bytes: &[u8]) -> io::Result<ImageTexture> {
+        let mut pos = 0;
+        let magic = next_token(bytes, &mut pos).ok_or_else(|| invalid_ppm("empty file"))?;
+        let width = next_number(bytes, &mut pos)?;
+        let height = next_number(bytes, &mut pos)?;
+        let max_value = next_number(bytes, &mut pos)?;
+        if width == 0 || height == 0 || max_value == 0 || max_value > 255 {
+            return Err(invalid_ppm("unsupported dimensions or depth"));
+        }
+        let scale = 1.0 / max_value as f64;
+
+        let count = width * height * 3;
+        let values: Vec<f64> = match magic.as_str() {
+            "P3" => (0..count)
+                .map(|_| next_number(bytes, &mut pos).map(|v| v as f64 * scale))
+                .collect::<io::Result<_>>()?,
+            "P6" => {
+                // A single whitespace byte separates the header from the raster
+                let start = pos + 1;
+                let raster = bytes
+                    .get(start..start + count)
+                    .ok_or_else(|| invalid_ppm("truncated raster"))?;
+                raster.iter().map(|&v| v as f64 * scale).collect()
+            }
+            _ => return Err(invalid_ppm("not a P3 or P6 file")),
+        };
+
+        let pixels = values
+            .chunks_exact(3)
+            .map(|rgb| Color::new(rgb[0], rgb[1], rgb[2]))
+            .collect();
+        Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn parse_hdr(bytes: &[u8]) -> io::Result<ImageTexture> {
+        // Header lines run up to a blank line, followed by the resolution line
+        let mut pos = 0;
+        if !next_line(bytes, &mut pos).starts_with("#?") {
+            return Err(invalid_hdr("missing #? signature"));
+        }
+        loop {
+            let line = next_line(bytes, &mut pos);
+            if line.trim().is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line.trim() != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid_hdr("only RGBE pixels are supported"));
+            }
+            if pos >= bytes.len() {
+                return Err(invalid_hdr("truncated header"));
+            }
+        }
+
+        // Only the standard orientation, rows from top to bottom, is supported
+        let resolution = next_line(bytes, &mut pos);
+        let fields: Vec<&str> = resolution.split_whitespace().collect();
+        let (height, width) = match fields[..] {
+            ["-Y", height, "+X", width] => (height.parse().ok(), width.parse().ok()),
+            _ => (None, None),
+        };
+        let (height, width): (usize, usize) = height
+            .zip(width)
+            .filter(|&(h, w)| h > 0 && w > 0)
+            .ok_or_else(|| invalid_hdr("unsupported resolution line"))?;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_hdr_scanline(bytes, &mut pos, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|rgbe| {
+                if rgbe[3] == 0 {
+                    return Color::new(0.0, 0.0, 0.0);
+                }
+                let f = 2f64.powi(rgbe[3] as i32 - 136);
+                Color::new(
+                    (rgbe[0] as f64 + 0.5) * f,
+                    (rgbe[1] as f64 + 0.5) * f,
+                    (rgbe[2] as f64 + 0.5) * f,
+                )
+            }));
+        }
+
+        Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Vec3) -> Color {
+        // Image rows run top to bottom while v runs bottom to top
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0).lerp(self.pixel(x0 + 1, y0), fx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(self.pixel(x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+fn invalid_ppm(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PPM: {}", message),
+    )
+}
+
+fn invalid_hdr(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid HDR: {}", message),
+    )
+}
+
+// Reads one scanline of RGBE pixels, either run-length encoded one component at a time or stored
+// flat
+fn read_hdr_scanline(bytes: &[u8], pos: &mut usize, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+
+    let header = bytes
+        .get(*pos..*pos + 4)
+        .ok_or_else(|| invalid_hdr("truncated pixels"))?;
+    let encoded = (8..32768).contains(&width)
+        && header[0] == 2
+        && header[1] == 2
+        && ((header[2] as usize) << 8 | header[3] as usize) == width;
+
+    if !encoded {
+        for pixel in scanline.iter_mut() {
+            for component in pixel.iter_mut() {
+                *component = next_byte(bytes, pos)?;
+            }
+        }
+        return Ok(());
+    }
+
+    *pos += 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = next_byte(bytes, pos)? as usize;
+            if count > 128 {
+                // A run of one repeated value
+                let count = count - 128;
+                let value = next_byte(bytes, pos)?;
+                if x + count > width {
+                    return Err(invalid_hdr("run overflows scanline"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[component] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_hdr("bad run length"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[component] = next_byte(bytes, pos)?;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn next_line(bytes: &[u8], pos: &mut usize) -> String {
+    let start = (*pos).min(bytes.len());
+    let mut end = start;
+    while end < bytes.len() && bytes[end] != b'\n' {
+        end += 1;
+    }
+    *pos = end + 1;
+    String::from_utf8_lossy(&bytes[start..end]).into_owned()
+}
+
+fn next_byte(bytes: &[u8], pos: &mut usize) -> io::Result<u8> {
+    let byte = bytes.get(*pos).copied();
+    *pos += 1;
+    byte.ok_or_else(|| invalid_hdr("truncated pixels"))
+}
+
+// Reads the next whitespace separated token of a PPM header, skipping # comments
+fn next_token(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    loop {
+        while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == b'#' {
+            while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                *pos += 1;
+            }
+            continue;
+        }
+        break;
+    }
+
+    let start = *pos;
+    while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    (start < *pos).then(|| String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+}
+
+fn next_number(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    next_token(bytes, pos)
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| invalid_ppm("malformed header"))
+}
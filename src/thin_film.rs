@@ -0,0 +1,202 @@
+use std::{
+    ops::{Add, Div, Mul, Sub},
+    sync::Arc,
+};
+
+use crate::{
+    hit::HitRecord, ray::Ray, spectrum::reflectance_to_rgb, texture::Texture, util::PI, vec3::Color,
+};
+
+// A transparent film a few hundred nanometers thick on top of a surface, as on soap bubbles, oil
+// slicks and anodized metal. Light reflected off the top of the film interferes with light
+// reflected off the surface below, so the reflectance oscillates with wavelength and angle.
+//
+// Thickness is given in nanometers and read from the red channel of the texture.
+pub struct ThinFilm {
+    thickness: Arc<dyn Texture>,
+    refraction_index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: Arc<dyn Texture>, refraction_index: f64) -> ThinFilm {
+        ThinFilm {
+            thickness,
+            refraction_index,
+        }
+    }
+
+    // Reflectance of the filmed interface for light arriving from a medium with index
+    // n_incident at cos_i to the normal. `substrate` gives the complex index of refraction
+    // (eta, k) of the far side at a wavelength.
+    //
+    // In spectral mode this is evaluated exactly at the ray's wavelengths. Otherwise the
+    // reflectance spectrum is projected onto RGB.
+    pub fn reflectance<F: Fn(f64) -> (f64, f64)>(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        cos_i: f64,
+        n_incident: f64,
+        substrate: F,
+    ) -> Color {
+        let thickness = self.thickness.value(rec.u, rec.v, rec.p).r.max(0.0);
+        let at = |lambda: f64| {
+            let (eta, k) = substrate(lambda);
+            self.airy(thickness, cos_i, n_incident, Complex::new(eta, k), lambda)
+        };
+
+        match r_in.wavelengths {
+            Some(wavelengths) => wavelengths.map(at),
+            None => reflectance_to_rgb(at),
+        }
+    }
+
+    // Sums the multiple reflections inside the film with the Airy formula, for each polarization.
+    // Working with n cos(theta) in each layer keeps evanescent and absorbing waves on the right
+    // branch of the square root.
+    fn airy(&self, thickness: f64, cos_i: f64, n0: f64, n2: Complex, lambda: f64) -> f64 {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        let n1 = Complex::new(self.refraction_index, 0.0);
+        let n0 = Complex::new(n0, 0.0);
+
+        // n sin(theta) is the same in every layer
+        let tangential = n0 * n0 * (1.0 - cos_i * cos_i);
+        let q0 = n0 * cos_i;
+        let q1 = (n1 * n1 - tangential).sqrt();
+        let q2 = (n2 * n2 - tangential).sqrt();
+
+        let phase = (Complex::new(0.0, 4.0 * PI * thickness / lambda) * q1).exp();
+        let one = Complex::new(1.0, 0.0);
+        let airy = |r01: Complex, r12: Complex| {
+            ((r01 + r12 * phase) / (one + r01 * r12 * phase)).norm_sqr()
+        };
+
+        let s = airy((q0 - q1) / (q0 + q1), (q1 - q2) / (q1 + q2));
+        let p = airy(
+            (n1 * n1 * q0 - n0 * n0 * q1) / (n1 * n1 * q0 + n0 * n0 * q1),
+            (n2 * n2 * q1 - n1 * n1 * q2) / (n2 * n2 * q1 + n1 * n1 * q2),
+        );
+        (0.5 * (s + p)).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn norm_sqr(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // Principal square root
+    fn sqrt(&self) -> Complex {
+        let norm = self.norm_sqr().sqrt();
+        let re = (0.5 * (norm + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (norm - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(&self) -> Complex {
+        let magnitude = self.re.exp();
+        Complex::new(magnitude * self.im.cos(), magnitude * self.im.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Complex;
+
+    fn mul(self, t: f64) -> Complex {
+        Complex::new(self.re * t, self.im * t)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.norm_sqr();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        microfacet::{fresnel_conductor, fresnel_dielectric},
+        texture::SolidColor,
+    };
+
+    fn film(refraction_index: f64) -> ThinFilm {
+        ThinFilm::new(Arc::new(SolidColor::gray(0.0)), refraction_index)
+    }
+
+    #[test]
+    fn vanishing_film_leaves_bare_fresnel() {
+        let film = film(1.33);
+        for cos_i in [1.0, 0.7, 0.3, 0.05] {
+            let glass = film.airy(0.0, cos_i, 1.0, Complex::new(1.5, 0.0), 550.0);
+            assert!((glass - fresnel_dielectric(cos_i, 1.5)).abs() < 1e-9);
+
+            let gold = film.airy(0.0, cos_i, 1.0, Complex::new(0.27, 2.8), 550.0);
+            assert!((gold - fresnel_conductor(cos_i, 0.27, 2.8)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn total_internal_reflection_reflects_everything() {
+        // From glass through a film of water into air, past the critical angle of glass and air
+        // nothing is transmitted whatever the film's thickness
+        let film = film(1.33);
+        let critical = (1.0_f64 - 1.0 / (1.5 * 1.5)).sqrt();
+        for cos_i in [0.0, 0.3, 0.5, critical - 1e-3] {
+            for thickness in [0.0, 50.0, 300.0, 1000.0] {
+                let r = film.airy(thickness, cos_i, 1.5, Complex::new(1.0, 0.0), 550.0);
+                assert!((r - 1.0).abs() < 1e-9, "{} at cos {}", r, cos_i);
+            }
+        }
+
+        // Short of it the reflectance is still a fraction
+        for thickness in [0.0, 50.0, 300.0, 1000.0] {
+            let r = film.airy(thickness, 0.9, 1.5, Complex::new(1.0, 0.0), 550.0);
+            assert!(r > 0.0 && r < 1.0, "{}", r);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    csg::{pair_crossings, Solid, Span},
+    hit::{HitRecord, Hittable},
+    material::Material,
+    onb::Onb,
+    poly::solve_quartic,
+    ray::Ray,
+    util::{Interval, PI},
+    vec3::Vec3,
+};
+
+// A torus around `axis`, where major_radius is the distance from the center to the middle of
+// the tube and minor_radius is the radius of the tube itself
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Arc<dyn Material>,
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Torus {
+        let frame = Onb::new(axis);
+        let n = frame.w;
+        let extent = Vec3::new(
+            major_radius * (1.0 - n.x * n.x).max(0.0).sqrt() + minor_radius,
+            major_radius * (1.0 - n.y * n.y).max(0.0).sqrt() + minor_radius,
+            major_radius * (1.0 - n.z * n.z).max(0.0).sqrt() + minor_radius,
+        );
+        Torus {
+            center,
+            axis: n,
+            major_radius,
+            minor_radius,
+            material,
+            frame,
+            bbox: Aabb::from_points(center - extent, center + extent),
+        }
+    }
+}
+
+impl Torus {
+    // Every point where the line through r crosses the surface, sorted by t, with outward normals
+    fn crossings(&self, r: &Ray) -> Vec<HitRecord> {
+        // Solve in the torus frame with a unit direction, starting from the point on the ray
+        // closest to the center. This keeps the quartic's coefficients well conditioned even for
+        // rays that start far away.
+        let dir_length = r.direction.length();
+        let d = self.frame.to_local(r.direction) / dir_length;
+        let mut o = self.frame.to_local(r.origin - self.center);
+        let t_shift = -o.dot(d);
+        o = o + t_shift * d;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (p.x^2 + p.y^2), with p = o + s*d
+        let r2 = self.major_radius * self.major_radius;
+        let f = o.dot(d);
+        let e = o.length_squared() + r2 - self.minor_radius * self.minor_radius;
+        let coeff_3 = 4.0 * f;
+        let coeff_2 = 4.0 * f * f + 2.0 * e - 4.0 * r2 * (d.x * d.x + d.y * d.y);
+        let coeff_1 = 4.0 * f * e - 8.0 * r2 * (o.x * d.x + o.y * d.y);
+        let coeff_0 = e * e - 4.0 * r2 * (o.x * o.x + o.y * o.y);
+
+        solve_quartic(coeff_3, coeff_2, coeff_1, coeff_0)
+            .into_iter()
+            .map(|s| {
+                let t = (s + t_shift) / dir_length;
+                let p = o + s * d;
+                let ring_dist = (p.x * p.x + p.y * p.y).sqrt();
+                // Nearest point on the center circle of the tube
+                let ring = if ring_dist > 0.0 {
+                    Vec3::new(p.x, p.y, 0.0) * (self.major_radius / ring_dist)
+                } else {
+                    Vec3::new(self.major_radius, 0.0, 0.0)
+                };
+                HitRecord {
+                    p: r.at(t),
+                    normal: self.frame.local((p - ring).unit_vector()),
+                    material: Arc::clone(&self.material),
+                    t,
+                    // u goes around the axis, v around the tube
+                    u: (p.y.atan2(p.x) + PI) / (2.0 * PI),
+                    v: (p.z.atan2(ring_dist - self.major_radius) + PI) / (2.0 * PI),
+                    ..HitRecord::new()
+                }
+            })
+            .collect()
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // The quartic is comparatively expensive, so reject rays that miss the bounds first
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+
+        for crossing in self.crossings(r) {
+            if ray_t.surrounds(crossing.t) {
+                *rec = crossing;
+                rec.set_face_normal(r, rec.normal);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+impl Solid for Torus {
+    fn spans(&self, r: &Ray) -> Vec<Span> {
+        if !self
+            .bbox
+            .hit(r, Interval::new(f64::NEG_INFINITY, f64::INFINITY))
+        {
+            return Vec::new();
+        }
+        pair_crossings(self.crossings(r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_near, grey, hit};
+
+    // A torus around +z with a ring of radius 1 and a tube of radius 0.25
+    fn torus() -> Torus {
+        Torus::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            0.25,
+            grey(),
+        )
+    }
+
+    #[test]
+    fn ray_through_tube() {
+        let r = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&torus(), &r).expect("ray along the ring's plane should hit");
+        assert!((rec.t - 3.75).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ray_tangent_to_top_of_tube() {
+        // Just under the top of the tube the quartic has two pairs of nearly double roots,
+        // around x = -1 and x = 1
+        let inside = Ray::new(Vec3::new(-5.0, 0.0, 0.25 - 1e-6), Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&torus(), &inside).expect("ray just inside the tangent should hit");
+        assert!((rec.t - 4.0).abs() < 1e-2);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let outside = Ray::new(Vec3::new(-5.0, 0.0, 0.25 + 1e-6), Vec3::new(1.0, 0.0, 0.0));
+        assert!(hit(&torus(), &outside).is_none());
+    }
+
+    #[test]
+    fn ray_tangent_from_far_away() {
+        // The same grazing ray starting 1000 units away, where the coefficients would lose the
+        // close roots without the shift to the closest point
+        let inside = Ray::new(
+            Vec3::new(-1000.0, 0.0, 0.25 - 1e-6),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        let rec = hit(&torus(), &inside).expect("distant ray just inside the tangent should hit");
+        assert!((rec.t - 999.0).abs() < 1e-2);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+}
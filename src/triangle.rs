@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable},
+    material::Material,
+    ray::Ray,
+    util::Interval,
+    vec3::Vec3,
+};
+
+// A single triangle with texture coordinates at each vertex. The front face is the one from
+// which a, b and c appear counterclockwise.
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub uvs: [(f64, f64); 3],
+    pub material: Arc<dyn Material>,
+    normal: Vec3,
+    tangent: Vec3,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, material: Arc<dyn Material>) -> Triangle {
+        Triangle::with_uvs(a, b, c, [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], material)
+    }
+
+    pub fn with_uvs(
+        a: Vec3,
+        b: Vec3,
+        c: Vec3,
+        uvs: [(f64, f64); 3],
+        material: Arc<dyn Material>,
+    ) -> Triangle {
+        let e1 = b - a;
+        let e2 = c - a;
+        let normal = e1.cross(e2).unit_vector();
+
+        // Solve for dp/du from the two edges and their differences in texture coordinates
+        let (du1, dv1) = (uvs[1].0 - uvs[0].0, uvs[1].1 - uvs[0].1);
+        let (du2, dv2) = (uvs[2].0 - uvs[0].0, uvs[2].1 - uvs[0].1);
+        let determinant = du1 * dv2 - du2 * dv1;
+        let tangent = if determinant.abs() > 1e-12 {
+            ((e1 * dv2 - e2 * dv1) / determinant).unit_vector()
+        } else {
+            e1.unit_vector()
+        };
+
+        Triangle {
+            a,
+            b,
+            c,
+            uvs,
+            material,
+            normal,
+            tangent,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Moller-Trumbore intersection
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let e1 = self.b - self.a;
+        let e2 = self.c - self.a;
+
+        let p = r.direction.cross(e2);
+        let determinant = e1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return false;
+        }
+        let inv_determinant = 1.0 / determinant;
+
+        let s = r.origin - self.a;
+        let beta = s.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        let q = s.cross(e1);
+        let gamma = r.direction.dot(q) * inv_determinant;
+        if gamma < 0.0 || beta + gamma > 1.0 {
+            return false;
+        }
+
+        let t = e2.dot(q) * inv_determinant;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        let alpha = 1.0 - beta - gamma;
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.set_face_normal(r, self.normal);
+        rec.u = alpha * self.uvs[0].0 + beta * self.uvs[1].0 + gamma * self.uvs[2].0;
+        rec.v = alpha * self.uvs[0].1 + beta * self.uvs[1].1 + gamma * self.uvs[2].1;
+        rec.tangent = self.tangent;
+        rec.material = Arc::clone(&self.material);
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::surrounding(
+            &Aabb::from_points(self.a, self.b),
+            &Aabb::from_points(self.c, self.c),
+        )
+    }
+}
@@ -1,29 +1,66 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+use crate::sampler::random;
+
 const INFINITY: f64 = f64::INFINITY;
-const PI: f64 = std::f64::consts::PI;
+pub const PI: f64 = std::f64::consts::PI;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
 pub fn radians_to_degrees(radians: f64) -> f64 {
-    return radians * 180.0 / PI;
+    radians * 180.0 / PI
 }
 
 #[inline(always)]
 pub fn random_float_range(min: f64, max: f64) -> f64 {
     // Returns a random float in [min,max)
-    return min + (max - min) * rand::random::<f64>();
+    min + (max - min) * random()
+}
+
+// Weight for a sample drawn with density pdf_a, when the same integral is also estimated with
+// samples of density pdf_b (Veach's power heuristic with an exponent of 2)
+pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let (a, b) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
 }
 
 #[inline(always)]
 pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
-        return linear_component.sqrt();
+        linear_component.sqrt()
     } else {
-        return 0.0;
+        0.0
     }
 }
 
+// Calls f with each of 0..count, handing them out in order to one thread per core as each
+// finishes its last
+pub fn parallel_for(count: u32, f: impl Fn(u32) + Sync) {
+    let next = AtomicU32::new(0);
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= count {
+                    break;
+                }
+                f(i);
+            });
+        }
+    });
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -34,35 +71,49 @@ impl Interval {
         Interval { min, max }
     }
 
+    // Returns the tightest interval enclosing both a and b
+    pub fn enclosing(a: Interval, b: Interval) -> Interval {
+        Interval {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
     pub fn size(&self) -> f64 {
-        return self.max - self.min;
+        self.max - self.min
     }
 
     pub fn contains(&self, value: f64) -> bool {
-        return self.min <= value && value <= self.max;
+        self.min <= value && value <= self.max
     }
 
     pub fn surrounds(&self, value: f64) -> bool {
-        return self.min < value && value < self.max;
+        self.min < value && value < self.max
     }
 
     pub fn clamp(&self, value: f64) -> f64 {
         if value < self.min {
-            return self.min;
+            self.min
         } else if value > self.max {
-            return self.max;
+            self.max
         } else {
-            return value;
+            value
         }
     }
+
+    // Pads the interval by delta, split evenly between both ends
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
 }
 
-const EMPTY_INTERVAL: Interval = Interval {
+pub const EMPTY_INTERVAL: Interval = Interval {
     min: INFINITY,
     max: -INFINITY,
 };
 
-const UNIVERSE_INTERVAL: Interval = Interval {
+pub const UNIVERSE_INTERVAL: Interval = Interval {
     min: -INFINITY,
     max: INFINITY,
 };
@@ -1,6 +1,7 @@
 use std::io::Write;
 use std::ops::*;
 
+use crate::sampler::random;
 use crate::util::{linear_to_gamma, random_float_range, Interval};
 
 #[derive(Debug, Clone, Copy)]
@@ -37,11 +38,7 @@ impl Vec3 {
     }
 
     pub fn random() -> Vec3 {
-        Vec3::new(
-            rand::random::<f64>(),
-            rand::random::<f64>(),
-            rand::random::<f64>(),
-        )
+        Vec3::new(random(), random(), random())
     }
 
     pub fn random_range(min: f64, max: f64) -> Vec3 {
@@ -53,12 +50,12 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector() -> Vec3 {
-        let p = Vec3::random_range(-1.0, 1.0);
-        let lensq = p.length_squared();
-        if lensq > 1.0 && lensq > 1e-6 {
-            p / lensq.sqrt()
-        } else {
-            p
+        loop {
+            let p = Vec3::random_range(-1.0, 1.0);
+            let lensq = p.length_squared();
+            if 1e-160 < lensq && lensq <= 1.0 {
+                return p / lensq.sqrt();
+            }
         }
     }
 
@@ -75,7 +72,7 @@ impl Vec3 {
         let cos_theta = (-*self).dot(*n).min(1.0);
         let r_out_perp = etai_over_etat * (*self + cos_theta * *n);
         let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * *n;
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
     }
 
     pub fn random_on_hemisphere(normal: Vec3) -> Vec3 {
@@ -87,6 +84,19 @@ impl Vec3 {
         }
     }
 
+    // Cosine weighted direction on the hemisphere around +z
+    pub fn random_cosine_direction() -> Vec3 {
+        let r1 = random();
+        let r2 = random();
+
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+
+        Vec3::new(x, y, z)
+    }
+
     pub fn cross(&self, other: Vec3) -> Vec3 {
         Vec3::new(
             self.y * other.z - self.z * other.y,
@@ -176,6 +186,18 @@ impl Neg for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+}
+
 impl Add<Color> for Vec3 {
     type Output = Color;
 
@@ -186,9 +208,9 @@ impl Add<Color> for Vec3 {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
-    r: f64,
-    g: f64,
-    b: f64,
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
 }
 
 impl Color {
@@ -196,7 +218,21 @@ impl Color {
         Color { r, g, b }
     }
 
-    pub fn write_color(&self, out: &mut std::io::Stdout) {
+    // Relative luminance of linear sRGB
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn average(&self) -> f64 {
+        (self.r + self.g + self.b) / 3.0
+    }
+
+    // Linear interpolation from self at t = 0 to other at t = 1
+    pub fn lerp(&self, other: Color, t: f64) -> Color {
+        (1.0 - t) * *self + t * other
+    }
+
+    pub fn write_color(&self, out: &mut impl Write) {
         static INTENSITY: Interval = Interval {
             min: 0.0,
             max: 0.999,
@@ -239,6 +275,14 @@ impl Mul<f64> for Color {
     }
 }
 
+impl Div<f64> for Color {
+    type Output = Color;
+
+    fn div(self, n: f64) -> Color {
+        Color::new(self.r / n, self.g / n, self.b / n)
+    }
+}
+
 impl Mul<Color> for f64 {
     type Output = Color;
 